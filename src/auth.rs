@@ -0,0 +1,652 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpMessage, HttpRequest};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::future::{ready, Future, Ready};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use crate::config::Config;
+use crate::error::ApiError;
+
+// Header carrying an API key
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+// Define the roles a client can hold, each allowed everything the ones before
+// it are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // May read the catalogue and post reviews
+    Reader,
+    // May also change movies, directors, people and credits
+    Editor,
+    // May also delete movies, directors and people, and moderate reviews
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Reader => write!(f, "reader"),
+            Role::Editor => write!(f, "editor"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+// Define the client a request was authenticated as
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+}
+
+// Return the client a request was authenticated as, if any
+pub fn principal(req: &HttpRequest) -> Option<Principal> {
+    req.extensions().get::<Principal>().cloned()
+}
+
+// Define an entry of the API key file. Only the SHA-256 hash of each key is
+// stored, so the file does not hand out the keys themselves.
+#[derive(Debug, Deserialize)]
+struct ApiKeyEntry {
+    name: String,
+    key_sha256: String,
+    role: Role,
+}
+
+// Define the claims a bearer token has to carry besides its expiry time
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    role: Role,
+}
+
+// Define the errors that can occur while loading the credentials
+#[derive(Debug)]
+pub enum AuthError {
+    // A credentials file could not be read
+    Io(PathBuf, io::Error),
+    // A credentials file could not be parsed
+    Parse(PathBuf, String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            AuthError::Parse(path, msg) => write!(f, "cannot parse {}: {}", path.display(), msg),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+// Hash an API key the way the key file stores it
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+// Read a credentials file
+fn read(path: &Path) -> Result<Vec<u8>, AuthError> {
+    fs::read(path).map_err(|err| AuthError::Io(path.to_path_buf(), err))
+}
+
+// Define the credentials clients can authenticate with
+pub struct Authenticator {
+    // Map the hash of each API key to the client holding it
+    api_keys: HashMap<String, Principal>,
+    hs256: Option<DecodingKey>,
+    rs256: Option<DecodingKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    // Let clients without credentials read the catalogue
+    public_reads: bool,
+}
+
+impl Authenticator {
+    // Load the API keys and token verification keys named in the configuration
+    pub fn from_config(config: &Config) -> Result<Self, AuthError> {
+        let mut api_keys = HashMap::new();
+        if let Some(path) = &config.api_keys {
+            let parse = |msg: String| AuthError::Parse(path.clone(), msg);
+            let entries: Vec<ApiKeyEntry> =
+                serde_json::from_slice(&read(path)?).map_err(|err| parse(err.to_string()))?;
+            for entry in entries {
+                let hash = entry.key_sha256.to_ascii_lowercase();
+                if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(parse(format!(
+                        "key_sha256 of '{}' is not a hex-encoded SHA-256 hash",
+                        entry.name
+                    )));
+                }
+                let principal = Principal {
+                    name: entry.name,
+                    role: entry.role,
+                };
+                if api_keys.insert(hash, principal).is_some() {
+                    return Err(parse("the same key is listed twice".to_string()));
+                }
+            }
+        }
+
+        // Surrounding whitespace, such as a trailing newline, is not part of
+        // the secret
+        let hs256 = match &config.jwt_secret_file {
+            Some(path) => {
+                let secret = read(path)?;
+                let secret = String::from_utf8_lossy(&secret);
+                Some(DecodingKey::from_secret(secret.trim().as_bytes()))
+            }
+            None => None,
+        };
+        let rs256 = match &config.jwt_public_key {
+            Some(path) => Some(
+                DecodingKey::from_rsa_pem(&read(path)?)
+                    .map_err(|err| AuthError::Parse(path.clone(), err.to_string()))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            api_keys,
+            hs256,
+            rs256,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+            public_reads: config.public_reads,
+        })
+    }
+
+    // Return true if no client could ever authenticate
+    pub fn is_empty(&self) -> bool {
+        self.api_keys.is_empty() && self.hs256.is_none() && self.rs256.is_none()
+    }
+
+    // Work out who sent a request from its API key or bearer token, returning
+    // None if it carries neither
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, ApiError> {
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            let key = key
+                .to_str()
+                .map_err(|_| unauthenticated("malformed API key"))?;
+            return match self.api_keys.get(&hash_key(key)) {
+                Some(principal) => Ok(Some(principal.clone())),
+                None => Err(unauthenticated("unknown API key")),
+            };
+        }
+
+        let authorization = match headers.get(header::AUTHORIZATION) {
+            Some(authorization) => authorization,
+            None => return Ok(None),
+        };
+        let token = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthenticated("expected a bearer token in Authorization"))?;
+        self.verify(token.trim()).map(Some)
+    }
+
+    // Verify a bearer token against the key for the algorithm it names
+    fn verify(&self, token: &str) -> Result<Principal, ApiError> {
+        let invalid = |err: jsonwebtoken::errors::Error| {
+            unauthenticated(format!("invalid bearer token: {}", err))
+        };
+
+        let algorithm = jsonwebtoken::decode_header(token).map_err(invalid)?.alg;
+        let key = match algorithm {
+            Algorithm::HS256 => self.hs256.as_ref(),
+            Algorithm::RS256 => self.rs256.as_ref(),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            unauthenticated(format!(
+                "bearer tokens signed with {:?} are not accepted",
+                algorithm
+            ))
+        })?;
+
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
+        let claims = jsonwebtoken::decode::<Claims>(token, key, &validation)
+            .map_err(invalid)?
+            .claims;
+
+        Ok(Principal {
+            name: claims.sub,
+            role: claims.role,
+        })
+    }
+}
+
+// Build the 401 response for a request whose credentials are missing or wrong
+fn unauthenticated(detail: impl Into<String>) -> ApiError {
+    ApiError::new(
+        StatusCode::UNAUTHORIZED,
+        "unauthenticated",
+        "Unauthenticated",
+        detail,
+    )
+    .with_header(header::WWW_AUTHENTICATE, "Bearer")
+}
+
+// Define the outcome of checking the credentials of a request, kept with the
+// request so that they are checked only once
+#[derive(Clone)]
+struct Credentials(Result<Option<Principal>, ApiError>);
+
+// Work out who sent a request, returning None if it carries no credentials.
+// The rate limiter and the role guard both ask, but verifying a bearer token
+// is costly enough to do only the first time.
+pub fn identify(req: &ServiceRequest) -> Result<Option<Principal>, ApiError> {
+    if let Some(Credentials(outcome)) = req.extensions().get::<Credentials>() {
+        return outcome.clone();
+    }
+
+    let authenticator = req
+        .app_data::<web::Data<Authenticator>>()
+        .ok_or_else(ApiError::internal)?;
+    let outcome = authenticator.authenticate(req.headers());
+    req.extensions_mut().insert(Credentials(outcome.clone()));
+    outcome
+}
+
+// Check that a request is allowed to reach a route needing the given role,
+// recording who sent it for the handler. Clients without credentials are let
+// through only where anonymous access is allowed and reads are public.
+fn authorize(req: &ServiceRequest, required: Role, anonymous: bool) -> Result<(), ApiError> {
+    let authenticator = req
+        .app_data::<web::Data<Authenticator>>()
+        .ok_or_else(ApiError::internal)?;
+
+    match identify(req)? {
+        Some(principal) if principal.role >= required => {
+            tracing::Span::current().record("principal", principal.name.as_str());
+            req.extensions_mut().insert(principal);
+            Ok(())
+        }
+        Some(principal) => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "Forbidden",
+            format!(
+                "{} has role {}, but this requires role {}",
+                principal.name, principal.role, required
+            ),
+        )),
+        None if anonymous && authenticator.public_reads => Ok(()),
+        None => Err(unauthenticated(
+            "this requires an API key or a bearer token",
+        )),
+    }
+}
+
+// Define a route middleware that lets through only the requests of clients
+// holding at least the given role
+pub struct Require {
+    role: Role,
+    anonymous: bool,
+}
+
+impl Require {
+    // Guard a route needing the given role, which anyone may reach without
+    // credentials if it only needs the reader role and reads are public
+    pub fn new(role: Role) -> Self {
+        Self {
+            role,
+            anonymous: role == Role::Reader,
+        }
+    }
+
+    // Guard a route that only authenticated clients holding the given role may
+    // reach, even when reads are public
+    pub fn authenticated(role: Role) -> Self {
+        Self {
+            role,
+            anonymous: false,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Require
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = RequireMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireMiddleware {
+            service,
+            role: self.role,
+            anonymous: self.anonymous,
+        }))
+    }
+}
+
+pub struct RequireMiddleware<S> {
+    service: S,
+    role: Role,
+    anonymous: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Answer with a problem response rather than an error, so that the
+        // middleware wrapping the app can still name the request path in it
+        if let Err(err) = authorize(&req, self.role, self.anonymous) {
+            return Box::pin(ready(Ok(req.error_response(err))));
+        }
+
+        let response = self.service.call(req);
+        Box::pin(async move { Ok(response.await?.map_into_boxed_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{App, HttpResponse, ResponseError};
+    use jsonwebtoken::{EncodingKey, Header};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const SECRET: &[u8] = b"hs256-test-secret";
+    const RSA_KEY: &[u8] = include_bytes!("../fixtures/keys/jwt-rs256.key");
+    const RSA_PUB: &[u8] = include_bytes!("../fixtures/keys/jwt-rs256.pub");
+
+    fn authenticator(public_reads: bool) -> Authenticator {
+        let mut api_keys = HashMap::new();
+        for (key, name, role) in [
+            ("reader-key", "reader", Role::Reader),
+            ("editor-key", "editor", Role::Editor),
+            ("admin-key", "admin", Role::Admin),
+        ] {
+            let name = name.to_string();
+            api_keys.insert(hash_key(key), Principal { name, role });
+        }
+        Authenticator {
+            api_keys,
+            hs256: Some(DecodingKey::from_secret(SECRET)),
+            rs256: Some(DecodingKey::from_rsa_pem(RSA_PUB).unwrap()),
+            issuer: Some("movies-issuer".to_string()),
+            audience: Some("movies-api".to_string()),
+            public_reads,
+        }
+    }
+
+    fn token(algorithm: Algorithm, iss: &str, aud: &str) -> String {
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600;
+        let claims = serde_json::json!({
+            "sub": "token-client",
+            "role": "editor",
+            "iss": iss,
+            "aud": aud,
+            "exp": exp,
+        });
+        let key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(RSA_KEY).unwrap(),
+            _ => EncodingKey::from_secret(SECRET),
+        };
+        jsonwebtoken::encode(&Header::new(algorithm), &claims, &key).unwrap()
+    }
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, header::HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn api_key(key: &str) -> HeaderMap {
+        headers(header::HeaderName::from_static("x-api-key"), key)
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        headers(header::AUTHORIZATION, &format!("Bearer {}", token))
+    }
+
+    fn status(result: Result<impl fmt::Debug, ApiError>) -> StatusCode {
+        result.unwrap_err().status_code()
+    }
+
+    #[test]
+    fn api_keys_are_looked_up_by_hash() {
+        let auth = authenticator(false);
+        let principal = auth.authenticate(&api_key("editor-key")).unwrap().unwrap();
+        assert_eq!(principal.name, "editor");
+        assert_eq!(principal.role, Role::Editor);
+        assert_eq!(
+            status(auth.authenticate(&api_key("wrong-key"))),
+            StatusCode::UNAUTHORIZED
+        );
+        // The stored hash is not itself a key
+        assert!(auth
+            .authenticate(&api_key(&hash_key("editor-key")))
+            .is_err());
+    }
+
+    #[test]
+    fn requests_without_credentials_are_anonymous() {
+        let auth = authenticator(false);
+        assert!(auth.authenticate(&HeaderMap::new()).unwrap().is_none());
+        let basic = headers(header::AUTHORIZATION, "Basic dXNlcjpwYXNz");
+        assert_eq!(status(auth.authenticate(&basic)), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn bearer_tokens_are_verified_with_either_algorithm() {
+        let auth = authenticator(false);
+        for algorithm in [Algorithm::HS256, Algorithm::RS256] {
+            let token = token(algorithm, "movies-issuer", "movies-api");
+            let principal = auth.authenticate(&bearer(&token)).unwrap().unwrap();
+            assert_eq!(principal.name, "token-client");
+            assert_eq!(principal.role, Role::Editor);
+        }
+    }
+
+    #[test]
+    fn bearer_tokens_with_other_issuer_or_audience_are_rejected() {
+        let auth = authenticator(false);
+        for token in [
+            token(Algorithm::HS256, "someone-else", "movies-api"),
+            token(Algorithm::RS256, "movies-issuer", "another-api"),
+        ] {
+            assert_eq!(
+                status(auth.authenticate(&bearer(&token))),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+    }
+
+    #[test]
+    fn bearer_tokens_for_unconfigured_keys_are_rejected() {
+        let mut auth = authenticator(false);
+        auth.rs256 = None;
+        let token = token(Algorithm::RS256, "movies-issuer", "movies-api");
+        assert_eq!(
+            status(auth.authenticate(&bearer(&token))),
+            StatusCode::UNAUTHORIZED
+        );
+        // A token signed with another secret does not verify
+        let forged = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &serde_json::json!({"sub": "x", "role": "admin", "exp": u32::MAX}),
+            &EncodingKey::from_secret(b"not-the-secret"),
+        )
+        .unwrap();
+        assert!(auth.authenticate(&bearer(&forged)).is_err());
+    }
+
+    #[test]
+    fn credentials_are_checked_once_per_request() {
+        let req = TestRequest::default()
+            .app_data(web::Data::new(authenticator(false)))
+            .insert_header((API_KEY_HEADER, "editor-key"))
+            .to_srv_request();
+        assert_eq!(identify(&req).unwrap().unwrap().name, "editor");
+
+        // Later checks reuse the outcome of the first one
+        let cached = Principal {
+            name: "cached".to_string(),
+            role: Role::Reader,
+        };
+        req.extensions_mut().insert(Credentials(Ok(Some(cached))));
+        assert_eq!(identify(&req).unwrap().unwrap().name, "cached");
+    }
+
+    #[test]
+    fn roles_are_ordered() {
+        assert!(Role::Reader < Role::Editor);
+        assert!(Role::Editor < Role::Admin);
+    }
+
+    fn authorize_with(
+        public_reads: bool,
+        key: Option<&str>,
+        required: Role,
+        anonymous: bool,
+    ) -> Result<Option<Principal>, ApiError> {
+        let mut req = TestRequest::default().app_data(web::Data::new(authenticator(public_reads)));
+        if let Some(key) = key {
+            req = req.insert_header((API_KEY_HEADER, key));
+        }
+        let req = req.to_srv_request();
+        authorize(&req, required, anonymous)?;
+        let principal = req.extensions().get::<Principal>().cloned();
+        Ok(principal)
+    }
+
+    #[test]
+    fn authorize_checks_the_role() {
+        let principal = authorize_with(false, Some("admin-key"), Role::Editor, false).unwrap();
+        assert_eq!(principal.unwrap().name, "admin");
+        assert_eq!(
+            status(authorize_with(
+                false,
+                Some("reader-key"),
+                Role::Editor,
+                false
+            )),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(authorize_with(false, None, Role::Editor, false)),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn public_reads_let_anonymous_clients_through_only_where_allowed() {
+        assert!(authorize_with(true, None, Role::Reader, true)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            status(authorize_with(false, None, Role::Reader, true)),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(authorize_with(true, None, Role::Reader, false)),
+            StatusCode::UNAUTHORIZED
+        );
+        // Wrong credentials are refused even when reads are public
+        assert_eq!(
+            status(authorize_with(true, Some("wrong-key"), Role::Reader, true)),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    async fn whoami(req: HttpRequest) -> HttpResponse {
+        match principal(&req) {
+            Some(principal) => HttpResponse::Ok().body(principal.name),
+            None => HttpResponse::Ok().body("anonymous"),
+        }
+    }
+
+    async fn call(guard: Require, public_reads: bool, key: Option<&str>) -> (StatusCode, String) {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(authenticator(public_reads)))
+                .route("/", web::get().to(whoami).wrap(guard)),
+        )
+        .await;
+        let mut req = TestRequest::get();
+        if let Some(key) = key {
+            req = req.insert_header((API_KEY_HEADER, key));
+        }
+        let response = call_service(&app, req.to_request()).await;
+        let status = response.status();
+        let body = read_body(response).await;
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[actix_web::test]
+    async fn guard_lets_through_clients_with_the_role() {
+        let (status, body) = call(Require::new(Role::Editor), false, Some("editor-key")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "editor");
+    }
+
+    #[actix_web::test]
+    async fn guard_refuses_clients_without_the_role() {
+        let (status, body) = call(Require::new(Role::Admin), false, Some("editor-key")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("/problems/forbidden"));
+    }
+
+    #[actix_web::test]
+    async fn guard_refuses_clients_without_credentials() {
+        let (status, _) = call(Require::new(Role::Reader), false, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn guard_lets_anonymous_readers_through_when_reads_are_public() {
+        let (status, body) = call(Require::new(Role::Reader), true, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "anonymous");
+    }
+
+    #[actix_web::test]
+    async fn authenticated_guard_ignores_public_reads() {
+        let guard = Require::authenticated(Role::Reader);
+        let (status, _) = call(guard, true, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let guard = Require::authenticated(Role::Reader);
+        let (status, body) = call(guard, true, Some("reader-key")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "reader");
+    }
+}
@@ -0,0 +1,469 @@
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Prefix of the environment variables naming settings, as in MOVIES_STORAGE
+const ENV_PREFIX: &str = "MOVIES_";
+
+// Define the storage backends the server can run with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// Keep the movies in memory only, losing them on restart
+    Memory,
+    /// Persist the movies in an SQLite database file
+    Sqlite,
+    /// Keep the movies in memory, backed by a write-ahead log and JSON snapshots
+    Journal,
+}
+
+// Define the formats log lines can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+// Define an address the server listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bind {
+    // A host and port, such as 127.0.0.1:8080
+    Tcp(String),
+    // The path of a Unix domain socket, written as unix:/run/movies.sock
+    Unix(PathBuf),
+}
+
+impl FromStr for Bind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return match path.is_empty() {
+                true => Err("expected a socket path after unix:".to_string()),
+                false => Ok(Bind::Unix(PathBuf::from(path))),
+            };
+        }
+
+        // Host names are only resolved when binding, so just check the shape
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Bind::Tcp(s.to_string()))
+            }
+            _ => Err("expected host:port or unix:<path>".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{}", addr),
+            Bind::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// Write addresses the way they are given, so the printed configuration can be
+// read back
+impl Serialize for Bind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// Define the settings the server accepts. Each can be given as a flag, as an
+// environment variable named after it with the MOVIES_ prefix, or in the
+// configuration file, which is also the order in which they take precedence.
+#[derive(Debug, Parser, Serialize)]
+#[command(version, about = "A CRUD REST API for movies")]
+pub struct Config {
+    /// TOML file holding settings, keyed by the names of their flags
+    #[arg(long, value_name = "FILE")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+
+    /// Addresses to listen on, as host:port or unix:<socket path>
+    #[arg(long, default_value = "127.0.0.1:8080", value_delimiter = ',')]
+    pub bind: Vec<Bind>,
+
+    /// PEM file holding the certificate chain to serve HTTPS with on the TCP
+    /// addresses, which is reloaded when it changes
+    #[arg(long, value_name = "FILE")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file holding the private key of the certificate
+    #[arg(long, value_name = "FILE")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM file holding the CAs client certificates have to be signed by, which
+    /// makes clients present one
+    #[arg(long, value_name = "FILE")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Seconds between checks of the certificate and key files for changes
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub tls_reload_interval: u64,
+
+    /// Number of worker threads, by default one per CPU core
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub workers: Option<u64>,
+
+    /// Seconds to let the requests in progress finish on shutdown before
+    /// cutting them off
+    #[arg(long, default_value_t = 30)]
+    pub shutdown_timeout: u64,
+
+    /// Largest JSON request body accepted, in bytes
+    #[arg(long, default_value_t = 2_097_152)]
+    pub json_limit: usize,
+
+    /// Largest request body of any other kind accepted, in bytes
+    #[arg(long, default_value_t = 262_144)]
+    pub payload_limit: usize,
+
+    /// Origins browsers may call the API from, such as https://example.com, or
+    /// * for any; CORS is off if none are given
+    #[arg(long, value_delimiter = ',')]
+    pub cors_allowed_origins: Vec<String>,
+
+    /// Methods cross-origin requests may use
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "GET,HEAD,POST,PUT,PATCH,DELETE"
+    )]
+    pub cors_allowed_methods: Vec<String>,
+
+    /// Headers cross-origin requests may send
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "authorization,content-type,if-match,if-none-match,x-api-key,x-request-id"
+    )]
+    pub cors_allowed_headers: Vec<String>,
+
+    /// Response headers scripts on other origins may read
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "etag,link,x-total-count,retry-after,ratelimit-limit,ratelimit-remaining,ratelimit-reset,x-request-id"
+    )]
+    pub cors_exposed_headers: Vec<String>,
+
+    /// Let cross-origin requests carry cookies and credentials, which needs
+    /// the allowed origins to be listed
+    #[arg(long)]
+    pub cors_allow_credentials: bool,
+
+    /// Seconds browsers may cache the answer to a preflight request
+    #[arg(long, default_value_t = 3600)]
+    pub cors_max_age: usize,
+
+    /// Storage backend used to hold the movies
+    #[arg(long, value_enum, default_value = "memory")]
+    pub storage: StorageKind,
+
+    /// Path of the SQLite database file used by the sqlite backend
+    #[arg(long, default_value = "movies.db")]
+    pub sqlite_path: PathBuf,
+
+    /// Directory holding the snapshot and log files of the journal backend
+    #[arg(long, default_value = "data")]
+    pub journal_dir: PathBuf,
+
+    /// Number of log entries after which the journal is compacted into a snapshot
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub journal_compact_every: u64,
+
+    /// JSON or CSV fixture file with movies to insert at startup
+    #[arg(long)]
+    pub seed: Option<PathBuf>,
+
+    /// JSON file listing the API keys clients may send in X-Api-Key, as objects
+    /// with a name, the hex-encoded SHA-256 hash of the key and a role
+    #[arg(long)]
+    pub api_keys: Option<PathBuf>,
+
+    /// File holding the shared secret HS256 bearer tokens are signed with
+    #[arg(long)]
+    pub jwt_secret_file: Option<PathBuf>,
+
+    /// PEM file holding the RSA public key RS256 bearer tokens are signed with
+    #[arg(long)]
+    pub jwt_public_key: Option<PathBuf>,
+
+    /// Issuer bearer tokens have to name, if any
+    #[arg(long)]
+    pub jwt_issuer: Option<String>,
+
+    /// Audience bearer tokens have to name, if any
+    #[arg(long)]
+    pub jwt_audience: Option<String>,
+
+    /// Let clients without credentials read the catalogue
+    #[arg(long)]
+    pub public_reads: bool,
+
+    /// Let Prometheus scrape /metrics without credentials; otherwise a scrape
+    /// needs an API key or bearer token with the reader role
+    #[arg(long)]
+    pub public_metrics: bool,
+
+    /// Read requests each client may make per minute, or 0 for no limit
+    #[arg(long, default_value_t = 600)]
+    pub read_rate_limit: u32,
+
+    /// Read requests each client may make in a burst before the limit applies
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    pub read_burst: u32,
+
+    /// Write requests each client may make per minute, or 0 for no limit
+    #[arg(long, default_value_t = 60)]
+    pub write_rate_limit: u32,
+
+    /// Write requests each client may make in a burst before the limit applies
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    pub write_burst: u32,
+
+    /// Filter deciding which log lines are written, such as `info` or
+    /// `info,rs_movies_crud=debug`; admins can change it while the server runs
+    #[arg(long, default_value = "info")]
+    pub log_level: String,
+
+    /// Format log lines are written in
+    #[arg(long, value_enum, default_value = "pretty")]
+    pub log_format: LogFormat,
+}
+
+impl Config {
+    // Build the configuration from the command line, the environment and the
+    // configuration file, exiting with a usage message if any of it is invalid
+    pub fn load() -> Self {
+        let mut command = Self::command().mut_args(|arg| {
+            let env = format!("{}{}", ENV_PREFIX, arg.get_id().as_str().to_uppercase());
+            arg.env(env)
+        });
+
+        // Look for the configuration file first, as its settings take the
+        // place of the built-in defaults
+        let path = command
+            .clone()
+            .ignore_errors(true)
+            .get_matches()
+            .get_one::<PathBuf>("config")
+            .cloned();
+        if let Some(path) = path {
+            let settings = read_file(&path).unwrap_or_else(|msg| {
+                let msg = format!("cannot load {}: {}", path.display(), msg);
+                command.error(ErrorKind::Io, msg).exit()
+            });
+            for (key, values) in settings {
+                let known = key != "config"
+                    && command
+                        .get_arguments()
+                        .any(|arg| arg.get_id().as_str() == key);
+                if !known {
+                    let msg = format!("unknown setting '{}' in {}", key, path.display());
+                    command.error(ErrorKind::UnknownArgument, msg).exit();
+                }
+                command = command.mut_arg(key, |arg| arg.default_values(values));
+            }
+        }
+
+        let matches = command.get_matches();
+        Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+    }
+
+    // Render the configuration in the format of the configuration file
+    pub fn render(&self) -> String {
+        toml::to_string(self).unwrap_or_else(|err| format!("cannot render: {}", err))
+    }
+}
+
+// Read the settings of a configuration file, as the values of the flags they
+// are named after
+fn read_file(path: &Path) -> Result<Vec<(String, Vec<String>)>, String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let table: toml::Table = text
+        .parse()
+        .map_err(|err: toml::de::Error| err.to_string())?;
+
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            // Accept the spelling of the flag as well as that of the variable
+            let key = key.replace('-', "_");
+            let values = match value {
+                toml::Value::Array(items) => items.into_iter().map(scalar).collect(),
+                value => scalar(value).map(|value| vec![value]),
+            }
+            .map_err(|msg| format!("{}: {}", key, msg))?;
+            Ok((key, values))
+        })
+        .collect()
+}
+
+// Return a single setting value as the text of a flag
+fn scalar(value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Datetime(d) => Ok(d.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => Err("expected a single value".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    // Write a configuration file, removed once the test ends
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("config-test-{}.toml", Uuid::new_v4()));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn bind_parses_tcp_addresses() {
+        assert_eq!(
+            "127.0.0.1:8080".parse::<Bind>(),
+            Ok(Bind::Tcp("127.0.0.1:8080".to_string()))
+        );
+        assert_eq!(
+            "localhost:80".parse::<Bind>(),
+            Ok(Bind::Tcp("localhost:80".to_string()))
+        );
+        assert_eq!(
+            "[::1]:443".parse::<Bind>(),
+            Ok(Bind::Tcp("[::1]:443".to_string()))
+        );
+    }
+
+    #[test]
+    fn bind_parses_unix_sockets() {
+        assert_eq!(
+            "unix:/run/movies.sock".parse::<Bind>(),
+            Ok(Bind::Unix(PathBuf::from("/run/movies.sock")))
+        );
+        assert!("unix:".parse::<Bind>().is_err());
+    }
+
+    #[test]
+    fn bind_rejects_malformed_addresses() {
+        assert!("".parse::<Bind>().is_err());
+        assert!("8080".parse::<Bind>().is_err());
+        assert!(":8080".parse::<Bind>().is_err());
+        assert!("localhost:".parse::<Bind>().is_err());
+        assert!("localhost:http".parse::<Bind>().is_err());
+        assert!("localhost:65536".parse::<Bind>().is_err());
+    }
+
+    #[test]
+    fn bind_displays_as_given() {
+        for addr in ["127.0.0.1:8080", "unix:/run/movies.sock"] {
+            assert_eq!(addr.parse::<Bind>().unwrap().to_string(), addr);
+        }
+    }
+
+    #[test]
+    fn scalar_renders_values_as_flags() {
+        assert_eq!(scalar(toml::Value::String("info".into())).unwrap(), "info");
+        assert_eq!(scalar(toml::Value::Integer(600)).unwrap(), "600");
+        assert_eq!(scalar(toml::Value::Float(1.5)).unwrap(), "1.5");
+        assert_eq!(scalar(toml::Value::Boolean(true)).unwrap(), "true");
+        assert!(scalar(toml::Value::Array(Vec::new())).is_err());
+        assert!(scalar(toml::Value::Table(toml::Table::new())).is_err());
+    }
+
+    #[test]
+    fn read_file_returns_settings_as_flag_values() {
+        let file = TempFile::new(
+            r#"
+            storage = "journal"
+            read-rate-limit = 100
+            public_reads = true
+            bind = ["127.0.0.1:8080", "unix:/run/movies.sock"]
+            "#,
+        );
+        let mut settings = read_file(&file.0).unwrap();
+        settings.sort();
+        assert_eq!(
+            settings,
+            vec![
+                (
+                    "bind".to_string(),
+                    vec![
+                        "127.0.0.1:8080".to_string(),
+                        "unix:/run/movies.sock".to_string()
+                    ]
+                ),
+                ("public_reads".to_string(), vec!["true".to_string()]),
+                ("read_rate_limit".to_string(), vec!["100".to_string()]),
+                ("storage".to_string(), vec!["journal".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_file_rejects_invalid_files() {
+        let nested = TempFile::new("bind = [[\"127.0.0.1:8080\"]]");
+        assert!(read_file(&nested.0).unwrap_err().starts_with("bind:"));
+
+        let table = TempFile::new("[storage]\nkind = \"memory\"");
+        assert!(read_file(&table.0).is_err());
+
+        let malformed = TempFile::new("storage = ");
+        assert!(read_file(&malformed.0).is_err());
+
+        assert!(read_file(Path::new("/nonexistent/movies.toml")).is_err());
+    }
+
+    #[test]
+    fn rendered_config_reads_back() {
+        let config = Config::parse_from([
+            "movies",
+            "--bind",
+            "127.0.0.1:8080,unix:/run/movies.sock",
+            "--storage",
+            "sqlite",
+        ]);
+        let file = TempFile::new(&config.render());
+        let settings = read_file(&file.0).unwrap();
+        let value = |key: &str| {
+            settings
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, values)| values.clone())
+        };
+        assert_eq!(
+            value("bind"),
+            Some(vec![
+                "127.0.0.1:8080".to_string(),
+                "unix:/run/movies.sock".to_string()
+            ])
+        );
+        assert_eq!(value("storage"), Some(vec!["sqlite".to_string()]));
+        assert_eq!(value("config"), None);
+    }
+}
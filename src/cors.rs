@@ -0,0 +1,204 @@
+use actix_cors::Cors;
+use actix_web::http::header::HeaderName;
+use actix_web::http::{Method, Uri};
+use actix_web::middleware::Condition;
+
+use crate::config::Config;
+
+// Origin allowing every origin to call the API
+const ANY_ORIGIN: &str = "*";
+
+// Define the validated CORS settings, shared by the workers
+#[derive(Debug, Clone)]
+pub struct CorsSettings {
+    // The allowed origins, or None for any origin
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    exposed_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: usize,
+}
+
+impl CorsSettings {
+    // Check the CORS settings in the configuration, returning None if no
+    // origin is allowed, which leaves CORS off
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        if config.cors_allowed_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let origins = match config.cors_allowed_origins.iter().any(|o| o == ANY_ORIGIN) {
+            // Letting any site send the credentials of its visitors would let
+            // it act on their behalf
+            true if config.cors_allow_credentials => {
+                return Err(
+                    "cors_allow_credentials cannot be combined with allowing any origin"
+                        .to_string(),
+                )
+            }
+            true => None,
+            false => Some(
+                config
+                    .cors_allowed_origins
+                    .iter()
+                    .map(|origin| check_origin(origin))
+                    .collect::<Result<_, _>>()?,
+            ),
+        };
+        let methods = config
+            .cors_allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .map_err(|_| format!("'{}' is not an HTTP method", method))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(Self {
+            origins,
+            methods,
+            headers: header_names(&config.cors_allowed_headers)?,
+            exposed_headers: header_names(&config.cors_exposed_headers)?,
+            credentials: config.cors_allow_credentials,
+            max_age: config.cors_max_age,
+        }))
+    }
+
+    // Build the middleware answering preflight requests and adding the CORS
+    // headers to the responses to allowed origins
+    fn cors(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.methods.clone())
+            .allowed_headers(self.headers.clone())
+            .expose_headers(self.exposed_headers.clone())
+            .max_age(self.max_age);
+        cors = match &self.origins {
+            Some(origins) => origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin)),
+            None => cors.allow_any_origin().send_wildcard(),
+        };
+        match self.credentials {
+            true => cors.supports_credentials(),
+            false => cors,
+        }
+    }
+}
+
+// Build the CORS middleware, which lets every request through untouched if
+// CORS is off
+pub fn middleware(settings: Option<&CorsSettings>) -> Condition<Cors> {
+    match settings {
+        Some(settings) => Condition::new(true, settings.cors()),
+        None => Condition::new(false, Cors::default()),
+    }
+}
+
+// Check that an origin is a scheme and host with an optional port, as browsers
+// send it in the Origin header
+fn check_origin(origin: &str) -> Result<String, String> {
+    let invalid = || format!("'{}' is not an origin such as https://example.com", origin);
+    let uri: Uri = origin.parse().map_err(|_| invalid())?;
+    let bare = matches!(uri.path_and_query().map(|p| p.as_str()), None | Some("/"));
+    match (uri.scheme(), uri.host()) {
+        (Some(_), Some(_)) if bare && !origin.ends_with('/') => Ok(origin.to_string()),
+        _ => Err(invalid()),
+    }
+}
+
+// Parse the names of request or response headers
+fn header_names(names: &[String]) -> Result<Vec<HeaderName>, String> {
+    names
+        .iter()
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("'{}' is not a header name", name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(std::iter::once("movies").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn check_origin_accepts_scheme_and_host() {
+        for origin in [
+            "https://example.com",
+            "http://localhost:3000",
+            "https://app.example.com:8443",
+            "http://[::1]:8080",
+        ] {
+            assert_eq!(check_origin(origin).as_deref(), Ok(origin));
+        }
+    }
+
+    #[test]
+    fn check_origin_rejects_anything_else() {
+        for origin in [
+            "",
+            "example.com",
+            "https://example.com/",
+            "https://example.com/app",
+            "https://example.com?x=1",
+            "https://",
+            "not an origin",
+        ] {
+            assert!(check_origin(origin).is_err(), "accepted {:?}", origin);
+        }
+    }
+
+    #[test]
+    fn cors_is_off_without_origins() {
+        assert!(CorsSettings::from_config(&config(&[])).unwrap().is_none());
+    }
+
+    #[test]
+    fn settings_are_validated() {
+        let settings = CorsSettings::from_config(&config(&[
+            "--cors-allowed-origins",
+            "https://example.com",
+            "--cors-allowed-methods",
+            "get,post",
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            settings.origins,
+            Some(vec!["https://example.com".to_string()])
+        );
+        assert_eq!(settings.methods, vec![Method::GET, Method::POST]);
+
+        let any = CorsSettings::from_config(&config(&["--cors-allowed-origins", "*"]))
+            .unwrap()
+            .unwrap();
+        assert!(any.origins.is_none());
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let from = |args: &[&str]| CorsSettings::from_config(&config(args));
+        assert!(from(&["--cors-allowed-origins", "example.com"]).is_err());
+        assert!(from(&["--cors-allowed-origins", "*", "--cors-allow-credentials"]).is_err());
+        assert!(from(&[
+            "--cors-allowed-origins",
+            "https://example.com",
+            "--cors-allowed-headers",
+            "bad header"
+        ])
+        .is_err());
+        assert!(from(&[
+            "--cors-allowed-origins",
+            "https://example.com",
+            "--cors-allowed-methods",
+            "GET,NOT A METHOD"
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,457 @@
+use actix_web::dev::ServiceResponse;
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::pagination::PageError;
+use crate::patch::{self, PatchError};
+use crate::query::QueryError;
+use crate::repository::RepositoryError;
+use crate::validation::ValidationErrors;
+
+// Media type of RFC 7807 problem details
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+// Define the RFC 7807 problem details body of an error response
+#[derive(Debug, Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    kind: String,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<&'a str>,
+    // Additional members specific to the problem type
+    #[serde(flatten)]
+    extensions: &'a Map<String, Value>,
+}
+
+// Define the error type returned by every handler, rendered as problem details
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: StatusCode,
+    // Short identifier of the problem type, used to build its `type` URI
+    kind: &'static str,
+    title: &'static str,
+    detail: String,
+    extensions: Map<String, Value>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl ApiError {
+    // Create an error with the given status, problem type, title and detail
+    pub fn new(
+        status: StatusCode,
+        kind: &'static str,
+        title: &'static str,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            status,
+            kind,
+            title,
+            detail: detail.into(),
+            extensions: Map::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "bad-request",
+            "Bad request",
+            detail,
+        )
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not-found", "Not found", detail)
+    }
+
+    pub fn precondition_failed(detail: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::PRECONDITION_FAILED,
+            "precondition-failed",
+            "Precondition failed",
+            detail,
+        )
+    }
+
+    pub fn internal() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            "Internal server error",
+            "the server could not complete the request",
+        )
+    }
+
+    // Add a member to the problem details body
+    pub fn with_extension(mut self, name: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self.extensions.insert(name.to_string(), value);
+        self
+    }
+
+    // Add a header to the error response
+    pub fn with_header(mut self, name: HeaderName, value: &str) -> Self {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            self.headers.push((name, value));
+        }
+        self
+    }
+
+    // Render the error, naming the request path it occurred at if known
+    fn render(&self, instance: Option<&str>) -> HttpResponse {
+        let problem = Problem {
+            kind: format!("/problems/{}", self.kind),
+            title: self.title,
+            status: self.status.as_u16(),
+            detail: &self.detail,
+            instance,
+            extensions: &self.extensions,
+        };
+
+        let mut response = HttpResponse::build(self.status);
+        response.content_type(PROBLEM_JSON);
+        for header in &self.headers {
+            response.insert_header(header.clone());
+        }
+        response.json(problem)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.title, self.detail)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.render(None)
+    }
+}
+
+// Fill in the `instance` member of problem details with the request path. The
+// request is not available where errors are rendered, so responses built from
+// an ApiError are rendered again once the path is known, keeping any headers
+// middleware added in the meantime.
+pub fn add_instance(path: &str, res: ServiceResponse) -> ServiceResponse {
+    let mut response = match res.response().error() {
+        Some(err) => match err.as_error::<ApiError>() {
+            Some(err) => err.render(Some(path)),
+            None => return res,
+        },
+        None => return res,
+    };
+    for (name, value) in res.response().headers() {
+        if !response.headers().contains_key(name) {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    res.into_response(response)
+}
+
+impl From<RepositoryError> for ApiError {
+    fn from(err: RepositoryError) -> Self {
+        match &err {
+            RepositoryError::NotFound(entity, id) => {
+                ApiError::not_found(format!("no {} with id {} exists", entity, id))
+            }
+            RepositoryError::AlreadyExists(entity, id) => ApiError::new(
+                StatusCode::CONFLICT,
+                "already-exists",
+                "Already exists",
+                format!("a {} with id {} already exists", entity, id),
+            ),
+            RepositoryError::InvalidReference(entity, id) => ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid-reference",
+                "Invalid reference",
+                format!("no {} with id {} exists", entity, id),
+            ),
+            RepositoryError::Referenced { referrers, .. } => ApiError::new(
+                StatusCode::CONFLICT,
+                "still-referenced",
+                "Still referenced",
+                err.to_string(),
+            )
+            .with_extension("referrers", referrers),
+            RepositoryError::VersionMismatch { .. } => {
+                ApiError::precondition_failed(err.to_string())
+            }
+            RepositoryError::Storage(msg) => {
+                // Keep storage internals out of the response
+                tracing::error!(error = %msg, "storage error");
+                ApiError::internal()
+            }
+        }
+    }
+}
+
+impl From<PageError> for ApiError {
+    fn from(err: PageError) -> Self {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid-pagination",
+            "Invalid pagination",
+            err.to_string(),
+        )
+    }
+}
+
+impl From<QueryError> for ApiError {
+    fn from(err: QueryError) -> Self {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid-query",
+            "Invalid query",
+            err.to_string(),
+        )
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "validation",
+            "Validation failed",
+            errors.to_string(),
+        )
+        .with_extension("errors", &errors.errors)
+    }
+}
+
+impl From<PatchError> for ApiError {
+    fn from(err: PatchError) -> Self {
+        let (status, kind, title) = match err {
+            PatchError::UnsupportedMediaType(_) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported-media-type",
+                "Unsupported media type",
+            ),
+            PatchError::Malformed(_) => (
+                StatusCode::BAD_REQUEST,
+                "malformed-patch",
+                "Malformed patch",
+            ),
+            PatchError::Failed(_) => (
+                StatusCode::CONFLICT,
+                "patch-failed",
+                "Patch could not be applied",
+            ),
+            PatchError::Invalid(_) | PatchError::IdChanged => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid-patch-result",
+                "Invalid patch result",
+            ),
+        };
+        ApiError::new(status, kind, title, err.to_string()).with_header(
+            HeaderName::from_static("accept-patch"),
+            &format!("{}, {}", patch::MERGE_PATCH, patch::JSON_PATCH),
+        )
+    }
+}
+
+// Render JSON body extraction failures as problem details
+pub fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let detail = err.to_string();
+    match err {
+        JsonPayloadError::ContentType => ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported-media-type",
+            "Unsupported media type",
+            "the request body must be application/json",
+        )
+        .with_header(header::ACCEPT, "application/json"),
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload-too-large",
+                "Payload too large",
+                detail,
+            )
+        }
+        _ => ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "malformed-body",
+            "Malformed request body",
+            detail,
+        ),
+    }
+    .into()
+}
+
+// Render path extraction failures, such as a malformed UUID, as problem details
+pub fn path_error(err: PathError, req: &HttpRequest) -> actix_web::Error {
+    ApiError::not_found(format!("{} does not exist: {}", req.path(), err)).into()
+}
+
+// Render query string extraction failures as problem details
+pub fn query_error(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        "invalid-query",
+        "Invalid query",
+        err.to_string(),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::Entity;
+    use actix_web::body::to_bytes;
+    use actix_web::dev::Service;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App};
+    use uuid::Uuid;
+
+    async fn problem(err: &ApiError) -> Value {
+        let response = err.error_response();
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            PROBLEM_JSON
+        );
+        let body = to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn errors_are_rendered_as_problem_details() {
+        let err = ApiError::not_found("no movie with id 42 exists");
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            problem(&err).await,
+            serde_json::json!({
+                "type": "/problems/not-found",
+                "title": "Not found",
+                "status": 404,
+                "detail": "no movie with id 42 exists",
+            })
+        );
+
+        let err = ApiError::bad_request("title is empty").with_extension("field", "title");
+        assert_eq!(problem(&err).await["field"], "title");
+    }
+
+    #[actix_web::test]
+    async fn problem_details_name_the_request_path() {
+        let app = init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let path = req.path().to_string();
+                    let res = srv.call(req);
+                    async move { Ok(add_instance(&path, res.await?)) }
+                })
+                .route(
+                    "/movies/{id}",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(
+                            ApiError::bad_request("nope").with_header(header::RETRY_AFTER, "5"),
+                        )
+                    }),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/movies/42").to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "5");
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["instance"], "/movies/42");
+        assert_eq!(body["type"], "/problems/bad-request");
+    }
+
+    #[test]
+    fn repository_errors_map_to_statuses() {
+        let id = Uuid::new_v4();
+        let cases = [
+            (
+                RepositoryError::NotFound(Entity::Movie, id),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                RepositoryError::AlreadyExists(Entity::Movie, id),
+                StatusCode::CONFLICT,
+            ),
+            (
+                RepositoryError::InvalidReference(Entity::Director, id),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                RepositoryError::Referenced {
+                    entity: Entity::Director,
+                    id,
+                    referrer: Entity::Movie,
+                    referrers: 2,
+                },
+                StatusCode::CONFLICT,
+            ),
+            (
+                RepositoryError::VersionMismatch {
+                    id,
+                    expected: 1,
+                    actual: 2,
+                },
+                StatusCode::PRECONDITION_FAILED,
+            ),
+            (
+                RepositoryError::Storage("disk I/O error".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (err, status) in cases {
+            assert_eq!(ApiError::from(err).status_code(), status);
+        }
+    }
+
+    #[actix_web::test]
+    async fn referenced_records_report_their_referrers() {
+        let err = ApiError::from(RepositoryError::Referenced {
+            entity: Entity::Director,
+            id: Uuid::new_v4(),
+            referrer: Entity::Movie,
+            referrers: 2,
+        });
+        let body = problem(&err).await;
+        assert_eq!(body["type"], "/problems/still-referenced");
+        assert_eq!(body["referrers"], 2);
+    }
+
+    #[actix_web::test]
+    async fn storage_errors_are_kept_out_of_responses() {
+        let err = ApiError::from(RepositoryError::Storage(
+            "disk I/O error at /var/lib/movies".to_string(),
+        ));
+        let body = problem(&err).await;
+        assert_eq!(body["type"], "/problems/internal");
+        assert!(!body.to_string().contains("/var/lib/movies"));
+    }
+
+    #[actix_web::test]
+    async fn bodies_of_the_wrong_type_are_refused_with_the_accepted_one() {
+        let req = TestRequest::default().to_http_request();
+        let err = json_error(JsonPayloadError::ContentType, &req);
+        let response = err.error_response();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            response.headers().get(header::ACCEPT).unwrap(),
+            "application/json"
+        );
+    }
+}
@@ -0,0 +1,1728 @@
+use actix_web::http::header::{self, ETag, EntityTag, Header, IfMatch, IfNoneMatch};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::auth::{self, Role};
+use crate::error::ApiError;
+use crate::health::{self, Health, HealthReport};
+use crate::logging::LogControl;
+use crate::metrics;
+use crate::models::{
+    Credit, CreditView, Director, Movie, MovieUpdate, MovieView, NewCredit, NewDirector, NewMovie,
+    NewPerson, NewReview, Review, ReviewView,
+};
+use crate::pagination::{self, PageParams};
+use crate::patch;
+use crate::query::MovieQuery;
+use crate::ratings::{RatingIndex, RatingSummary};
+use crate::repository::{Entity, Repository, RepositoryError};
+use crate::search::{self, SearchHit, SearchIndex, SearchParams};
+use crate::validation::Validate;
+
+// Define a type alias for a shared state that holds the storage backend
+pub type MovieData = web::Data<dyn Repository>;
+
+// Define the query parameter naming the related records to embed in a movie
+#[derive(Debug, Deserialize)]
+pub struct ExpandParams {
+    expand: Option<String>,
+}
+
+impl ExpandParams {
+    // Return true if the director should be embedded, rejecting anything else
+    fn director(&self) -> Result<bool, ApiError> {
+        match self.expand.as_deref() {
+            None | Some("") => Ok(false),
+            Some("director") => Ok(true),
+            Some(other) => Err(ApiError::bad_request(format!(
+                "cannot expand '{}', expected director",
+                other
+            ))),
+        }
+    }
+}
+
+// Return the strong ETag of a movie, which covers its version and everything
+// shown of its rating, so that no two different representations share one.
+// The weighted rating moves with the reviews of other movies too, which then
+// refresh cached copies of this one as well.
+fn etag(movie: &Movie, rating: &RatingSummary) -> EntityTag {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&rating.fingerprint.to_le_bytes());
+    if let Some(weighted_rating) = rating.weighted_rating {
+        hasher.update(&weighted_rating.to_bits().to_le_bytes());
+    }
+    EntityTag::new_strong(format!("{}-{:08x}", movie.version, hasher.finalize()))
+}
+
+// Return the version of the movie an ETag was issued for
+fn etag_version(tag: &EntityTag) -> Option<u64> {
+    let tag = tag.tag();
+    tag.split_once('-')
+        .map_or(tag, |(version, _)| version)
+        .parse()
+        .ok()
+}
+
+// Evaluate an If-Match header against the stored movie, returning the version
+// an update has to find still stored, or None if the request is unconditional.
+// Only the version of the movie is compared, as reviews posted by others do
+// not change the movie an editor is about to overwrite.
+fn if_match(req: &HttpRequest, stored: &Movie) -> Result<Option<u64>, ApiError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(None);
+    }
+
+    let matches = match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => true,
+        Ok(IfMatch::Items(tags)) => tags
+            .iter()
+            .any(|tag| !tag.weak && etag_version(tag) == Some(stored.version)),
+        Err(_) => return Err(ApiError::bad_request("malformed If-Match header")),
+    };
+
+    match matches {
+        true => Ok(Some(stored.version)),
+        false => Err(ApiError::precondition_failed(format!(
+            "movie {} is at version {}, which does not match If-Match",
+            stored.id, stored.version
+        ))),
+    }
+}
+
+// Evaluate an If-None-Match header against a movie's current ETag, returning
+// true if the client's cached copy is still current
+fn if_none_match(req: &HttpRequest, current: &EntityTag) -> bool {
+    if !req.headers().contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(current)),
+        Err(_) => false,
+    }
+}
+
+// Run storage operations on the thread pool for blocking work. Backends wait
+// for the disk while holding their locks, which would otherwise stall every
+// other request served by the same worker.
+async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+{
+    web::block(f).await.map_err(|err| {
+        tracing::error!(error = %err, "storage operation did not complete");
+        ApiError::internal()
+    })?
+}
+
+// Build the representation of a movie with its rating, embedding its director
+// if asked to
+fn view(
+    data: &MovieData,
+    ratings: &RatingIndex,
+    movie: Movie,
+    expand: bool,
+) -> Result<MovieView, ApiError> {
+    let director = match expand {
+        true => Some(data.get_director(movie.director_id)?),
+        false => None,
+    };
+    Ok(MovieView {
+        rating: Some(ratings.summary(movie.id)?),
+        movie,
+        director,
+    })
+}
+
+// Respond with a movie, embedding its director if asked to. Only the plain
+// representation carries an ETag, as the embedded director can change without
+// the movie's version changing.
+fn movie_response(
+    mut response: actix_web::HttpResponseBuilder,
+    view: MovieView,
+    expand: bool,
+) -> HttpResponse {
+    if let (false, Some(rating)) = (expand, &view.rating) {
+        response.insert_header(ETag(etag(&view.movie, rating)));
+    }
+    response.json(view)
+}
+
+// Define the director a movie request body refers to
+struct ResolvedDirector {
+    id: Uuid,
+    // Whether the director was stored for this request
+    created: bool,
+}
+
+// Return the director a movie request body names, storing an inline director
+// unless one with the same name already exists
+fn resolve_director(
+    data: &MovieData,
+    director_id: Option<Uuid>,
+    director: Option<NewDirector>,
+) -> Result<ResolvedDirector, ApiError> {
+    match (director_id, director) {
+        (Some(id), _) => Ok(ResolvedDirector { id, created: false }),
+        (None, Some(director)) => {
+            let proposed = Uuid::new_v4();
+            let id = data
+                .find_or_insert_director(director.into_director(proposed))?
+                .id;
+            Ok(ResolvedDirector {
+                id,
+                created: id == proposed,
+            })
+        }
+        (None, None) => Err(ApiError::bad_request("no director given")),
+    }
+}
+
+// Store a movie referring to the given director, removing the director again
+// if it was stored for this request and the movie is refused, so that a failed
+// request leaves no director behind
+fn store_movie(
+    data: &MovieData,
+    director: &ResolvedDirector,
+    store: impl FnOnce() -> Result<Movie, RepositoryError>,
+) -> Result<Movie, RepositoryError> {
+    let result = store();
+    if result.is_err() && director.created {
+        // Another request may have started referring to the director in the
+        // meantime, in which case it is refused and kept
+        if let Err(err) = data.delete_director(director.id) {
+            tracing::debug!(error = %err, "kept the director of a refused movie");
+        }
+    }
+    result
+}
+
+// Define a handler function for getting all movies, optionally filtered, sorted
+// and one page at a time
+pub async fn get_movies(
+    req: HttpRequest,
+    data: MovieData,
+    ratings: web::Data<RatingIndex>,
+    params: web::Query<PageParams>,
+    expand: web::Query<ExpandParams>,
+    filters: web::Query<Vec<(String, String)>>,
+) -> Result<HttpResponse, ApiError> {
+    // Parse the filter, sort and expand parameters
+    let query = MovieQuery::parse(&filters)?;
+    let expand = expand.director()?;
+
+    // Ask the storage backend for all movies, joined with their directors so
+    // that they can be filtered and sorted by director name
+    let (directors, movies) = blocking(move || Ok((data.list_directors()?, data.list()?))).await?;
+    let directors: HashMap<Uuid, Director> = directors
+        .into_iter()
+        .map(|director| (director.id, director))
+        .collect();
+    let movies = movies
+        .into_iter()
+        .map(|movie| MovieView {
+            director: directors.get(&movie.director_id).cloned(),
+            rating: None,
+            movie,
+        })
+        .collect();
+
+    // Filter and sort the movies, breaking ties by ID so that pages are stable
+    // between requests
+    let movies = query.apply(movies);
+
+    // Select the requested page
+    let mut page = pagination::paginate(movies, &params, &query)?;
+
+    // Drop the directors again unless they were asked for, and add the
+    // ratings of the movies on the page
+    for movie in &mut page.items {
+        if !expand {
+            movie.director = None;
+        }
+        movie.rating = Some(ratings.summary(movie.movie.id)?);
+    }
+
+    // Return a JSON response with the movies, the total count and page links
+    let mut response = HttpResponse::Ok();
+    response.insert_header(("X-Total-Count", page.total));
+    if let Some(link) = page.link_header(req.path(), req.query_string()) {
+        response.insert_header(("Link", link));
+    }
+    Ok(response.json(page.items))
+}
+
+// Define a handler function for searching movies by title and director name
+pub async fn search_movies(
+    data: MovieData,
+    index: web::Data<SearchIndex>,
+    ratings: web::Data<RatingIndex>,
+    params: web::Query<SearchParams>,
+) -> Result<HttpResponse, ApiError> {
+    // Look up the matching IDs in the index, best match first
+    let matches = index.search(&params)?;
+    let query = params.into_inner().q;
+
+    let hits = blocking(move || {
+        let mut hits = Vec::new();
+        for (id, score) in matches {
+            // Fetch each movie from the storage backend, skipping any that were
+            // deleted since the index was queried
+            let movie = match data.get(id) {
+                Ok(movie) => movie,
+                Err(RepositoryError::NotFound(..)) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let director = data.get_director(movie.director_id)?;
+            hits.push(SearchHit {
+                score,
+                highlights: search::highlights(&movie, &director, &query),
+                movie: MovieView {
+                    rating: Some(ratings.summary(movie.id)?),
+                    movie,
+                    director: Some(director),
+                },
+            });
+        }
+        Ok(hits)
+    })
+    .await?;
+
+    // Return a JSON response with the ranked results
+    Ok(HttpResponse::Ok().json(hits))
+}
+
+// Define a handler function for getting a movie by ID
+pub async fn get_movie_by_id(
+    req: HttpRequest,
+    data: MovieData,
+    ratings: web::Data<RatingIndex>,
+    id: web::Path<Uuid>,
+    expand: web::Query<ExpandParams>,
+) -> Result<HttpResponse, ApiError> {
+    let expand = expand.director()?;
+
+    // Try to find the movie by ID in the storage backend, returning a 404
+    // response if it is not there
+    let id = *id;
+    let view = blocking(move || view(&data, &ratings, data.get(id)?, expand)).await?;
+
+    // Return a 304 response if the client already has this version
+    if let (false, Some(rating)) = (expand, &view.rating) {
+        let current = etag(&view.movie, rating);
+        if if_none_match(&req, &current) {
+            return Ok(HttpResponse::NotModified()
+                .insert_header(ETag(current))
+                .finish());
+        }
+    }
+
+    // Return a JSON response with the movie and its ETag
+    Ok(movie_response(HttpResponse::Ok(), view, expand))
+}
+
+// Define a handler function for creating a new movie
+pub async fn create_movie(
+    data: MovieData,
+    ratings: web::Data<RatingIndex>,
+    movie: web::Json<NewMovie>,
+    expand: web::Query<ExpandParams>,
+) -> Result<HttpResponse, ApiError> {
+    // IDs are assigned by the server, so refuse one supplied by the client
+    // rather than silently discarding it
+    if let Some(id) = movie.id {
+        return Err(ApiError::bad_request(format!(
+            "id {} must not be given when creating a movie, it is assigned by the server",
+            id
+        )));
+    }
+
+    // Reject the movie with a field-by-field report if it is invalid
+    movie.validate()?;
+    let expand = expand.director()?;
+
+    let mut movie = movie.into_inner();
+    let view = blocking(move || {
+        // Create a new movie with the given fields and a randomly generated
+        // ID, referring to the given or inline director
+        let director = resolve_director(&data, movie.director_id, movie.director.take())?;
+        let movie = movie.into_movie(Uuid::new_v4(), director.id);
+
+        // Store the new movie
+        let movie = store_movie(&data, &director, || data.insert(movie))?;
+        view(&data, &ratings, movie, expand)
+    })
+    .await?;
+
+    // Return a 201 response with the new movie
+    Ok(movie_response(HttpResponse::Created(), view, expand))
+}
+
+// Define a handler function for updating a movie by ID
+pub async fn update_movie_by_id(
+    req: HttpRequest,
+    data: MovieData,
+    ratings: web::Data<RatingIndex>,
+    id: web::Path<Uuid>,
+    movie: web::Json<MovieUpdate>,
+    expand: web::Query<ExpandParams>,
+) -> Result<HttpResponse, ApiError> {
+    // An ID repeated in the body has to match the one in the path
+    if let Some(body_id) = movie.id.filter(|body_id| *body_id != *id) {
+        return Err(ApiError::bad_request(format!(
+            "id {} in the body does not match id {} in the path",
+            body_id, id
+        )));
+    }
+
+    // Reject the movie with a field-by-field report if it is invalid
+    movie.validate()?;
+    let expand = expand.director()?;
+
+    // Return a 404 response if the movie does not exist, and honour If-Match,
+    // so that an editor cannot overwrite changes they have not seen; the
+    // version is checked again atomically by the update
+    let id = *id;
+    let stored = {
+        let data = data.clone();
+        blocking(move || Ok(data.get(id)?)).await?
+    };
+    let expected_version = if_match(&req, &stored)?;
+
+    let mut movie = movie.into_inner();
+    let view = blocking(move || {
+        // Build the updated movie from the given fields, keeping the ID from
+        // the path
+        let director = resolve_director(&data, movie.director_id, movie.director.take())?;
+        let movie = movie.into_movie(id, director.id);
+
+        // Store the movie, returning a 404 response if it was deleted or a 412
+        // response if it changed since it was read. An inline director stored
+        // above is removed again in either case.
+        let movie = store_movie(&data, &director, || data.update(movie, expected_version))?;
+        view(&data, &ratings, movie, expand)
+    })
+    .await?;
+
+    // Return a 200 response with the updated movie
+    Ok(movie_response(HttpResponse::Ok(), view, expand))
+}
+
+// Define a handler function for partially updating a movie by ID with a JSON
+// Merge Patch or JSON Patch document
+pub async fn patch_movie_by_id(
+    req: HttpRequest,
+    data: MovieData,
+    ratings: web::Data<RatingIndex>,
+    id: web::Path<Uuid>,
+    expand: web::Query<ExpandParams>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let expand = expand.director()?;
+
+    // Try to find the movie and its director by ID in the storage backend
+    let id = *id;
+    let (stored, director) = {
+        let data = data.clone();
+        blocking(move || {
+            let stored = data.get(id)?;
+            let director = data.get_director(stored.director_id)?;
+            Ok((stored, director))
+        })
+        .await?
+    };
+    let expected_version = if_match(&req, &stored)?;
+
+    // Apply the patch to the movie and its director, reporting why it could
+    // not be applied
+    let patched = patch::apply(&stored, &director, req.content_type(), &body)?;
+
+    // Reject the patched movie with a field-by-field report if it is invalid
+    patched.validate()?;
+
+    let view = blocking(move || {
+        // Refer to the director the patch renamed the movie's director to,
+        // which is reused or stored the same way as an inline director given
+        // to PUT
+        let mut movie = patched.movie;
+        let director = match patched.director {
+            Some(director) => resolve_director(&data, None, Some(director))?,
+            None => ResolvedDirector {
+                id: movie.director_id,
+                created: false,
+            },
+        };
+        movie.director_id = director.id;
+
+        // Store the movie only if nobody changed it since it was read,
+        // returning a 404 response if it was deleted in the meantime
+        let movie = match store_movie(&data, &director, || {
+            data.update(movie, Some(stored.version))
+        }) {
+            Ok(movie) => movie,
+            // Without If-Match the client did not ask for a precondition, so a
+            // concurrent change is reported as a conflict to retry
+            Err(RepositoryError::VersionMismatch { .. }) if expected_version.is_none() => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "concurrent-update",
+                    "Concurrent update",
+                    format!("movie {} was changed while applying the patch", id),
+                ))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        view(&data, &ratings, movie, expand)
+    })
+    .await?;
+
+    // Return a 200 response with the updated movie
+    Ok(movie_response(HttpResponse::Ok(), view, expand))
+}
+
+// Define a handler function for deleting a movie by ID
+pub async fn delete_movie_by_id(
+    req: HttpRequest,
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    // Honour If-Match, so that a movie is not deleted after changes the client
+    // has not seen
+    let id = *id;
+    let expected_version = match req.headers().contains_key(header::IF_MATCH) {
+        true => {
+            let data = data.clone();
+            let stored = blocking(move || Ok(data.get(id)?)).await?;
+            if_match(&req, &stored)?
+        }
+        false => None,
+    };
+
+    // Try to remove the movie by ID from the storage backend, returning a 404
+    // response if it is not there
+    blocking(move || Ok(data.delete(id, expected_version)?)).await?;
+
+    // Return a 204 response
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Define a handler function for getting all directors, sorted by name
+pub async fn get_directors(data: MovieData) -> Result<HttpResponse, ApiError> {
+    let mut directors = blocking(move || Ok(data.list_directors()?)).await?;
+    directors
+        .sort_by(|a, b| (&a.lastname, &a.firstname, a.id).cmp(&(&b.lastname, &b.firstname, b.id)));
+
+    Ok(HttpResponse::Ok().json(directors))
+}
+
+// Define a handler function for getting a director by ID
+pub async fn get_director_by_id(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    let director = blocking(move || Ok(data.get_director(id)?)).await?;
+    Ok(HttpResponse::Ok().json(director))
+}
+
+// Define a handler function for getting the movies of a director, sorted by title
+pub async fn get_director_movies(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    let movies = blocking(move || {
+        // Return a 404 response rather than an empty list for an unknown director
+        data.get_director(id)?;
+        Ok(data.list()?)
+    })
+    .await?;
+
+    let mut movies: Vec<Movie> = movies
+        .into_iter()
+        .filter(|movie| movie.director_id == id)
+        .collect();
+    movies.sort_by(|a, b| (&a.title, a.id).cmp(&(&b.title, b.id)));
+
+    Ok(HttpResponse::Ok().json(movies))
+}
+
+// Define a handler function for creating a new director
+pub async fn create_director(
+    data: MovieData,
+    director: web::Json<NewDirector>,
+) -> Result<HttpResponse, ApiError> {
+    // Reject the director with a field-by-field report if it is invalid
+    director.validate()?;
+
+    // Store the new director under a randomly generated ID
+    let director = director.into_inner().into_director(Uuid::new_v4());
+    let director = blocking(move || Ok(data.insert_director(director)?)).await?;
+    Ok(HttpResponse::Created().json(director))
+}
+
+// Define a handler function for renaming a director by ID, which every movie
+// referring to it picks up
+pub async fn update_director_by_id(
+    data: MovieData,
+    id: web::Path<Uuid>,
+    director: web::Json<NewDirector>,
+) -> Result<HttpResponse, ApiError> {
+    director.validate()?;
+
+    let director = director.into_inner().into_director(*id);
+    let director = blocking(move || Ok(data.update_director(director)?)).await?;
+    Ok(HttpResponse::Ok().json(director))
+}
+
+// Define a handler function for deleting a director by ID, which is refused
+// with a 409 response while any movie still refers to it
+pub async fn delete_director_by_id(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    blocking(move || Ok(data.delete_director(id)?)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Define a handler function for getting all people, sorted by name
+pub async fn get_people(data: MovieData) -> Result<HttpResponse, ApiError> {
+    let mut people = blocking(move || Ok(data.list_people()?)).await?;
+    people
+        .sort_by(|a, b| (&a.lastname, &a.firstname, a.id).cmp(&(&b.lastname, &b.firstname, b.id)));
+
+    Ok(HttpResponse::Ok().json(people))
+}
+
+// Define a handler function for getting a person by ID
+pub async fn get_person_by_id(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    let person = blocking(move || Ok(data.get_person(id)?)).await?;
+    Ok(HttpResponse::Ok().json(person))
+}
+
+// Define a handler function for getting the filmography of a person: their
+// credits with the movies embedded, sorted by movie title
+pub async fn get_person_credits(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    let mut credits = blocking(move || {
+        // Return a 404 response rather than an empty list for an unknown person
+        data.get_person(id)?;
+
+        let mut credits = Vec::new();
+        for credit in data.person_credits(id)? {
+            credits.push(CreditView {
+                movie: Some(data.get(credit.movie_id)?),
+                person: None,
+                credit,
+            });
+        }
+        Ok(credits)
+    })
+    .await?;
+    credits.sort_by(|a, b| {
+        let key = |view: &CreditView| {
+            let title = view.movie.as_ref().map(|movie| movie.title.clone());
+            (title, view.credit.role, view.credit.billing, view.credit.id)
+        };
+        key(a).cmp(&key(b))
+    });
+
+    Ok(HttpResponse::Ok().json(credits))
+}
+
+// Define a handler function for creating a new person
+pub async fn create_person(
+    data: MovieData,
+    person: web::Json<NewPerson>,
+) -> Result<HttpResponse, ApiError> {
+    // Reject the person with a field-by-field report if they are invalid
+    person.validate()?;
+
+    // Store the new person under a randomly generated ID
+    let person = person.into_inner().into_person(Uuid::new_v4());
+    let person = blocking(move || Ok(data.insert_person(person)?)).await?;
+    Ok(HttpResponse::Created().json(person))
+}
+
+// Define a handler function for replacing a person by ID
+pub async fn update_person_by_id(
+    data: MovieData,
+    id: web::Path<Uuid>,
+    person: web::Json<NewPerson>,
+) -> Result<HttpResponse, ApiError> {
+    person.validate()?;
+
+    let person = person.into_inner().into_person(*id);
+    let person = blocking(move || Ok(data.update_person(person)?)).await?;
+    Ok(HttpResponse::Ok().json(person))
+}
+
+// Define a handler function for deleting a person by ID, which is refused with
+// a 409 response while any credit still refers to them
+pub async fn delete_person_by_id(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    blocking(move || Ok(data.delete_person(id)?)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Embed the person in a credit of a movie
+fn credit_view(data: &MovieData, credit: Credit) -> Result<CreditView, ApiError> {
+    Ok(CreditView {
+        person: Some(data.get_person(credit.person_id)?),
+        movie: None,
+        credit,
+    })
+}
+
+// Return the credit of a movie stored under the given ID, treating a credit of
+// another movie as missing
+fn get_movie_credit(data: &MovieData, movie_id: Uuid, id: Uuid) -> Result<Credit, ApiError> {
+    match data.get_credit(id)? {
+        credit if credit.movie_id == movie_id => Ok(credit),
+        _ => Err(RepositoryError::NotFound(Entity::Credit, id).into()),
+    }
+}
+
+// Define a handler function for getting the full credits of a movie with the
+// people embedded, grouped by role and in billing order within each role
+pub async fn get_movie_credits(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    let credits = blocking(move || {
+        // Return a 404 response rather than an empty list for an unknown movie
+        data.get(id)?;
+
+        let mut credits = data.movie_credits(id)?;
+        credits.sort_by_key(|credit| (credit.role, credit.billing, credit.id));
+        credits
+            .into_iter()
+            .map(|credit| credit_view(&data, credit))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(credits))
+}
+
+// Define a handler function for adding a credit to a movie
+pub async fn create_movie_credit(
+    data: MovieData,
+    id: web::Path<Uuid>,
+    credit: web::Json<NewCredit>,
+) -> Result<HttpResponse, ApiError> {
+    // Reject the credit with a field-by-field report if it is invalid
+    credit.validate()?;
+
+    let id = *id;
+    let credit = credit.into_inner();
+    let credit = blocking(move || {
+        // Return a 404 response for an unknown movie
+        data.get(id)?;
+
+        // Without a billing position, bill the credit after the others of its
+        // role
+        let billing = match credit.billing {
+            Some(billing) => billing,
+            None => {
+                let last = data
+                    .movie_credits(id)?
+                    .iter()
+                    .filter(|other| other.role == credit.role)
+                    .map(|other| other.billing)
+                    .max();
+                last.unwrap_or(0) + 1
+            }
+        };
+
+        let credit = data.insert_credit(credit.into_credit(Uuid::new_v4(), id, billing))?;
+        credit_view(&data, credit)
+    })
+    .await?;
+    Ok(HttpResponse::Created().json(credit))
+}
+
+// Define a handler function for replacing a credit of a movie
+pub async fn update_movie_credit(
+    data: MovieData,
+    path: web::Path<(Uuid, Uuid)>,
+    credit: web::Json<NewCredit>,
+) -> Result<HttpResponse, ApiError> {
+    let (movie_id, id) = path.into_inner();
+
+    credit.validate()?;
+
+    let credit = credit.into_inner();
+    let credit = blocking(move || {
+        // Without a billing position, the credit keeps its current one
+        let stored = get_movie_credit(&data, movie_id, id)?;
+        let billing = credit.billing.unwrap_or(stored.billing);
+
+        let credit = data.update_credit(credit.into_credit(id, movie_id, billing))?;
+        credit_view(&data, credit)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(credit))
+}
+
+// Define a handler function for removing a credit from a movie
+pub async fn delete_movie_credit(
+    data: MovieData,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (movie_id, id) = path.into_inner();
+
+    blocking(move || {
+        get_movie_credit(&data, movie_id, id)?;
+        Ok(data.delete_credit(id)?)
+    })
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Return the review of a movie stored under the given ID, treating a review of
+// another movie as missing
+fn get_movie_review(data: &MovieData, movie_id: Uuid, id: Uuid) -> Result<Review, ApiError> {
+    match data.get_review(id)? {
+        review if review.movie_id == movie_id => Ok(review),
+        _ => Err(RepositoryError::NotFound(Entity::Review, id).into()),
+    }
+}
+
+// Return the client writing a review. Review writes are only let through with
+// credentials, so a request without them never gets here.
+fn reviewer(req: &HttpRequest) -> Result<auth::Principal, ApiError> {
+    auth::principal(req).ok_or_else(ApiError::internal)
+}
+
+// Check that a request comes from the author of a review, or from an editor
+// or admin moderating it
+fn check_review_owner(req: &HttpRequest, review: &Review) -> Result<(), ApiError> {
+    let principal = reviewer(req)?;
+    if principal.name == review.author || principal.role >= Role::Editor {
+        return Ok(());
+    }
+
+    Err(ApiError::new(
+        StatusCode::FORBIDDEN,
+        "not-review-owner",
+        "Not the review's author",
+        format!(
+            "review {} was posted by {} and can only be changed by them or an editor",
+            review.id, review.author
+        ),
+    ))
+}
+
+// Define a handler function for getting the reviews of a movie, newest first
+pub async fn get_movie_reviews(
+    data: MovieData,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = *id;
+    let mut reviews = blocking(move || {
+        // Return a 404 response rather than an empty list for an unknown movie
+        data.get(id)?;
+        Ok(data.movie_reviews(id)?)
+    })
+    .await?;
+
+    reviews.sort_by_key(|review| Reverse((review.created_at, review.id)));
+    let reviews: Vec<ReviewView> = reviews.into_iter().map(ReviewView::from).collect();
+
+    Ok(HttpResponse::Ok().json(reviews))
+}
+
+// Define a handler function for getting a review of a movie
+pub async fn get_movie_review_by_id(
+    data: MovieData,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (movie_id, id) = path.into_inner();
+
+    let review = blocking(move || get_movie_review(&data, movie_id, id)).await?;
+    Ok(HttpResponse::Ok().json(ReviewView::from(review)))
+}
+
+// Define a handler function for posting a review of a movie, written by the
+// client posting it
+pub async fn create_movie_review(
+    req: HttpRequest,
+    data: MovieData,
+    id: web::Path<Uuid>,
+    review: web::Json<NewReview>,
+) -> Result<HttpResponse, ApiError> {
+    // Reject the review with a field-by-field report if it is invalid
+    review.validate()?;
+
+    let now = OffsetDateTime::now_utc();
+    let review = review.into_inner();
+    let review = Review {
+        id: Uuid::new_v4(),
+        movie_id: *id,
+        author: reviewer(&req)?.name,
+        score: review.score,
+        text: review.text,
+        created_at: now,
+        updated_at: now,
+    };
+    let review = blocking(move || {
+        // Return a 404 response for an unknown movie
+        data.get(review.movie_id)?;
+        Ok(data.insert_review(review)?)
+    })
+    .await?;
+
+    Ok(HttpResponse::Created().json(ReviewView::from(review)))
+}
+
+// Define a handler function for replacing a review of a movie, which only its
+// author or an editor may do
+pub async fn update_movie_review(
+    req: HttpRequest,
+    data: MovieData,
+    path: web::Path<(Uuid, Uuid)>,
+    review: web::Json<NewReview>,
+) -> Result<HttpResponse, ApiError> {
+    let (movie_id, id) = path.into_inner();
+
+    review.validate()?;
+
+    let stored = {
+        let data = data.clone();
+        blocking(move || get_movie_review(&data, movie_id, id)).await?
+    };
+    check_review_owner(&req, &stored)?;
+
+    let review = review.into_inner();
+    let review = Review {
+        score: review.score,
+        text: review.text,
+        updated_at: OffsetDateTime::now_utc(),
+        ..stored
+    };
+    let review = blocking(move || Ok(data.update_review(review)?)).await?;
+    Ok(HttpResponse::Ok().json(ReviewView::from(review)))
+}
+
+// Define a handler function for removing a review of a movie, which only its
+// author or an editor may do
+pub async fn delete_movie_review(
+    req: HttpRequest,
+    data: MovieData,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (movie_id, id) = path.into_inner();
+
+    let stored = {
+        let data = data.clone();
+        blocking(move || get_movie_review(&data, movie_id, id)).await?
+    };
+    check_review_owner(&req, &stored)?;
+
+    blocking(move || Ok(data.delete_review(id)?)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Define the body of the log level endpoints
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogLevel {
+    filter: String,
+}
+
+// Define a handler function for getting the filter deciding which log lines
+// are written
+pub async fn get_log_level(control: web::Data<LogControl>) -> HttpResponse {
+    HttpResponse::Ok().json(LogLevel {
+        filter: control.filter(),
+    })
+}
+
+// Define a handler function for changing the filter deciding which log lines
+// are written, without restarting the server
+pub async fn set_log_level(
+    control: web::Data<LogControl>,
+    level: web::Json<LogLevel>,
+) -> Result<HttpResponse, ApiError> {
+    control
+        .set_filter(&level.filter)
+        .map_err(|err| ApiError::bad_request(format!("invalid log filter: {}", err)))?;
+    tracing::info!(filter = %level.filter, "log filter changed");
+
+    Ok(HttpResponse::Ok().json(level.into_inner()))
+}
+
+// Define a handler function for getting the metrics in the Prometheus text
+// format
+pub async fn get_metrics(index: web::Data<SearchIndex>) -> Result<HttpResponse, ApiError> {
+    // The search index holds every movie, so it can count them without
+    // locking the store
+    let body = metrics::render(index.movie_count()).map_err(|err| {
+        tracing::error!(error = %err, "cannot render metrics");
+        ApiError::internal()
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(metrics::content_type())
+        .body(body))
+}
+
+// Answer a health endpoint with the report of its checks, which is 503 if a
+// check failed. The checks reach the storage backend, so they run on the
+// thread pool for blocking work like every other storage operation.
+async fn health_response(check: impl FnOnce() -> HealthReport + Send + 'static) -> HttpResponse {
+    let report = match web::block(check).await {
+        Ok(report) => report,
+        Err(err) => {
+            tracing::error!(error = %err, "health check did not complete");
+            return HttpResponse::ServiceUnavailable().finish();
+        }
+    };
+    match report.status {
+        health::Status::Ok => HttpResponse::Ok().json(report),
+        health::Status::Failing => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
+
+// Define a handler function for checking every part of the server's health
+pub async fn get_health(data: MovieData, health: web::Data<Health>) -> HttpResponse {
+    health_response(move || health.health(data.get_ref())).await
+}
+
+// Define a handler function for checking whether the server can serve
+// requests, so that a load balancer only sends it traffic when it can
+pub async fn get_readiness(data: MovieData, health: web::Data<Health>) -> HttpResponse {
+    health_response(move || health.readiness(data.get_ref())).await
+}
+
+// Define a handler function for checking whether the server has to be
+// restarted to recover
+pub async fn get_liveness(data: MovieData, health: web::Data<Health>) -> HttpResponse {
+    health_response(move || health.liveness(data.get_ref())).await
+}
+
+// Define a fallback handler for requests that match no route
+pub async fn not_found(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    Err(ApiError::not_found(format!(
+        "no resource at {} {}",
+        req.method(),
+        req.path()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{initial_version, MovieMetadata};
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+
+    fn movie(version: u64) -> Movie {
+        Movie {
+            id: Uuid::new_v4(),
+            isbn: "978-0-261-10235-4".to_string(),
+            title: "The Fellowship of the Ring".to_string(),
+            director_id: Uuid::new_v4(),
+            metadata: MovieMetadata::default(),
+            version,
+        }
+    }
+
+    fn rating(fingerprint: u32) -> RatingSummary {
+        RatingSummary {
+            count: 0,
+            mean: None,
+            histogram: Default::default(),
+            weighted_rating: None,
+            fingerprint,
+        }
+    }
+
+    fn with_if_match(value: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header((header::IF_MATCH, value))
+            .to_http_request()
+    }
+
+    #[test]
+    fn if_match_ignores_review_changes() {
+        let stored = movie(3);
+        // The client read the movie before someone else reviewed it
+        let tag = etag(&stored, &rating(0x1234)).to_string();
+        assert_eq!(if_match(&with_if_match(&tag), &stored).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn if_match_rejects_other_versions_and_weak_tags() {
+        let stored = movie(3);
+        let old = etag(&movie(2), &rating(0)).to_string();
+        assert!(if_match(&with_if_match(&old), &stored).is_err());
+        assert!(if_match(&with_if_match("W/\"3-00000000\""), &stored).is_err());
+        assert!(if_match(&with_if_match("\"garbage\""), &stored).is_err());
+    }
+
+    #[test]
+    fn etag_covers_the_weighted_rating() {
+        let movie = movie(3);
+        let mut rated = rating(0x1234);
+        let before = etag(&movie, &rated);
+        rated.weighted_rating = Some(7.25);
+        let after = etag(&movie, &rated);
+        assert!(!before.strong_eq(&after));
+        rated.weighted_rating = Some(7.5);
+        assert!(!after.strong_eq(&etag(&movie, &rated)));
+        assert!(etag(&movie, &rated).strong_eq(&etag(&movie, &rated)));
+    }
+
+    #[test]
+    fn if_match_accepts_any_and_absent_header() {
+        let stored = movie(initial_version());
+        assert_eq!(if_match(&with_if_match("*"), &stored).unwrap(), Some(1));
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(if_match(&req, &stored).unwrap(), None);
+    }
+
+    fn review(author: &str) -> Review {
+        let now = OffsetDateTime::now_utc();
+        Review {
+            id: Uuid::new_v4(),
+            movie_id: Uuid::new_v4(),
+            author: author.to_string(),
+            score: 7,
+            text: "Long, but worth it".to_string(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn as_client(name: &str, role: Role) -> HttpRequest {
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(auth::Principal {
+            name: name.to_string(),
+            role,
+        });
+        req
+    }
+
+    #[test]
+    fn reviews_can_be_changed_by_their_author() {
+        let review = review("alice");
+        assert!(check_review_owner(&as_client("alice", Role::Reader), &review).is_ok());
+    }
+
+    #[test]
+    fn reviews_cannot_be_changed_by_other_readers() {
+        let review = review("alice");
+        let err = check_review_owner(&as_client("mallory", Role::Reader), &review).unwrap_err();
+        assert_eq!(
+            actix_web::ResponseError::status_code(&err),
+            StatusCode::FORBIDDEN
+        );
+        let anonymous = TestRequest::default().to_http_request();
+        assert!(check_review_owner(&anonymous, &review).is_err());
+    }
+
+    #[test]
+    fn reviews_can_be_moderated_by_editors_and_admins() {
+        let review = review("alice");
+        for role in [Role::Editor, Role::Admin] {
+            assert!(check_review_owner(&as_client("moderator", role), &review).is_ok());
+        }
+    }
+
+    fn memory_data() -> MovieData {
+        use crate::repository::InMemoryMovieRepository;
+        use std::sync::Arc;
+
+        let repository: Arc<dyn Repository> = Arc::new(InMemoryMovieRepository::new());
+        web::Data::from(repository)
+    }
+
+    fn new_director(lastname: &str) -> NewDirector {
+        NewDirector {
+            firstname: "Peter".to_string(),
+            lastname: lastname.to_string(),
+        }
+    }
+
+    #[test]
+    fn refused_movies_leave_no_inline_director_behind() {
+        let data = memory_data();
+        let director = resolve_director(&data, None, Some(new_director("Jackson"))).unwrap();
+        assert!(director.created);
+
+        let refused = movie(1);
+        let result = store_movie(&data, &director, || {
+            Err(RepositoryError::NotFound(Entity::Movie, refused.id))
+        });
+        assert!(result.is_err());
+        assert!(data.get_director(director.id).is_err());
+    }
+
+    #[test]
+    fn refused_movies_keep_existing_directors() {
+        let data = memory_data();
+        let stored = resolve_director(&data, None, Some(new_director("Weir"))).unwrap();
+        let director = resolve_director(&data, None, Some(new_director("Weir"))).unwrap();
+        assert_eq!(director.id, stored.id);
+        assert!(!director.created);
+
+        let refused = movie(1);
+        let result = store_movie(&data, &director, || {
+            Err(RepositoryError::VersionMismatch {
+                id: refused.id,
+                expected: 1,
+                actual: 2,
+            })
+        });
+        assert!(result.is_err());
+        assert!(data.get_director(stored.id).is_ok());
+    }
+
+    #[test]
+    fn stored_movies_keep_their_inline_director() {
+        let data = memory_data();
+        let director = resolve_director(&data, None, Some(new_director("Jackson"))).unwrap();
+        let mut new = movie(initial_version());
+        new.director_id = director.id;
+        assert!(store_movie(&data, &director, || data.insert(new)).is_ok());
+        assert!(data.get_director(director.id).is_ok());
+    }
+
+    // Store a movie titled as given, with a director of its own
+    fn stored_movie(data: &MovieData, title: &str) -> Movie {
+        let director = resolve_director(data, None, Some(new_director(title))).unwrap();
+        let mut new = movie(initial_version());
+        new.title = title.to_string();
+        new.director_id = director.id;
+        data.insert(new).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn directors_are_renamed_for_their_movies_and_kept_while_referenced() {
+        use actix_web::test::{
+            call_and_read_body_json, call_service, init_service, read_body_json,
+        };
+        use serde_json::{json, Value};
+
+        let data = memory_data();
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/directors", web::post().to(create_director))
+                .route("/directors/{id}", web::get().to(get_director_by_id))
+                .route("/directors/{id}", web::put().to(update_director_by_id))
+                .route("/directors/{id}", web::delete().to(delete_director_by_id))
+                .route("/directors/{id}/movies", web::get().to(get_director_movies)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/directors")
+            .set_json(json!({"firstname": "Ridley", "lastname": ""}))
+            .to_request();
+        assert!(call_service(&app, req).await.status().is_client_error());
+
+        let req = TestRequest::post()
+            .uri("/directors")
+            .set_json(json!({"firstname": "Ridley", "lastname": "Scot"}))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let director: Director = read_body_json(response).await;
+        let uri = format!("/directors/{}", director.id);
+
+        let mut alien = movie(initial_version());
+        alien.title = "Alien".to_string();
+        alien.director_id = director.id;
+        let alien = data.insert(alien).unwrap();
+        stored_movie(&data, "Heat");
+
+        // Renaming the director keeps the movies referring to it
+        let req = TestRequest::put()
+            .uri(&uri)
+            .set_json(json!({"firstname": "Ridley", "lastname": "Scott"}))
+            .to_request();
+        let renamed: Director = call_and_read_body_json(&app, req).await;
+        assert_eq!(renamed.id, director.id);
+        assert_eq!(renamed.lastname, "Scott");
+        let req = TestRequest::get().uri(&uri).to_request();
+        let stored: Director = call_and_read_body_json(&app, req).await;
+        assert_eq!(stored, renamed);
+
+        let req = TestRequest::get()
+            .uri(&format!("{}/movies", uri))
+            .to_request();
+        let movies: Value = call_and_read_body_json(&app, req).await;
+        let titles: Vec<&str> = movies
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|movie| movie["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["Alien"]);
+
+        // The director cannot be deleted while a movie refers to it
+        let req = TestRequest::delete().uri(&uri).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CONFLICT);
+        data.delete(alien.id, None).unwrap();
+        let req = TestRequest::delete().uri(&uri).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+
+        for uri in [uri.clone(), format!("{}/movies", uri)] {
+            let req = TestRequest::get().uri(&uri).to_request();
+            assert_eq!(
+                call_service(&app, req).await.status(),
+                StatusCode::NOT_FOUND
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn credits_are_kept_in_billing_order_and_listed_in_filmographies() {
+        use actix_web::test::{call_and_read_body_json, call_service, init_service};
+        use serde_json::{json, Value};
+
+        let data = memory_data();
+        let heat = stored_movie(&data, "Heat");
+        let alien = stored_movie(&data, "Alien");
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/people", web::post().to(create_person))
+                .route("/people/{id}", web::delete().to(delete_person_by_id))
+                .route("/people/{id}/credits", web::get().to(get_person_credits))
+                .route("/movies/{id}/credits", web::get().to(get_movie_credits))
+                .route("/movies/{id}/credits", web::post().to(create_movie_credit))
+                .route(
+                    "/movies/{id}/credits/{credit_id}",
+                    web::put().to(update_movie_credit),
+                )
+                .route(
+                    "/movies/{id}/credits/{credit_id}",
+                    web::delete().to(delete_movie_credit),
+                ),
+        )
+        .await;
+
+        let mut people = HashMap::new();
+        for name in ["Pacino", "De Niro", "Mann"] {
+            let req = TestRequest::post()
+                .uri("/people")
+                .set_json(json!({"firstname": "Someone", "lastname": name}))
+                .to_request();
+            let person: Value = call_and_read_body_json(&app, req).await;
+            people.insert(name, person["id"].as_str().unwrap().to_string());
+        }
+        let credit = |movie: &Movie, body: Value| {
+            TestRequest::post()
+                .uri(&format!("/movies/{}/credits", movie.id))
+                .set_json(body)
+                .to_request()
+        };
+
+        // Credits without a billing position are billed after the others of
+        // their role
+        let pacino: Value = call_and_read_body_json(
+            &app,
+            credit(
+                &heat,
+                json!({"person_id": people["Pacino"], "role": "actor", "character": "Vincent Hanna"}),
+            ),
+        )
+        .await;
+        assert_eq!(pacino["billing"], 1);
+        assert_eq!(pacino["person"]["lastname"], "Pacino");
+        let de_niro: Value = call_and_read_body_json(
+            &app,
+            credit(
+                &heat,
+                json!({"person_id": people["De Niro"], "role": "actor", "character": "Neil"}),
+            ),
+        )
+        .await;
+        assert_eq!(de_niro["billing"], 2);
+        for (movie, role) in [(&heat, "writer"), (&alien, "producer")] {
+            let req = credit(movie, json!({"person_id": people["Mann"], "role": role}));
+            assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+        }
+
+        // A replaced credit keeps its position unless given a new one
+        let uri = |credit: &Value| {
+            format!(
+                "/movies/{}/credits/{}",
+                heat.id,
+                credit["id"].as_str().unwrap()
+            )
+        };
+        let req = TestRequest::put()
+            .uri(&uri(&de_niro))
+            .set_json(json!({"person_id": people["De Niro"], "role": "actor", "character": "Neil McCauley"}))
+            .to_request();
+        let replaced: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(replaced["billing"], 2);
+        assert_eq!(replaced["character"], "Neil McCauley");
+        let req = TestRequest::put()
+            .uri(&uri(&pacino))
+            .set_json(json!({"person_id": people["Pacino"], "role": "actor", "character": "Vincent Hanna", "billing": 3}))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        // The credits of a movie are grouped by role, in billing order
+        let req = TestRequest::get()
+            .uri(&format!("/movies/{}/credits", heat.id))
+            .to_request();
+        let credits: Vec<Value> = call_and_read_body_json(&app, req).await;
+        let order: Vec<_> = credits
+            .iter()
+            .map(|credit| credit["person"]["lastname"].as_str().unwrap())
+            .collect();
+        assert_eq!(order, ["Mann", "De Niro", "Pacino"]);
+
+        // A filmography lists the credits of a person by movie title
+        let req = TestRequest::get()
+            .uri(&format!("/people/{}/credits", people["Mann"]))
+            .to_request();
+        let filmography: Vec<Value> = call_and_read_body_json(&app, req).await;
+        let titles: Vec<_> = filmography
+            .iter()
+            .map(|credit| credit["movie"]["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["Alien", "Heat"]);
+
+        // A credit is only found under its own movie, and removed once
+        let req = TestRequest::delete()
+            .uri(&format!(
+                "/movies/{}/credits/{}",
+                alien.id,
+                pacino["id"].as_str().unwrap()
+            ))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let req = TestRequest::delete().uri(&uri(&pacino)).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        let req = TestRequest::delete().uri(&uri(&pacino)).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        // People stay while they are credited
+        let req = TestRequest::delete()
+            .uri(&format!("/people/{}", people["De Niro"]))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn credits_need_a_stored_movie_and_person() {
+        use actix_web::test::{call_service, init_service};
+        use serde_json::json;
+
+        let data = memory_data();
+        let heat = stored_movie(&data, "Heat");
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/movies/{id}/credits", web::post().to(create_movie_credit)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri(&format!("/movies/{}/credits", heat.id))
+            .set_json(json!({"person_id": Uuid::new_v4(), "role": "writer"}))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let req = TestRequest::post()
+            .uri(&format!("/movies/{}/credits", Uuid::new_v4()))
+            .set_json(json!({"person_id": Uuid::new_v4(), "role": "writer"}))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert!(data.movie_credits(heat.id).unwrap().is_empty());
+    }
+
+    fn ratings() -> web::Data<RatingIndex> {
+        web::Data::new(RatingIndex::build(&[]).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn movie_ids_are_assigned_by_the_server() {
+        use actix_web::test::{call_and_read_body_json, call_service, init_service};
+        use serde_json::{json, Value};
+
+        let data = memory_data();
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .app_data(ratings())
+                .route("/movies", web::post().to(create_movie))
+                .route("/movies/{id}", web::put().to(update_movie_by_id)),
+        )
+        .await;
+        let body = json!({
+            "isbn": "978-0-261-10235-4",
+            "title": "Heat",
+            "director": {"firstname": "Michael", "lastname": "Mann"},
+        });
+
+        // A client cannot pick the ID of a new movie
+        let mut with_id = body.clone();
+        with_id["id"] = json!(Uuid::new_v4());
+        let req = TestRequest::post()
+            .uri("/movies")
+            .set_json(with_id)
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert!(data.list().unwrap().is_empty());
+
+        let req = TestRequest::post()
+            .uri("/movies")
+            .set_json(&body)
+            .to_request();
+        let created: Value = call_and_read_body_json(&app, req).await;
+        let id = created["id"].as_str().unwrap().to_string();
+        assert_eq!(created["version"], 1);
+        assert!(data.get(Uuid::parse_str(&id).unwrap()).is_ok());
+
+        // An update may repeat the ID of the movie, but not name another one
+        let uri = format!("/movies/{}", id);
+        let mut other = body.clone();
+        other["id"] = json!(Uuid::new_v4());
+        let req = TestRequest::put().uri(&uri).set_json(other).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        let mut same = body.clone();
+        same["id"] = json!(id);
+        let req = TestRequest::put().uri(&uri).set_json(same).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+        let req = TestRequest::put().uri(&uri).set_json(&body).to_request();
+        let updated: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(updated["version"], 3);
+    }
+
+    #[actix_web::test]
+    async fn etags_answer_conditional_requests() {
+        use actix_web::test::{call_service, init_service};
+        use serde_json::json;
+
+        let data = memory_data();
+        let movie = stored_movie(&data, "Heat");
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .app_data(ratings())
+                .route("/movies/{id}", web::get().to(get_movie_by_id))
+                .route("/movies/{id}", web::put().to(update_movie_by_id))
+                .route("/movies/{id}", web::delete().to(delete_movie_by_id)),
+        )
+        .await;
+        let uri = format!("/movies/{}", movie.id);
+        let etag = |response: &actix_web::dev::ServiceResponse| {
+            response.headers().get(header::ETAG).unwrap().clone()
+        };
+
+        // A client holding the current version gets a 304 response
+        let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let first = etag(&response);
+        let req = TestRequest::get()
+            .uri(&uri)
+            .insert_header((header::IF_NONE_MATCH, first.clone()))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_MODIFIED
+        );
+
+        // An update needs the current version, and changes the ETag
+        let body = json!({
+            "isbn": movie.isbn,
+            "title": "Heat (1995)",
+            "director_id": movie.director_id,
+        });
+        let put = |tag: &str| {
+            TestRequest::put()
+                .uri(&uri)
+                .insert_header((header::IF_MATCH, tag))
+                .set_json(&body)
+                .to_request()
+        };
+        let response = call_service(&app, put(first.to_str().unwrap())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let second = etag(&response);
+        assert_ne!(first, second);
+        let response = call_service(&app, put(first.to_str().unwrap())).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        // A tag that cannot be parsed matches no version either
+        let response = call_service(&app, put("not an etag")).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let req = TestRequest::get()
+            .uri(&uri)
+            .insert_header((header::IF_NONE_MATCH, first.clone()))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        // So does a delete
+        let delete = |tag: &header::HeaderValue| {
+            TestRequest::delete()
+                .uri(&uri)
+                .insert_header((header::IF_MATCH, tag.clone()))
+                .to_request()
+        };
+        let response = call_service(&app, delete(&first)).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert!(data.get(movie.id).is_ok());
+        let response = call_service(&app, delete(&second)).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(data.get(movie.id).is_err());
+    }
+
+    #[actix_web::test]
+    async fn movies_are_patched_with_either_patch_format() {
+        use actix_web::test::{call_and_read_body_json, call_service, init_service};
+        use serde_json::Value;
+
+        let data = memory_data();
+        let movie = stored_movie(&data, "Heat");
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .app_data(ratings())
+                .route("/movies/{id}", web::patch().to(patch_movie_by_id)),
+        )
+        .await;
+        let patch = |media_type: &str, body: &str| {
+            TestRequest::patch()
+                .uri(&format!("/movies/{}", movie.id))
+                .insert_header((header::CONTENT_TYPE, media_type))
+                .set_payload(body.to_string())
+                .to_request()
+        };
+
+        let patched: Value = call_and_read_body_json(
+            &app,
+            patch(
+                patch::MERGE_PATCH,
+                r#"{"title": "Heat (1995)", "runtime_minutes": 170}"#,
+            ),
+        )
+        .await;
+        assert_eq!(patched["title"], "Heat (1995)");
+        assert_eq!(patched["runtime_minutes"], 170);
+        assert_eq!(patched["version"], 2);
+        assert_eq!(patched["isbn"], movie.isbn.as_str());
+
+        // Renaming the director refers the movie to another director rather
+        // than renaming the one other movies may share
+        let patched: Value = call_and_read_body_json(
+            &app,
+            patch(
+                patch::JSON_PATCH,
+                r#"[{"op": "replace", "path": "/director/lastname", "value": "Mann"}]"#,
+            ),
+        )
+        .await;
+        let director_id = Uuid::parse_str(patched["director_id"].as_str().unwrap()).unwrap();
+        assert_ne!(director_id, movie.director_id);
+        assert_eq!(data.get_director(director_id).unwrap().lastname, "Mann");
+        assert_eq!(
+            data.get_director(movie.director_id).unwrap().lastname,
+            "Heat"
+        );
+
+        // Patches that cannot be applied change nothing
+        let cases = [
+            (
+                "application/json",
+                r#"{"title": "Ronin"}"#,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (patch::MERGE_PATCH, "{", StatusCode::BAD_REQUEST),
+            (
+                patch::JSON_PATCH,
+                r#"[{"op": "test", "path": "/title", "value": "Ronin"}]"#,
+                StatusCode::CONFLICT,
+            ),
+            (
+                patch::MERGE_PATCH,
+                r#"{"title": ""}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                patch::MERGE_PATCH,
+                r#"{"id": "00000000-0000-0000-0000-000000000000"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ];
+        for (media_type, body, status) in cases {
+            let response = call_service(&app, patch(media_type, body)).await;
+            assert_eq!(response.status(), status, "{}", body);
+        }
+        let response = call_service(&app, patch("text/plain", "{}")).await;
+        assert!(response.headers().contains_key("accept-patch"));
+        assert_eq!(data.get(movie.id).unwrap().version, 3);
+    }
+
+    #[actix_web::test]
+    async fn storage_operations_run_off_the_worker() {
+        let worker = std::thread::current().id();
+        let thread = blocking(|| Ok(std::thread::current().id())).await.unwrap();
+        assert_ne!(thread, worker);
+    }
+
+    #[actix_web::test]
+    async fn health_endpoints_answer_503_until_ready() {
+        let data = memory_data();
+        let health = web::Data::new(Health::default());
+
+        let response = get_readiness(data.clone(), health.clone()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = get_liveness(data.clone(), health.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        health.set_ready(true);
+        let response = get_health(data.clone(), health.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get_readiness(data, health).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
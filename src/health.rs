@@ -0,0 +1,217 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::repository::MovieRepository;
+
+// Define the outcome of a health check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Failing,
+}
+
+// Define the outcome of a single health check, with the reason it failed
+#[derive(Debug, Serialize)]
+pub struct Check {
+    name: &'static str,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, failure: Option<String>) -> Self {
+        Self {
+            name,
+            status: match failure {
+                Some(_) => Status::Failing,
+                None => Status::Ok,
+            },
+            detail: failure,
+        }
+    }
+}
+
+// Define the body of the health endpoints, which is ok only if every check is
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    checks: Vec<Check>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<Check>) -> Self {
+        let status = match checks.iter().all(|check| check.status == Status::Ok) {
+            true => Status::Ok,
+            false => Status::Failing,
+        };
+        Self { status, checks }
+    }
+}
+
+// Define the state the health checks look at besides the storage backend
+#[derive(Debug, Default)]
+pub struct Health {
+    // Set once the storage backend is open and seeded
+    ready: AtomicBool,
+}
+
+impl Health {
+    // Mark the server as ready to serve requests or not
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    // Check that startup finished
+    fn startup(&self) -> Check {
+        let failure = match self.ready.load(Ordering::SeqCst) {
+            true => None,
+            false => Some("the server is not ready to serve requests".to_string()),
+        };
+        Check::new("startup", failure)
+    }
+
+    // Report whether the server can recover without a restart, which it cannot
+    // once a storage lock is poisoned
+    pub fn liveness(&self, data: &(impl MovieRepository + ?Sized)) -> HealthReport {
+        HealthReport::new(vec![locks(data)])
+    }
+
+    // Report whether the server can serve requests right now
+    pub fn readiness(&self, data: &(impl MovieRepository + ?Sized)) -> HealthReport {
+        HealthReport::new(vec![self.startup(), storage(data)])
+    }
+
+    // Report every check at once
+    pub fn health(&self, data: &(impl MovieRepository + ?Sized)) -> HealthReport {
+        HealthReport::new(vec![self.startup(), locks(data), storage(data)])
+    }
+}
+
+// Check that no storage lock is poisoned
+fn locks(data: &(impl MovieRepository + ?Sized)) -> Check {
+    let failure = data
+        .is_poisoned()
+        .then(|| "a storage lock was poisoned by a panic".to_string());
+    Check::new("locks", failure)
+}
+
+// Check that the storage backend answers. The endpoints need no credentials,
+// so why it does not is only logged, as it may name files and their errors.
+fn storage(data: &(impl MovieRepository + ?Sized)) -> Check {
+    let failure = data.ping().err().map(|err| {
+        tracing::error!(error = %err, "storage health check failed");
+        "storage is unreachable".to_string()
+    });
+    Check::new("storage", failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Movie;
+    use crate::repository::RepositoryError;
+    use uuid::Uuid;
+
+    // Define a storage backend that only answers health checks
+    struct Backend {
+        reachable: bool,
+        poisoned: bool,
+    }
+
+    impl MovieRepository for Backend {
+        fn list(&self) -> Result<Vec<Movie>, RepositoryError> {
+            unimplemented!()
+        }
+
+        fn get(&self, _id: Uuid) -> Result<Movie, RepositoryError> {
+            unimplemented!()
+        }
+
+        fn insert(&self, _movie: Movie) -> Result<Movie, RepositoryError> {
+            unimplemented!()
+        }
+
+        fn update(&self, _movie: Movie, _expected: Option<u64>) -> Result<Movie, RepositoryError> {
+            unimplemented!()
+        }
+
+        fn delete(&self, _id: Uuid, _expected: Option<u64>) -> Result<(), RepositoryError> {
+            unimplemented!()
+        }
+
+        fn check_integrity(&self) -> Result<(), RepositoryError> {
+            unimplemented!()
+        }
+
+        fn ping(&self) -> Result<(), RepositoryError> {
+            match self.reachable {
+                true => Ok(()),
+                false => Err(RepositoryError::Storage(
+                    "unable to open /var/lib/movies/movies.db".to_string(),
+                )),
+            }
+        }
+
+        fn is_poisoned(&self) -> bool {
+            self.poisoned
+        }
+
+        fn flush(&self) -> Result<(), RepositoryError> {
+            unimplemented!()
+        }
+    }
+
+    const HEALTHY: Backend = Backend {
+        reachable: true,
+        poisoned: false,
+    };
+
+    fn ready() -> Health {
+        let health = Health::default();
+        health.set_ready(true);
+        health
+    }
+
+    #[test]
+    fn healthy_server_passes_every_check() {
+        let health = ready();
+        assert_eq!(health.health(&HEALTHY).status, Status::Ok);
+        assert_eq!(health.readiness(&HEALTHY).status, Status::Ok);
+        assert_eq!(health.liveness(&HEALTHY).status, Status::Ok);
+    }
+
+    #[test]
+    fn server_is_not_ready_until_startup_finishes() {
+        let health = Health::default();
+        assert_eq!(health.readiness(&HEALTHY).status, Status::Failing);
+        // Still starting up is no reason to restart the server
+        assert_eq!(health.liveness(&HEALTHY).status, Status::Ok);
+    }
+
+    #[test]
+    fn unreachable_storage_fails_readiness_without_naming_the_error() {
+        let backend = Backend {
+            reachable: false,
+            poisoned: false,
+        };
+        let report = ready().readiness(&backend);
+        assert_eq!(report.status, Status::Failing);
+        let body = serde_json::to_string(&report).unwrap();
+        assert!(body.contains("storage is unreachable"));
+        assert!(!body.contains("movies.db"));
+    }
+
+    #[test]
+    fn poisoned_lock_fails_liveness() {
+        let backend = Backend {
+            reachable: true,
+            poisoned: true,
+        };
+        let health = ready();
+        assert_eq!(health.liveness(&backend).status, Status::Failing);
+        assert_eq!(health.health(&backend).status, Status::Failing);
+        assert_eq!(health.readiness(&backend).status, Status::Ok);
+    }
+}
@@ -0,0 +1,890 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::metrics;
+use crate::models::{
+    initial_version, Credit, Director, Movie, MovieMetadata, NewDirector, Person, Review,
+};
+use crate::repository::{
+    CreditRepository, DirectorRepository, MovieRepository, PersonRepository, RepositoryError,
+    ReviewRepository, Store,
+};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_FILE: &str = "journal.log";
+
+// Define the mutations recorded in the write-ahead log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LogEntry {
+    // A movie was created or replaced
+    Put { movie: StoredMovie },
+    // A movie was removed together with its credits and reviews
+    Delete { id: Uuid },
+    // A director was created or replaced
+    PutDirector { director: Director },
+    // A director was removed
+    DeleteDirector { id: Uuid },
+    // A person was created or replaced
+    PutPerson { person: Person },
+    // A person was removed
+    DeletePerson { id: Uuid },
+    // A credit was created or replaced
+    PutCredit { credit: Credit },
+    // A credit was removed
+    DeleteCredit { id: Uuid },
+    // A review was created or replaced
+    PutReview { review: Review },
+    // A review was removed
+    DeleteReview { id: Uuid },
+}
+
+// Define a movie as written by current and by earlier versions of the journal,
+// which embedded the director's name instead of referring to it by ID
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredMovie {
+    Current(Movie),
+    Legacy(LegacyMovie),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyMovie {
+    id: Uuid,
+    isbn: String,
+    title: String,
+    director: NewDirector,
+    #[serde(default = "initial_version")]
+    version: u64,
+}
+
+// Define the layout of snapshot files, including the plain list of movies
+// written by earlier versions of the journal
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Snapshot {
+    Current {
+        directors: Vec<Director>,
+        movies: Vec<Movie>,
+        // Missing from snapshots written before people and credits existed
+        #[serde(default)]
+        people: Vec<Person>,
+        #[serde(default)]
+        credits: Vec<Credit>,
+        // Missing from snapshots written before reviews existed
+        #[serde(default)]
+        reviews: Vec<Review>,
+    },
+    Legacy(Vec<LegacyMovie>),
+}
+
+#[derive(Debug, Serialize)]
+struct SnapshotRef<'a> {
+    directors: Vec<&'a Director>,
+    movies: Vec<&'a Movie>,
+    people: Vec<&'a Person>,
+    credits: Vec<&'a Credit>,
+    reviews: Vec<&'a Review>,
+}
+
+impl From<io::Error> for RepositoryError {
+    fn from(err: io::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for RepositoryError {
+    fn from(err: serde_json::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+// Define the state guarded by the journal's Mutex
+struct JournalState {
+    store: Store,
+    log: File,
+    // Number of log entries written since the last snapshot
+    pending: usize,
+}
+
+// Define a backend that keeps the records in memory, appends every mutation to
+// a log file and periodically compacts the log into a JSON snapshot
+pub struct JournalMovieRepository {
+    dir: PathBuf,
+    compact_every: usize,
+    state: Mutex<JournalState>,
+    // Set once a failed write could not be cut off the log again, after which
+    // every write is refused
+    damaged: AtomicBool,
+}
+
+impl JournalMovieRepository {
+    // Open the journal in the given directory, replaying the snapshot and the log
+    pub fn open(dir: impl Into<PathBuf>, compact_every: usize) -> Result<Self, RepositoryError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        // Start from the last snapshot, if there is one
+        let mut store = Store::default();
+        let mut upgraded = read_snapshot(&dir.join(SNAPSHOT_FILE), &mut store)?;
+
+        // Replay the log on top of it, cutting off anything after a corrupt record
+        let log_path = dir.join(LOG_FILE);
+        let (pending, upgraded_log) = replay_log(&log_path, &mut store)?;
+        upgraded |= upgraded_log;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        sync_dir(&dir)?;
+
+        tracing::info!(
+            movies = store.movies.len(),
+            directors = store.directors.len(),
+            people = store.people.len(),
+            credits = store.credits.len(),
+            reviews = store.reviews.len(),
+            log_entries = pending,
+            "Recovered journal in {}",
+            dir.display()
+        );
+
+        let journal = Self {
+            dir,
+            compact_every,
+            state: Mutex::new(JournalState {
+                store,
+                log,
+                pending,
+            }),
+            damaged: AtomicBool::new(false),
+        };
+
+        // Directors created for movies in the earlier format only exist in memory
+        // so far, so persist them before they could be created again differently
+        if upgraded {
+            let mut state = journal.lock()?;
+            journal.compact(&mut state)?;
+        }
+
+        Ok(journal)
+    }
+
+    // Lock the state, reporting a poisoned Mutex as a storage error
+    fn lock(&self) -> Result<MutexGuard<'_, JournalState>, RepositoryError> {
+        metrics::lock("journal", &self.state)
+            .map_err(|_| RepositoryError::Storage("journal lock poisoned".to_string()))
+    }
+
+    // Durably append an entry to the log, then apply it in memory and compact the
+    // log once it has grown long enough
+    fn commit(&self, state: &mut JournalState, entry: LogEntry) -> Result<(), RepositoryError> {
+        if self.damaged.load(Ordering::Acquire) {
+            return Err(RepositoryError::Storage(
+                "journal log is damaged by an earlier failed write".to_string(),
+            ));
+        }
+
+        let json = serde_json::to_string(&entry)?;
+        let line = format!("{:08x} {}\n", crc32fast::hash(json.as_bytes()), json);
+
+        // Replay stops at the first torn entry and drops everything after it,
+        // so a failed write has to be cut off before anything else is appended
+        let offset = state.log.metadata()?.len();
+        if let Err(err) = append(&mut state.log, line.as_bytes()) {
+            if let Err(truncate_err) = truncate(&state.log, offset) {
+                self.damaged.store(true, Ordering::Release);
+                tracing::error!(
+                    error = %truncate_err,
+                    "cannot cut a failed write off the journal log, refusing further writes"
+                );
+            }
+            return Err(err.into());
+        }
+        state.pending += 1;
+        apply(&mut state.store, entry);
+
+        // The entry is already durable, so a failed compaction is only reported
+        if state.pending >= self.compact_every {
+            if let Err(err) = self.compact(state) {
+                tracing::warn!(error = %err, "journal compaction failed");
+            }
+        }
+        Ok(())
+    }
+
+    // Write the current records to a new snapshot and start an empty log
+    fn compact(&self, state: &mut JournalState) -> Result<(), RepositoryError> {
+        // Write the snapshot to a temporary file first so a crash never leaves a
+        // half-written snapshot behind, then atomically move it into place
+        let snapshot_path = self.dir.join(SNAPSHOT_FILE);
+        let tmp_path = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let mut tmp = File::create(&tmp_path)?;
+        let snapshot = SnapshotRef {
+            directors: state.store.directors.values().collect(),
+            movies: state.store.movies.values().collect(),
+            people: state.store.people.values().collect(),
+            credits: state.store.credits.values().collect(),
+            reviews: state.store.reviews.values().collect(),
+        };
+        serde_json::to_writer(&mut tmp, &snapshot)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &snapshot_path)?;
+        sync_dir(&self.dir)?;
+
+        // Everything in the log is now covered by the snapshot. Replaying entries
+        // again is harmless, so a crash before the truncation loses nothing.
+        // This also drops a failed write that could not be cut off before.
+        state.log.set_len(0)?;
+        state.log.sync_all()?;
+        state.pending = 0;
+        self.damaged.store(false, Ordering::Release);
+        Ok(())
+    }
+}
+
+// Store a movie in the earlier format, reusing or creating a director with the
+// embedded name
+fn upgrade(store: &mut Store, legacy: LegacyMovie) -> Movie {
+    let director = legacy.director.into_director(Uuid::new_v4());
+    let director_id = match store.find_director(&director) {
+        Some(stored) => stored.id,
+        None => {
+            let id = director.id;
+            store.directors.insert(id, director);
+            id
+        }
+    };
+
+    Movie {
+        id: legacy.id,
+        isbn: legacy.isbn,
+        title: legacy.title,
+        director_id,
+        metadata: MovieMetadata::default(),
+        version: legacy.version,
+    }
+}
+
+// Apply a log entry to the records, returning true if it had to be upgraded
+// from an earlier format
+fn apply(store: &mut Store, entry: LogEntry) -> bool {
+    match entry {
+        LogEntry::Put { movie } => {
+            let (movie, upgraded) = match movie {
+                StoredMovie::Current(movie) => (movie, false),
+                StoredMovie::Legacy(legacy) => (upgrade(store, legacy), true),
+            };
+            store.movies.insert(movie.id, movie);
+            return upgraded;
+        }
+        LogEntry::Delete { id } => store.remove_movie(id),
+        LogEntry::PutDirector { director } => {
+            store.directors.insert(director.id, director);
+        }
+        LogEntry::DeleteDirector { id } => {
+            store.directors.remove(&id);
+        }
+        LogEntry::PutPerson { person } => {
+            store.people.insert(person.id, person);
+        }
+        LogEntry::DeletePerson { id } => {
+            store.people.remove(&id);
+        }
+        LogEntry::PutCredit { credit } => {
+            store.credits.insert(credit.id, credit);
+        }
+        LogEntry::DeleteCredit { id } => {
+            store.credits.remove(&id);
+        }
+        LogEntry::PutReview { review } => {
+            store.reviews.insert(review.id, review);
+        }
+        LogEntry::DeleteReview { id } => {
+            store.reviews.remove(&id);
+        }
+    }
+    false
+}
+
+// Read the records from a snapshot file, treating a missing file as empty.
+// Returns true if the snapshot had to be upgraded from an earlier format.
+fn read_snapshot(path: &Path, store: &mut Store) -> Result<bool, RepositoryError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+        RepositoryError::Storage(format!("corrupt snapshot {}: {}", path.display(), err))
+    })?;
+    match snapshot {
+        Snapshot::Current {
+            directors,
+            movies,
+            people,
+            credits,
+            reviews,
+        } => {
+            store.directors = directors.into_iter().map(|d| (d.id, d)).collect();
+            store.movies = movies.into_iter().map(|m| (m.id, m)).collect();
+            store.people = people.into_iter().map(|p| (p.id, p)).collect();
+            store.credits = credits.into_iter().map(|c| (c.id, c)).collect();
+            store.reviews = reviews.into_iter().map(|r| (r.id, r)).collect();
+            Ok(false)
+        }
+        Snapshot::Legacy(movies) => {
+            for legacy in movies {
+                let movie = upgrade(store, legacy);
+                store.movies.insert(movie.id, movie);
+            }
+            Ok(true)
+        }
+    }
+}
+
+// Parse one log line, returning None if its checksum or JSON is invalid
+fn parse_log_line(line: &str) -> Option<LogEntry> {
+    let (crc, json) = line.trim_end_matches('\n').split_once(' ')?;
+    let crc = u32::from_str_radix(crc, 16).ok()?;
+    if crc32fast::hash(json.as_bytes()) != crc {
+        return None;
+    }
+    serde_json::from_str(json).ok()
+}
+
+// Apply every valid log entry to the records and return how many were replayed
+// and whether any had to be upgraded from an earlier format. Replay stops at
+// the first torn or corrupt record; the unreadable tail is moved to a side file
+// for inspection and the log is truncated to its valid prefix.
+fn replay_log(path: &Path, store: &mut Store) -> Result<(usize, bool), RepositoryError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((0, false)),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    let mut valid_len = 0;
+    let mut replayed = 0;
+    let mut upgraded = false;
+
+    loop {
+        line.clear();
+        let read = match reader.read_line(&mut line) {
+            Ok(0) => return Ok((replayed, upgraded)),
+            Ok(read) => read,
+            // Invalid UTF-8 is treated like any other corrupt record
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        // A record without its trailing newline was torn by a crash mid-write
+        if !line.ends_with('\n') {
+            break;
+        }
+        match parse_log_line(&line) {
+            Some(entry) => upgraded |= apply(store, entry),
+            None => break,
+        }
+
+        valid_len += read as u64;
+        replayed += 1;
+    }
+
+    // Preserve the unreadable tail before cutting it off the log
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let corrupt_path = path.with_extension(format!("corrupt-{}", Uuid::new_v4()));
+    file.seek(SeekFrom::Start(valid_len))?;
+    let mut corrupt = File::create(&corrupt_path)?;
+    io::copy(&mut file, &mut corrupt)?;
+    corrupt.sync_all()?;
+    file.set_len(valid_len)?;
+    file.sync_all()?;
+
+    tracing::warn!(
+        "journal {} is corrupt after {} entries, moved the remainder to {}",
+        path.display(),
+        replayed,
+        corrupt_path.display()
+    );
+    Ok((replayed, upgraded))
+}
+
+// Durably append a line to the log
+fn append(log: &mut File, line: &[u8]) -> io::Result<()> {
+    log.write_all(line)?;
+    log.sync_data()
+}
+
+// Durably cut the log back to the given length
+fn truncate(log: &File, len: u64) -> io::Result<()> {
+    log.set_len(len)?;
+    log.sync_data()
+}
+
+// Flush directory metadata so file creations and renames survive a crash
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+impl MovieRepository for JournalMovieRepository {
+    fn list(&self) -> Result<Vec<Movie>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state.store.movies.values().cloned().collect())
+    }
+
+    fn get(&self, id: Uuid) -> Result<Movie, RepositoryError> {
+        self.lock()?.store.get_movie(id).cloned()
+    }
+
+    fn insert(&self, movie: Movie) -> Result<Movie, RepositoryError> {
+        let mut state = self.lock()?;
+        let movie = state.store.prepare_insert_movie(movie)?;
+
+        // Log the mutation before applying it in memory
+        let entry = LogEntry::Put {
+            movie: StoredMovie::Current(movie.clone()),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(movie)
+    }
+
+    fn update(
+        &self,
+        movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, RepositoryError> {
+        let mut state = self.lock()?;
+        let movie = state.store.prepare_update_movie(movie, expected_version)?;
+
+        let entry = LogEntry::Put {
+            movie: StoredMovie::Current(movie.clone()),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(movie)
+    }
+
+    fn delete(&self, id: Uuid, expected_version: Option<u64>) -> Result<(), RepositoryError> {
+        let mut state = self.lock()?;
+        state.store.prepare_delete_movie(id, expected_version)?;
+
+        self.commit(&mut state, LogEntry::Delete { id })
+    }
+
+    fn check_integrity(&self) -> Result<(), RepositoryError> {
+        self.lock()?.store.check_integrity()
+    }
+
+    fn ping(&self) -> Result<(), RepositoryError> {
+        // Every entry is synced as it is written, so this only finds out
+        // whether the log file can still reach the disk
+        self.lock()?.log.sync_data()?;
+        Ok(())
+    }
+
+    fn is_poisoned(&self) -> bool {
+        self.state.is_poisoned() || self.damaged.load(Ordering::Acquire)
+    }
+
+    fn flush(&self) -> Result<(), RepositoryError> {
+        // The log already holds every write, but compacting it now saves
+        // replaying it on the next start, and repairs a damaged log
+        let mut state = self.lock()?;
+        match state.pending == 0 && !self.damaged.load(Ordering::Acquire) {
+            true => Ok(()),
+            false => self.compact(&mut state),
+        }
+    }
+}
+
+impl DirectorRepository for JournalMovieRepository {
+    fn list_directors(&self) -> Result<Vec<Director>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state.store.directors.values().cloned().collect())
+    }
+
+    fn get_director(&self, id: Uuid) -> Result<Director, RepositoryError> {
+        self.lock()?.store.get_director(id).cloned()
+    }
+
+    fn insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let mut state = self.lock()?;
+        let director = state.store.prepare_insert_director(director)?;
+
+        let entry = LogEntry::PutDirector {
+            director: director.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(director)
+    }
+
+    fn update_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let mut state = self.lock()?;
+        let director = state.store.prepare_update_director(director)?;
+
+        let entry = LogEntry::PutDirector {
+            director: director.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(director)
+    }
+
+    fn delete_director(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut state = self.lock()?;
+        state.store.prepare_delete_director(id)?;
+
+        self.commit(&mut state, LogEntry::DeleteDirector { id })
+    }
+
+    fn find_or_insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let mut state = self.lock()?;
+        if let Some(stored) = state.store.find_director(&director) {
+            return Ok(stored.clone());
+        }
+        let director = state.store.prepare_insert_director(director)?;
+
+        let entry = LogEntry::PutDirector {
+            director: director.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(director)
+    }
+}
+
+impl PersonRepository for JournalMovieRepository {
+    fn list_people(&self) -> Result<Vec<Person>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state.store.people.values().cloned().collect())
+    }
+
+    fn get_person(&self, id: Uuid) -> Result<Person, RepositoryError> {
+        self.lock()?.store.get_person(id).cloned()
+    }
+
+    fn insert_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        let mut state = self.lock()?;
+        let person = state.store.prepare_insert_person(person)?;
+
+        let entry = LogEntry::PutPerson {
+            person: person.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(person)
+    }
+
+    fn update_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        let mut state = self.lock()?;
+        let person = state.store.prepare_update_person(person)?;
+
+        let entry = LogEntry::PutPerson {
+            person: person.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(person)
+    }
+
+    fn delete_person(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut state = self.lock()?;
+        state.store.prepare_delete_person(id)?;
+
+        self.commit(&mut state, LogEntry::DeletePerson { id })
+    }
+}
+
+impl CreditRepository for JournalMovieRepository {
+    fn movie_credits(&self, movie_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        Ok(self
+            .lock()?
+            .store
+            .credits_where(|credit| credit.movie_id == movie_id))
+    }
+
+    fn person_credits(&self, person_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        Ok(self
+            .lock()?
+            .store
+            .credits_where(|credit| credit.person_id == person_id))
+    }
+
+    fn get_credit(&self, id: Uuid) -> Result<Credit, RepositoryError> {
+        self.lock()?.store.get_credit(id).cloned()
+    }
+
+    fn insert_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        let mut state = self.lock()?;
+        let credit = state.store.prepare_insert_credit(credit)?;
+
+        let entry = LogEntry::PutCredit {
+            credit: credit.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(credit)
+    }
+
+    fn update_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        let mut state = self.lock()?;
+        let credit = state.store.prepare_update_credit(credit)?;
+
+        let entry = LogEntry::PutCredit {
+            credit: credit.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(credit)
+    }
+
+    fn delete_credit(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut state = self.lock()?;
+        state.store.prepare_delete_credit(id)?;
+
+        self.commit(&mut state, LogEntry::DeleteCredit { id })
+    }
+}
+
+impl ReviewRepository for JournalMovieRepository {
+    fn list_reviews(&self) -> Result<Vec<Review>, RepositoryError> {
+        Ok(self.lock()?.store.reviews.values().cloned().collect())
+    }
+
+    fn movie_reviews(&self, movie_id: Uuid) -> Result<Vec<Review>, RepositoryError> {
+        Ok(self.lock()?.store.movie_reviews(movie_id))
+    }
+
+    fn get_review(&self, id: Uuid) -> Result<Review, RepositoryError> {
+        self.lock()?.store.get_review(id).cloned()
+    }
+
+    fn insert_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        let mut state = self.lock()?;
+        let review = state.store.prepare_insert_review(review)?;
+
+        let entry = LogEntry::PutReview {
+            review: review.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(review)
+    }
+
+    fn update_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        let mut state = self.lock()?;
+        let review = state.store.prepare_update_review(review)?;
+
+        let entry = LogEntry::PutReview {
+            review: review.clone(),
+        };
+        self.commit(&mut state, entry)?;
+        Ok(review)
+    }
+
+    fn delete_review(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut state = self.lock()?;
+        state.store.prepare_delete_review(id)?;
+
+        self.commit(&mut state, LogEntry::DeleteReview { id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a directory for a test's journal, removed once the test ends
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("journal-test-{}", Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn director() -> Director {
+        Director {
+            id: Uuid::new_v4(),
+            firstname: "Peter".to_string(),
+            lastname: "Jackson".to_string(),
+        }
+    }
+
+    fn movie(director_id: Uuid) -> Movie {
+        Movie {
+            id: Uuid::new_v4(),
+            isbn: "978-0-261-10235-4".to_string(),
+            title: "The Fellowship of the Ring".to_string(),
+            director_id,
+            metadata: MovieMetadata::default(),
+            version: initial_version(),
+        }
+    }
+
+    // Format a log line the way `commit` writes it
+    fn log_line(entry: &LogEntry) -> String {
+        let json = serde_json::to_string(entry).unwrap();
+        format!("{:08x} {}\n", crc32fast::hash(json.as_bytes()), json)
+    }
+
+    // Return the names of the side files holding cut off log tails
+    fn corrupt_files(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("journal.corrupt-"))
+            .collect()
+    }
+
+    #[test]
+    fn parse_log_line_accepts_written_lines() {
+        let line = log_line(&LogEntry::Delete { id: Uuid::nil() });
+        assert!(matches!(
+            parse_log_line(&line),
+            Some(LogEntry::Delete { id }) if id.is_nil()
+        ));
+    }
+
+    #[test]
+    fn parse_log_line_rejects_checksum_mismatch() {
+        let line = log_line(&LogEntry::Delete { id: Uuid::nil() });
+        let tampered = line.replace("00000000-", "00000001-");
+        assert!(parse_log_line(&tampered).is_none());
+    }
+
+    #[test]
+    fn parse_log_line_rejects_malformed_lines() {
+        assert!(parse_log_line("").is_none());
+        assert!(parse_log_line("not-hex {}\n").is_none());
+        let json = r#"{"op":"unknown"}"#;
+        let line = format!("{:08x} {}\n", crc32fast::hash(json.as_bytes()), json);
+        assert!(parse_log_line(&line).is_none());
+    }
+
+    #[test]
+    fn replay_log_cuts_off_torn_tail() {
+        let dir = TempDir::new();
+        let path = dir.0.join(LOG_FILE);
+        let director = director();
+        let valid = log_line(&LogEntry::PutDirector {
+            director: director.clone(),
+        });
+        let torn = log_line(&LogEntry::Delete { id: director.id });
+        let torn = &torn[..torn.len() / 2];
+        fs::write(&path, format!("{}{}", valid, torn)).unwrap();
+
+        let mut store = Store::default();
+        let (replayed, upgraded) = replay_log(&path, &mut store).unwrap();
+        assert_eq!(replayed, 1);
+        assert!(!upgraded);
+        assert!(store.directors.contains_key(&director.id));
+        assert_eq!(fs::read_to_string(&path).unwrap(), valid);
+        assert_eq!(corrupt_files(&dir.0).len(), 1);
+    }
+
+    #[test]
+    fn replay_log_stops_at_checksum_mismatch() {
+        let dir = TempDir::new();
+        let path = dir.0.join(LOG_FILE);
+        let first = director();
+        let second = director();
+        let valid = log_line(&LogEntry::PutDirector {
+            director: first.clone(),
+        });
+        let corrupt = log_line(&LogEntry::PutDirector {
+            director: second.clone(),
+        })
+        .replacen("Peter", "Petra", 1);
+        let after = log_line(&LogEntry::DeleteDirector { id: first.id });
+        fs::write(&path, format!("{}{}{}", valid, corrupt, after)).unwrap();
+
+        let mut store = Store::default();
+        let (replayed, _) = replay_log(&path, &mut store).unwrap();
+        assert_eq!(replayed, 1);
+        assert!(store.directors.contains_key(&first.id));
+        assert!(!store.directors.contains_key(&second.id));
+        assert_eq!(fs::read_to_string(&path).unwrap(), valid);
+
+        let corrupt_file = dir.0.join(&corrupt_files(&dir.0)[0]);
+        assert_eq!(
+            fs::read_to_string(corrupt_file).unwrap(),
+            format!("{}{}", corrupt, after)
+        );
+    }
+
+    #[test]
+    fn replay_log_treats_missing_log_as_empty() {
+        let dir = TempDir::new();
+        let mut store = Store::default();
+        let (replayed, _) = replay_log(&dir.0.join(LOG_FILE), &mut store).unwrap();
+        assert_eq!(replayed, 0);
+        assert!(corrupt_files(&dir.0).is_empty());
+    }
+
+    // Writing to /dev/full always fails and it cannot be truncated, so the
+    // failed write cannot be cut off the log
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_write_that_cannot_be_cut_off_refuses_later_writes() {
+        let dir = TempDir::new();
+        let log = OpenOptions::new().append(true).open("/dev/full").unwrap();
+        let journal = JournalMovieRepository {
+            dir: dir.0.clone(),
+            compact_every: 1000,
+            state: Mutex::new(JournalState {
+                store: Store::default(),
+                log,
+                pending: 0,
+            }),
+            damaged: AtomicBool::new(false),
+        };
+
+        let first = director();
+        assert!(journal.insert_director(first.clone()).is_err());
+        assert!(journal.get_director(first.id).is_err());
+        assert!(journal.is_poisoned());
+
+        let second = director();
+        let err = journal.insert_director(second).unwrap_err();
+        assert!(err.to_string().contains("damaged"));
+    }
+
+    #[test]
+    fn reopening_after_compaction_restores_snapshot_and_log() {
+        let dir = TempDir::new();
+        let director = director();
+        let first = movie(director.id);
+        let second = movie(director.id);
+        {
+            // The director and first movie fill the log, which is then
+            // compacted, so only the second movie is left in the log
+            let journal = JournalMovieRepository::open(&dir.0, 2).unwrap();
+            journal.insert_director(director.clone()).unwrap();
+            journal.insert(first.clone()).unwrap();
+            assert_eq!(fs::metadata(dir.0.join(LOG_FILE)).unwrap().len(), 0);
+            journal.insert(second.clone()).unwrap();
+        }
+        assert!(dir.0.join(SNAPSHOT_FILE).exists());
+
+        let journal = JournalMovieRepository::open(&dir.0, 2).unwrap();
+        assert_eq!(journal.get_director(director.id).unwrap().id, director.id);
+        assert_eq!(journal.get(first.id).unwrap().title, first.title);
+        assert_eq!(journal.get(second.id).unwrap().title, second.title);
+        assert_eq!(journal.list().unwrap().len(), 2);
+        journal.check_integrity().unwrap();
+    }
+}
@@ -0,0 +1,482 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error;
+use std::future::{ready, Future, Ready};
+use std::io::{self, IsTerminal};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+use uuid::Uuid;
+
+use crate::config::{Config, LogFormat};
+use crate::models::{Credit, Director, Movie, Person, Review};
+use crate::repository::{
+    CreditRepository, DirectorRepository, MovieRepository, PersonRepository, Repository,
+    RepositoryError, ReviewRepository,
+};
+
+// Header carrying the ID that ties the log lines of a request together
+const REQUEST_ID_HEADER: &str = "x-request-id";
+// Longest request ID accepted from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Define the handle through which the log filter can be changed while the
+// server runs
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    // The filter in effect, as it was given
+    filter: Mutex<String>,
+}
+
+impl LogControl {
+    // Return the filter in effect
+    pub fn filter(&self) -> String {
+        self.filter
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    // Replace the filter, such as `debug` or `info,rs_movies_crud=trace`
+    pub fn set_filter(&self, filter: &str) -> Result<(), String> {
+        let parsed = EnvFilter::try_new(filter).map_err(|err| err.to_string())?;
+        self.handle.reload(parsed).map_err(|err| err.to_string())?;
+        *self
+            .filter
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = filter.to_string();
+        Ok(())
+    }
+}
+
+// Install the global subscriber writing log lines in the configured format
+pub fn init(config: &Config) -> Result<LogControl, String> {
+    let filter = EnvFilter::try_new(&config.log_level).map_err(|err| err.to_string())?;
+    let (filter_layer, handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry().with(filter_layer);
+
+    let result = match config.log_format {
+        // Colour the lines only when someone is watching them
+        LogFormat::Pretty => registry
+            .with(fmt::layer().with_ansi(io::stdout().is_terminal()))
+            .try_init(),
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false),
+            )
+            .try_init(),
+    };
+    result.map_err(|err| err.to_string())?;
+
+    Ok(LogControl {
+        handle,
+        filter: Mutex::new(config.log_level.clone()),
+    })
+}
+
+// Return the ID a client gave its request, if it is fit to be logged
+fn client_request_id(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let fit = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.chars().all(|c| c.is_ascii_graphic());
+    fit.then(|| id.to_string())
+}
+
+// Define a middleware that runs every request in a span carrying its ID and
+// logs its status and latency once it completes
+pub struct RequestLogger;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestLogger
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestLoggerMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLoggerMiddleware { service }))
+    }
+}
+
+pub struct RequestLoggerMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLoggerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Keep the ID the client or a proxy in front of us gave the request, so
+        // that its log lines can be matched up with theirs
+        let request_id = client_request_id(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            uri = %req.uri(),
+            route = req.match_pattern().as_deref().unwrap_or(""),
+            client = req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default(),
+            principal = Empty,
+        );
+        let start = Instant::now();
+        let response = {
+            let _entered = span.enter();
+            self.service.call(req)
+        };
+
+        Box::pin(
+            async move {
+                let mut response = response.await?;
+                let status = response.status().as_u16();
+                let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+                match response.status().is_server_error() {
+                    true => tracing::error!(status, latency_ms, "request failed"),
+                    false => tracing::info!(status, latency_ms, "request completed"),
+                }
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    response
+                        .headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+// Define a storage decorator that runs every operation in a span and logs how
+// long it took
+pub struct TracedRepository {
+    inner: Arc<dyn Repository>,
+}
+
+impl TracedRepository {
+    pub fn new(inner: Arc<dyn Repository>) -> Self {
+        Self { inner }
+    }
+
+    // Run a storage operation in a span named after it
+    fn traced<T>(
+        &self,
+        operation: &'static str,
+        f: impl FnOnce() -> Result<T, RepositoryError>,
+    ) -> Result<T, RepositoryError> {
+        let span = tracing::debug_span!("storage", operation);
+        let _entered = span.enter();
+
+        let start = Instant::now();
+        let result = f();
+        let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+        // Failures are logged once they are turned into error responses
+        match &result {
+            Ok(_) => tracing::debug!(elapsed_ms, "storage operation completed"),
+            Err(err) => tracing::debug!(elapsed_ms, error = %err, "storage operation failed"),
+        }
+        result
+    }
+}
+
+impl MovieRepository for TracedRepository {
+    fn list(&self) -> Result<Vec<Movie>, RepositoryError> {
+        self.traced("list", || self.inner.list())
+    }
+
+    fn get(&self, id: Uuid) -> Result<Movie, RepositoryError> {
+        self.traced("get", || self.inner.get(id))
+    }
+
+    fn insert(&self, movie: Movie) -> Result<Movie, RepositoryError> {
+        self.traced("insert", || self.inner.insert(movie))
+    }
+
+    fn update(
+        &self,
+        movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, RepositoryError> {
+        self.traced("update", || self.inner.update(movie, expected_version))
+    }
+
+    fn delete(&self, id: Uuid, expected_version: Option<u64>) -> Result<(), RepositoryError> {
+        self.traced("delete", || self.inner.delete(id, expected_version))
+    }
+
+    fn check_integrity(&self) -> Result<(), RepositoryError> {
+        self.traced("check_integrity", || self.inner.check_integrity())
+    }
+
+    fn ping(&self) -> Result<(), RepositoryError> {
+        self.traced("ping", || self.inner.ping())
+    }
+
+    fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    fn flush(&self) -> Result<(), RepositoryError> {
+        self.traced("flush", || self.inner.flush())
+    }
+}
+
+impl DirectorRepository for TracedRepository {
+    fn list_directors(&self) -> Result<Vec<Director>, RepositoryError> {
+        self.traced("list_directors", || self.inner.list_directors())
+    }
+
+    fn get_director(&self, id: Uuid) -> Result<Director, RepositoryError> {
+        self.traced("get_director", || self.inner.get_director(id))
+    }
+
+    fn insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        self.traced("insert_director", || self.inner.insert_director(director))
+    }
+
+    fn update_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        self.traced("update_director", || self.inner.update_director(director))
+    }
+
+    fn delete_director(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.traced("delete_director", || self.inner.delete_director(id))
+    }
+
+    fn find_or_insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        self.traced("find_or_insert_director", || {
+            self.inner.find_or_insert_director(director)
+        })
+    }
+}
+
+impl PersonRepository for TracedRepository {
+    fn list_people(&self) -> Result<Vec<Person>, RepositoryError> {
+        self.traced("list_people", || self.inner.list_people())
+    }
+
+    fn get_person(&self, id: Uuid) -> Result<Person, RepositoryError> {
+        self.traced("get_person", || self.inner.get_person(id))
+    }
+
+    fn insert_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        self.traced("insert_person", || self.inner.insert_person(person))
+    }
+
+    fn update_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        self.traced("update_person", || self.inner.update_person(person))
+    }
+
+    fn delete_person(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.traced("delete_person", || self.inner.delete_person(id))
+    }
+}
+
+impl CreditRepository for TracedRepository {
+    fn movie_credits(&self, movie_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        self.traced("movie_credits", || self.inner.movie_credits(movie_id))
+    }
+
+    fn person_credits(&self, person_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        self.traced("person_credits", || self.inner.person_credits(person_id))
+    }
+
+    fn get_credit(&self, id: Uuid) -> Result<Credit, RepositoryError> {
+        self.traced("get_credit", || self.inner.get_credit(id))
+    }
+
+    fn insert_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        self.traced("insert_credit", || self.inner.insert_credit(credit))
+    }
+
+    fn update_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        self.traced("update_credit", || self.inner.update_credit(credit))
+    }
+
+    fn delete_credit(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.traced("delete_credit", || self.inner.delete_credit(id))
+    }
+}
+
+impl ReviewRepository for TracedRepository {
+    fn list_reviews(&self) -> Result<Vec<Review>, RepositoryError> {
+        self.traced("list_reviews", || self.inner.list_reviews())
+    }
+
+    fn movie_reviews(&self, movie_id: Uuid) -> Result<Vec<Review>, RepositoryError> {
+        self.traced("movie_reviews", || self.inner.movie_reviews(movie_id))
+    }
+
+    fn get_review(&self, id: Uuid) -> Result<Review, RepositoryError> {
+        self.traced("get_review", || self.inner.get_review(id))
+    }
+
+    fn insert_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        self.traced("insert_review", || self.inner.insert_review(review))
+    }
+
+    fn update_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        self.traced("update_review", || self.inner.update_review(review))
+    }
+
+    fn delete_review(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.traced("delete_review", || self.inner.delete_review(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::io::Write;
+
+    fn request_id(value: HeaderValue) -> Option<String> {
+        let req = TestRequest::default()
+            .insert_header((REQUEST_ID_HEADER, value))
+            .to_srv_request();
+        client_request_id(&req)
+    }
+
+    #[test]
+    fn client_request_ids_are_kept_when_fit_to_log() {
+        let id = "edge-7f3c2a9b";
+        assert_eq!(
+            request_id(HeaderValue::from_static(id)),
+            Some(id.to_string())
+        );
+        let longest = "a".repeat(MAX_REQUEST_ID_LEN);
+        assert_eq!(
+            request_id(HeaderValue::from_str(&longest).unwrap()),
+            Some(longest)
+        );
+    }
+
+    #[test]
+    fn client_request_ids_unfit_to_log_are_replaced() {
+        assert_eq!(request_id(HeaderValue::from_static("")), None);
+        let overlong = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        assert_eq!(request_id(HeaderValue::from_str(&overlong).unwrap()), None);
+        assert_eq!(request_id(HeaderValue::from_static("two words")), None);
+        assert_eq!(request_id(HeaderValue::from_static("tab\there")), None);
+        assert_eq!(
+            request_id(HeaderValue::from_bytes(b"caf\xc3\xa9").unwrap()),
+            None
+        );
+
+        let req = TestRequest::default().to_srv_request();
+        assert_eq!(client_request_id(&req), None);
+    }
+
+    // Define a log destination the tests can read back
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    #[actix_web::test]
+    async fn requests_are_logged_and_answered_with_their_id() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = init_service(
+            App::new()
+                .wrap(RequestLogger)
+                .route("/ok", web::get().to(HttpResponse::Ok))
+                .route("/fail", web::get().to(HttpResponse::InternalServerError)),
+        )
+        .await;
+
+        // The ID a client gave is echoed on the response and in the log
+        let req = TestRequest::get()
+            .uri("/ok")
+            .insert_header((REQUEST_ID_HEADER, "edge-7f3c2a9b"))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "edge-7f3c2a9b"
+        );
+
+        // An unfit ID is replaced by a generated one
+        let req = TestRequest::get()
+            .uri("/fail")
+            .insert_header((REQUEST_ID_HEADER, "two words"))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let generated = response
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(Uuid::parse_str(&generated).is_ok());
+
+        let lines = captured.lines();
+        let completed = lines
+            .iter()
+            .find(|line| line.contains("request completed"))
+            .unwrap();
+        assert!(completed.contains(" INFO "));
+        assert!(completed.contains("request_id=edge-7f3c2a9b"));
+        assert!(completed.contains("status=200"));
+
+        // Server errors are logged at error level
+        let failed = lines
+            .iter()
+            .find(|line| line.contains("request failed"))
+            .unwrap();
+        assert!(failed.contains("ERROR"));
+        assert!(failed.contains(&format!("request_id={}", generated)));
+        assert!(failed.contains("status=500"));
+    }
+}
@@ -1,177 +1,473 @@
 // Import the necessary crates and modules
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Mutex;
-use uuid::Uuid;
-
-// Define the Movie struct with the required fields
-#[derive(Debug, Serialize, Deserialize)]
-struct Movie {
-    id: Uuid,
-    isbn: String,
-    title: String,
-    director: Director,
-}
+use actix_web::{web, App, HttpServer, Route};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
-// Define the Director struct with the required fields
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Director {
-    firstname: String,
-    lastname: String,
-}
+mod auth;
+mod config;
+mod cors;
+mod error;
+mod handlers;
+mod health;
+mod journal;
+mod logging;
+mod metrics;
+mod models;
+mod pagination;
+mod patch;
+mod query;
+mod ratelimit;
+mod ratings;
+mod repository;
+mod search;
+mod seed;
+mod shutdown;
+mod sqlite;
+mod tls;
+mod validation;
 
-// Define a type alias for a shared state that holds a HashMap of movies
-type MovieData = web::Data<Mutex<HashMap<Uuid, Movie>>>;
+use actix_web::dev::Service;
+use auth::{Authenticator, Require, Role};
+use config::{Bind, Config, StorageKind};
+use cors::CorsSettings;
+use handlers::{
+    create_director, create_movie, create_movie_credit, create_movie_review, create_person,
+    delete_director_by_id, delete_movie_by_id, delete_movie_credit, delete_movie_review,
+    delete_person_by_id, get_director_by_id, get_director_movies, get_directors, get_health,
+    get_liveness, get_log_level, get_metrics, get_movie_by_id, get_movie_credits,
+    get_movie_review_by_id, get_movie_reviews, get_movies, get_people, get_person_by_id,
+    get_person_credits, get_readiness, not_found, patch_movie_by_id, search_movies, set_log_level,
+    update_director_by_id, update_movie_by_id, update_movie_credit, update_movie_review,
+    update_person_by_id, MovieData,
+};
+use health::Health;
+use journal::JournalMovieRepository;
+use logging::{RequestLogger, TracedRepository};
+use metrics::RecordMetrics;
+use ratelimit::{RateLimit, RateLimiter};
+use repository::{InMemoryMovieRepository, Repository};
+use search::IndexedMovieRepository;
+use shutdown::{InFlight, Signals};
+use sqlite::SqliteMovieRepository;
+use tls::Tls;
 
-fn print_request(method: &str, path: &str) {
-    println!("{} request to {}", method, path);
+// Build the route serving the metrics, which Prometheus may scrape without
+// credentials only if that is allowed
+fn metrics_route(public: bool) -> Route {
+    match public {
+        true => web::get().to(get_metrics),
+        false => web::get().to(get_metrics).wrap(Require::new(Role::Reader)),
+    }
 }
-// Define a handler function for getting all movies
-async fn get_movies(data: MovieData) -> impl Responder {
-    print_request("GET", "/movies");
 
-    // Lock the data and get a reference to the HashMap
-    let movies = data.lock().unwrap();
+// Define the main function that runs the server and registers the routes
+#[actix_web::main]
+async fn main() -> std::io::Result<ExitCode> {
+    // Read the settings from the configuration file, the environment and the
+    // command line
+    let config = Config::load();
 
-    // Convert the HashMap into a Vec of values
-    let movies: Vec<&Movie> = movies.values().collect();
+    // Write log lines in the configured format, keeping a handle to change the
+    // filter while the server runs
+    let log_control = web::Data::new(logging::init(&config).map_err(std::io::Error::other)?);
+    tracing::info!("Effective configuration:\n{}", config.render());
 
-    // Return a JSON response with the movies
-    HttpResponse::Ok().json(movies)
-}
+    // Load the credentials clients can authenticate with
+    let authenticator = Authenticator::from_config(&config).map_err(std::io::Error::other)?;
+    if authenticator.is_empty() {
+        match config.public_reads {
+            true => tracing::warn!("No credentials configured, only reads will be allowed"),
+            false => tracing::warn!(
+                "No credentials configured and reads are not public, every request will be refused"
+            ),
+        }
+    }
+    let authenticator = web::Data::new(authenticator);
+
+    // Share the rate limits between the workers, so they apply to the server
+    // as a whole
+    let limiter = Arc::new(RateLimiter::from_config(&config));
 
-// Define a handler function for getting a movie by ID
-async fn get_movie_by_id(data: MovieData, id: web::Path<Uuid>) -> impl Responder {
-    print_request("GET", &format!("/movies/{}", id));
+    // Check which origins browsers may call the API from
+    let cors = CorsSettings::from_config(&config).map_err(std::io::Error::other)?;
 
-    // Lock the data and get a reference to the HashMap
-    let movies = data.lock().unwrap();
+    // Load the certificate to serve HTTPS with, if one is configured
+    let tls = Tls::from_config(&config).map_err(std::io::Error::other)?;
 
-    // Try to find the movie by ID in the HashMap
-    match movies.get(&id) {
-        // If found, return a JSON response with the movie
-        Some(movie) => HttpResponse::Ok().json(movie),
-        // If not found, return a 404 response
-        None => HttpResponse::NotFound().finish(),
+    // Create the configured storage backend for the movies and directors
+    let repository: Arc<dyn Repository> = match config.storage {
+        StorageKind::Memory => Arc::new(InMemoryMovieRepository::new()),
+        StorageKind::Sqlite => {
+            tracing::info!("Opening SQLite database {}", config.sqlite_path.display());
+            Arc::new(
+                SqliteMovieRepository::open(&config.sqlite_path).map_err(std::io::Error::other)?,
+            )
+        }
+        StorageKind::Journal => {
+            tracing::info!("Opening journal in {}", config.journal_dir.display());
+            Arc::new(
+                JournalMovieRepository::open(
+                    &config.journal_dir,
+                    config.journal_compact_every as usize,
+                )
+                .map_err(std::io::Error::other)?,
+            )
+        }
+    };
+
+    // Refuse to start on a store whose records are not keyed by their own IDs or
+    // whose movies refer to missing directors
+    repository
+        .check_integrity()
+        .map_err(std::io::Error::other)?;
+
+    // Insert the movies from the fixture file, if one was given
+    if let Some(path) = &config.seed {
+        let movies = seed::load(path).map_err(std::io::Error::other)?;
+        let inserted = seed::seed(repository.as_ref(), movies).map_err(std::io::Error::other)?;
+        tracing::info!("Seeded {} movies from {}", inserted, path.display());
     }
-}
 
-// Define a handler function for creating a new movie
-async fn create_movie(data: MovieData, movie: web::Json<Movie>) -> impl Responder {
-    print_request("POST", "/movies");
+    // Keep a full-text search index and the review totals in step with the
+    // storage backend
+    let repository = IndexedMovieRepository::new(repository).map_err(std::io::Error::other)?;
+    let index = web::Data::from(repository.index());
+    let ratings = web::Data::from(repository.ratings());
 
-    // Lock the data and get a mutable reference to the HashMap
-    let mut movies = data.lock().unwrap();
+    // Trace every storage operation, and wrap the storage backend in a
+    // web::Data for shared state
+    let repository: Arc<dyn Repository> = Arc::new(TracedRepository::new(Arc::new(repository)));
+    let data: MovieData = web::Data::from(repository.clone());
 
-    // Generate a random ID for the new movie
-    let id = Uuid::new_v4();
+    // The storage backend is open, checked and seeded, so requests can be
+    // served
+    let health = Arc::new(Health::default());
+    health.set_ready(true);
+    let health_data = web::Data::from(health.clone());
 
-    // Create a new movie with the given fields and the generated ID
-    let movie = Movie {
-        id,
-        isbn: movie.isbn.clone(),
-        title: movie.title.clone(),
-        director: movie.director.clone(),
-    };
+    // Count the requests in progress, which shutdown waits for
+    let in_flight = InFlight::default();
+    let tracker = in_flight.clone();
 
-    // Insert the new movie into the HashMap with the ID as key
-    movies.insert(id, movie);
+    let public_metrics = config.public_metrics;
+    let json_limit = config.json_limit;
+    let payload_limit = config.payload_limit;
 
-    // Return a 201 response with the created movie
-    HttpResponse::Created().json(movies.get(&id).unwrap())
-}
+    // Register the routes with the shared state
+    let mut server = HttpServer::new(move || {
+        let tracker = tracker.clone();
+        App::new()
+            .app_data(data.clone())
+            .app_data(index.clone())
+            .app_data(ratings.clone())
+            .app_data(authenticator.clone())
+            .app_data(log_control.clone())
+            .app_data(health_data.clone())
+            // Render extractor failures as problem details too
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(json_limit)
+                    .error_handler(error::json_error),
+            )
+            .app_data(web::PayloadConfig::new(payload_limit))
+            .app_data(web::PathConfig::default().error_handler(error::path_error))
+            .app_data(web::QueryConfig::default().error_handler(error::query_error))
+            // Refuse requests from clients over their rate limit
+            .wrap(RateLimit(limiter.clone()))
+            // Name the request path in every problem details response
+            .wrap_fn(|req, srv| {
+                let path = req.path().to_string();
+                let res = srv.call(req);
+                async move { Ok(error::add_instance(&path, res.await?)) }
+            })
+            // Answer preflight requests and let browsers on the allowed origins
+            // read the responses, including those refused above
+            .wrap(cors::middleware(cors.as_ref()))
+            // Log every request, including those refused by the middleware above
+            .wrap(RequestLogger)
+            // Count and time every request, including those refused above
+            .wrap(RecordMetrics)
+            // Track every request until it completes, so that shutdown can
+            // wait for it
+            .wrap_fn(move |req, srv| {
+                let tracked = tracker.start();
+                let res = srv.call(req);
+                async move {
+                    let res = res.await;
+                    drop(tracked);
+                    res
+                }
+            })
+            // The health endpoints are for orchestrators and load balancers,
+            // which send no credentials
+            .route("/healthz", web::get().to(get_health))
+            .route("/readyz", web::get().to(get_readiness))
+            .route("/livez", web::get().to(get_liveness))
+            // Reading needs the reader role, and so does writing a review, though
+            // only with credentials even when reads are public. Changing the
+            // catalogue needs the editor role and deleting whole records the
+            // admin role.
+            .route(
+                "/movies",
+                web::get().to(get_movies).wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/movies/search",
+                web::get()
+                    .to(search_movies)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/movies/{id}",
+                web::get()
+                    .to(get_movie_by_id)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/movies",
+                web::post()
+                    .to(create_movie)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/movies/{id}",
+                web::put()
+                    .to(update_movie_by_id)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/movies/{id}",
+                web::patch()
+                    .to(patch_movie_by_id)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/movies/{id}",
+                web::delete()
+                    .to(delete_movie_by_id)
+                    .wrap(Require::new(Role::Admin)),
+            )
+            .route(
+                "/movies/{id}/credits",
+                web::get()
+                    .to(get_movie_credits)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/movies/{id}/credits",
+                web::post()
+                    .to(create_movie_credit)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/movies/{id}/credits/{credit_id}",
+                web::put()
+                    .to(update_movie_credit)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/movies/{id}/credits/{credit_id}",
+                web::delete()
+                    .to(delete_movie_credit)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/movies/{id}/reviews",
+                web::get()
+                    .to(get_movie_reviews)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/movies/{id}/reviews",
+                web::post()
+                    .to(create_movie_review)
+                    .wrap(Require::authenticated(Role::Reader)),
+            )
+            .route(
+                "/movies/{id}/reviews/{review_id}",
+                web::get()
+                    .to(get_movie_review_by_id)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/movies/{id}/reviews/{review_id}",
+                web::put()
+                    .to(update_movie_review)
+                    .wrap(Require::authenticated(Role::Reader)),
+            )
+            .route(
+                "/movies/{id}/reviews/{review_id}",
+                web::delete()
+                    .to(delete_movie_review)
+                    .wrap(Require::authenticated(Role::Reader)),
+            )
+            .route(
+                "/directors",
+                web::get()
+                    .to(get_directors)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/directors",
+                web::post()
+                    .to(create_director)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/directors/{id}",
+                web::get()
+                    .to(get_director_by_id)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/directors/{id}",
+                web::put()
+                    .to(update_director_by_id)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/directors/{id}",
+                web::delete()
+                    .to(delete_director_by_id)
+                    .wrap(Require::new(Role::Admin)),
+            )
+            .route(
+                "/directors/{id}/movies",
+                web::get()
+                    .to(get_director_movies)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/people",
+                web::get().to(get_people).wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/people",
+                web::post()
+                    .to(create_person)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/people/{id}",
+                web::get()
+                    .to(get_person_by_id)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route(
+                "/people/{id}",
+                web::put()
+                    .to(update_person_by_id)
+                    .wrap(Require::new(Role::Editor)),
+            )
+            .route(
+                "/people/{id}",
+                web::delete()
+                    .to(delete_person_by_id)
+                    .wrap(Require::new(Role::Admin)),
+            )
+            .route(
+                "/people/{id}/credits",
+                web::get()
+                    .to(get_person_credits)
+                    .wrap(Require::new(Role::Reader)),
+            )
+            .route("/metrics", metrics_route(public_metrics))
+            .route(
+                "/admin/log-level",
+                web::get().to(get_log_level).wrap(Require::new(Role::Admin)),
+            )
+            .route(
+                "/admin/log-level",
+                web::put().to(set_log_level).wrap(Require::new(Role::Admin)),
+            )
+            .default_service(web::to(not_found))
+    });
+    if let Some(workers) = config.workers {
+        server = server.workers(workers as usize);
+    }
+    // Handle the signals here rather than in the server, so that the store
+    // can be flushed once it has stopped
+    server = server.disable_signals();
 
-// Define a handler function for updating a movie by ID
-async fn update_movie_by_id(
-    data: MovieData,
-    id: web::Path<Uuid>,
-    movie: web::Json<Movie>,
-) -> impl Responder {
-    print_request("PUT", &format!("/movies/{}", id));
-
-    // Lock the data and get a mutable reference to the HashMap
-    let mut movies = data.lock().unwrap();
-
-    // Try to find the movie by ID in the HashMap
-    match movies.get_mut(&id) {
-        // If found, update its fields with the given values
-        Some(m) => {
-            m.isbn = movie.isbn.clone();
-            m.title = movie.title.clone();
-            m.director = movie.director.clone();
-            // Return a 200 response with the updated movie
-            HttpResponse::Ok().json(m)
+    // Listen on every configured address, failing if any cannot be bound.
+    // Unix domain sockets can only be reached locally, so they stay plain HTTP.
+    for bind in &config.bind {
+        server = match (bind, &tls) {
+            (Bind::Tcp(addr), Some(tls)) => server.bind_rustls(addr, tls.server_config.clone()),
+            (Bind::Tcp(addr), None) => server.bind(addr),
+            (Bind::Unix(path), _) => server.bind_uds(path),
+        }
+        .map_err(|err| std::io::Error::new(err.kind(), format!("cannot bind {}: {}", bind, err)))?;
+        match (bind, &tls) {
+            (Bind::Tcp(_), Some(_)) => tracing::info!("Listening on {} with TLS", bind),
+            _ => tracing::info!("Listening on {}", bind),
         }
-        // If not found, return a 404 response
-        None => HttpResponse::NotFound().finish(),
     }
-}
 
-// Define a handler function for deleting a movie by ID
-async fn delete_movie_by_id(data: MovieData, id: web::Path<Uuid>) -> impl Responder {
-    print_request("DELETE", &format!("/movies/{}", id));
+    // Pick up a renewed certificate without a restart
+    if let Some(tls) = &tls {
+        let interval = Duration::from_secs(config.tls_reload_interval);
+        actix_web::rt::spawn(tls.cert.clone().watch(interval));
+    }
+
+    let server = server.run();
+    let handle = server.handle();
+    let mut signals = Signals::new()?;
+    let running = actix_web::rt::spawn(server);
 
-    // Lock the data and get a mutable reference to the HashMap
-    let mut movies = data.lock().unwrap();
+    // Serve requests until asked to stop
+    let name = signals.recv().await;
+    tracing::info!("Received {}, finishing the requests in progress", name);
+    // Fail the readiness check, so that no new requests are sent our way
+    health.set_ready(false);
+    let timeout = Duration::from_secs(config.shutdown_timeout);
+    let drained = shutdown::drain(handle, signals, &in_flight, timeout).await;
+    running.await.map_err(std::io::Error::other)??;
 
-    // Try to remove the movie by ID from the HashMap
-    match movies.remove(&id) {
-        // If found and removed, return a 204 response
-        Some(_) => HttpResponse::NoContent().finish(),
-        // If not found, return a 404 response
-        None => HttpResponse::NotFound().finish(),
+    // Only exit once every write is durable
+    let flushed = match repository.flush() {
+        Ok(()) => true,
+        Err(err) => {
+            tracing::error!(error = %err, "cannot flush the store, recent writes may be lost");
+            false
+        }
+    };
+    match (flushed, drained) {
+        (true, true) => tracing::info!("Shut down cleanly"),
+        (true, false) => tracing::warn!("Shut down with requests still in progress"),
+        (false, _) => {}
     }
+    Ok(ExitCode::from(shutdown::exit_status(drained, flushed)))
 }
 
-// Define the main function that runs the server and registers the routes
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // Create an initial HashMap of movies for testing purposes
-    let mut movies = HashMap::new();
-
-    // Insert some sample movies into the HashMap
-    movies.insert(
-        Uuid::new_v4(),
-        Movie {
-            id: Uuid::new_v4(),
-            isbn: "978-3-16-148410-0".to_string(),
-            title: "The Lord of the Rings".to_string(),
-            director: Director {
-                firstname: "Peter".to_string(),
-                lastname: "Jackson".to_string(),
-            },
-        },
-    );
-    movies.insert(
-        Uuid::new_v4(),
-        Movie {
-            id: Uuid::new_v4(),
-            isbn: "978-0-06-055812-8".to_string(),
-            title: "The Hitchhiker's Guide to the Galaxy".to_string(),
-            director: Director {
-                firstname: "Garth".to_string(),
-                lastname: "Jennings".to_string(),
-            },
-        },
-    );
-
-    // Wrap the HashMap in a Mutex and a web::Data for shared state
-    let data = web::Data::new(Mutex::new(movies));
-
-    println!("Server starting on port 8080...");
-
-    // Run the server and register the routes with the shared state
-    HttpServer::new(move || {
-        App::new()
-            .app_data(data.clone())
-            .route("/movies", web::get().to(get_movies))
-            .route("/movies/{id}", web::get().to(get_movie_by_id))
-            .route("/movies", web::post().to(create_movie))
-            .route("/movies/{id}", web::put().to(update_movie_by_id))
-            .route("/movies/{id}", web::delete().to(delete_movie_by_id))
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use clap::Parser;
+
+    // Scrape the metrics without credentials
+    async fn scrape(public: bool) -> StatusCode {
+        let repository: Arc<dyn Repository> = Arc::new(InMemoryMovieRepository::new());
+        let repository = IndexedMovieRepository::new(repository).unwrap();
+        let config = Config::parse_from(["movies"]);
+        let authenticator = Authenticator::from_config(&config).unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::from(repository.index()))
+                .app_data(web::Data::new(authenticator))
+                .route("/metrics", metrics_route(public)),
+        )
+        .await;
+        let req = TestRequest::get().uri("/metrics").to_request();
+        call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn metrics_need_credentials_unless_public() {
+        assert_eq!(scrape(false).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(scrape(true).await, StatusCode::OK);
+    }
 }
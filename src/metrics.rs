@@ -0,0 +1,247 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::{LockResult, Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+
+// Route label of requests that match no route, so that clients probing random
+// paths cannot create a series each
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Define the metrics the server exposes to Prometheus
+struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    in_flight: IntGauge,
+    movies: IntGauge,
+    lock_wait: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let in_flight = IntGauge::new(
+            "http_requests_in_flight",
+            "Number of HTTP requests being handled",
+        )?;
+        let movies = IntGauge::new("movies_stored", "Number of movies in the store")?;
+        // Waits range from a few microseconds for an idle lock to whole
+        // seconds behind a slow disk write
+        let lock_wait = HistogramVec::new(
+            HistogramOpts::new(
+                "storage_lock_wait_seconds",
+                "Time spent waiting to acquire the storage locks",
+            )
+            .buckets(exponential_buckets(0.00001, 4.0, 10)?),
+            &["lock"],
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+        registry.register(Box::new(movies.clone()))?;
+        registry.register(Box::new(lock_wait.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            latency,
+            in_flight,
+            movies,
+            lock_wait,
+        })
+    }
+}
+
+// Return the metrics, shared by the workers and the storage backends
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    // The names and labels are fixed, so registering them cannot fail
+    METRICS.get_or_init(|| Metrics::new().expect("metrics are valid"))
+}
+
+// Render every metric in the Prometheus text format, given the number of
+// stored movies
+pub fn render(movies: usize) -> Result<String, String> {
+    let metrics = metrics();
+    metrics.movies.set(movies as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .map_err(|err| err.to_string())?;
+    String::from_utf8(buffer).map_err(|err| err.to_string())
+}
+
+// Return the content type of the rendered metrics
+pub fn content_type() -> &'static str {
+    prometheus::TEXT_FORMAT
+}
+
+// Define a request being handled, which stops counting as in flight when it
+// completes or its connection is dropped
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        metrics().in_flight.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        metrics().in_flight.dec();
+    }
+}
+
+// Acquire a storage lock, recording how long it took under the given name
+pub fn lock<'a, T>(name: &str, mutex: &'a Mutex<T>) -> LockResult<MutexGuard<'a, T>> {
+    let start = Instant::now();
+    let guard = mutex.lock();
+    metrics()
+        .lock_wait
+        .with_label_values(&[name])
+        .observe(start.elapsed().as_secs_f64());
+    guard
+}
+
+// Define a middleware that counts every request and times it, labelled by the
+// route it matched rather than its path
+pub struct RecordMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RecordMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RecordMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordMetricsMiddleware { service }))
+    }
+}
+
+pub struct RecordMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let method = req.method().to_string();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        let start = Instant::now();
+        let in_flight = InFlight::start();
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            let response = response.await;
+            drop(in_flight);
+            let response = response?;
+            let status = response.status().as_u16().to_string();
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+
+            let metrics = metrics();
+            metrics.requests.with_label_values(&labels).inc();
+            metrics
+                .latency
+                .with_label_values(&labels)
+                .observe(start.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    fn requests(method: &str, route: &str, status: &str) -> u64 {
+        metrics()
+            .requests
+            .with_label_values(&[method, route, status])
+            .get()
+    }
+
+    fn requests_in_flight() -> i64 {
+        metrics().in_flight.get()
+    }
+
+    // The requests in flight are counted across the whole process, so every
+    // request through the middleware is made from this one test
+    #[actix_web::test]
+    async fn requests_are_counted_by_route_and_while_in_flight() {
+        let app = init_service(
+            App::new()
+                .wrap(RecordMetrics)
+                .route("/metrics-test/{id}", web::get().to(HttpResponse::Ok))
+                .default_service(web::to(HttpResponse::NotFound)),
+        )
+        .await;
+
+        // Requests are labelled by the route they matched rather than their path
+        let before = requests("GET", "/metrics-test/{id}", "200");
+        for id in ["1", "2"] {
+            let req = TestRequest::get()
+                .uri(&format!("/metrics-test/{}", id))
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+        }
+        assert_eq!(requests("GET", "/metrics-test/{id}", "200"), before + 2);
+
+        // Paths that match no route share a single label
+        let before = requests("DELETE", UNMATCHED_ROUTE, "404");
+        let req = TestRequest::delete().uri("/probe/a1b2c3").to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(requests("DELETE", UNMATCHED_ROUTE, "404"), before + 1);
+
+        // A request counts as in flight until it completes or is dropped
+        let before = requests_in_flight();
+        let req = TestRequest::get().uri("/metrics-test/3").to_request();
+        let response = app.call(req);
+        assert_eq!(requests_in_flight(), before + 1);
+        drop(response);
+        assert_eq!(requests_in_flight(), before);
+    }
+}
@@ -0,0 +1,317 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::ratings::RatingSummary;
+
+// Define the Movie struct with the required fields
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Movie {
+    pub id: Uuid,
+    pub isbn: String,
+    pub title: String,
+    // ID of the director stored in the directors collection
+    pub director_id: Uuid,
+    #[serde(flatten)]
+    pub metadata: MovieMetadata,
+    // Incremented on every update, used for ETags and optimistic concurrency
+    #[serde(default = "initial_version")]
+    pub version: u64,
+}
+
+// Define the descriptive fields of a movie. All of them are optional, so that
+// movies stored and requests written before they existed remain valid.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MovieMetadata {
+    // First release date, as an ISO 8601 calendar date such as `2001-12-19`
+    pub release_date: Option<String>,
+    pub runtime_minutes: Option<u32>,
+    // Genres from the controlled vocabulary in `validation::GENRES`
+    pub genres: Vec<String>,
+    // ISO 639-1 code of the original language, such as `en`
+    pub original_language: Option<String>,
+    // ISO 3166-1 alpha-2 code of the country of origin, such as `NZ`
+    pub country: Option<String>,
+    // Age certification, such as `PG-13`
+    pub certification: Option<String>,
+    pub synopsis: Option<String>,
+}
+
+// Return the version of a newly created movie
+pub fn initial_version() -> u64 {
+    1
+}
+
+// Define the Director struct with the required fields
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Director {
+    pub id: Uuid,
+    pub firstname: String,
+    pub lastname: String,
+}
+
+// Define the representation of a movie returned to clients, with its director
+// embedded when it was asked for and the aggregate of its reviews
+#[derive(Debug, Clone, Serialize)]
+pub struct MovieView {
+    #[serde(flatten)]
+    pub movie: Movie,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub director: Option<Director>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<RatingSummary>,
+}
+
+// Define the request body for creating or replacing a director, also accepted
+// inline in movie request bodies
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewDirector {
+    pub firstname: String,
+    pub lastname: String,
+}
+
+impl NewDirector {
+    // Build the director to store under the given ID
+    pub fn into_director(self, id: Uuid) -> Director {
+        Director {
+            id,
+            firstname: self.firstname,
+            lastname: self.lastname,
+        }
+    }
+}
+
+// Define the request body for creating a movie; the ID is assigned by the server.
+// The director is given either by ID or inline by name, in which case an
+// existing director with the same name is reused.
+#[derive(Debug, Deserialize)]
+pub struct NewMovie {
+    // Present only so that a client-supplied ID can be rejected explicitly
+    #[serde(default)]
+    pub id: Option<Uuid>,
+    pub isbn: String,
+    pub title: String,
+    #[serde(default)]
+    pub director_id: Option<Uuid>,
+    #[serde(default)]
+    pub director: Option<NewDirector>,
+    #[serde(flatten)]
+    pub metadata: MovieMetadata,
+}
+
+impl NewMovie {
+    // Build the movie to store under the given ID
+    pub fn into_movie(self, id: Uuid, director_id: Uuid) -> Movie {
+        Movie {
+            id,
+            isbn: self.isbn,
+            title: self.title,
+            director_id,
+            metadata: self.metadata,
+            version: initial_version(),
+        }
+    }
+}
+
+// Define the request body for replacing a movie; the ID comes from the path and
+// may only be repeated in the body if it matches. The director is given the
+// same way as when creating a movie.
+#[derive(Debug, Deserialize)]
+pub struct MovieUpdate {
+    #[serde(default)]
+    pub id: Option<Uuid>,
+    pub isbn: String,
+    pub title: String,
+    #[serde(default)]
+    pub director_id: Option<Uuid>,
+    #[serde(default)]
+    pub director: Option<NewDirector>,
+    #[serde(flatten)]
+    pub metadata: MovieMetadata,
+}
+
+impl MovieUpdate {
+    // Build the movie to store under the given ID; the storage backend assigns
+    // its next version
+    pub fn into_movie(self, id: Uuid, director_id: Uuid) -> Movie {
+        Movie {
+            id,
+            isbn: self.isbn,
+            title: self.title,
+            director_id,
+            metadata: self.metadata,
+            version: initial_version(),
+        }
+    }
+}
+
+// Define the Person struct for cast and crew members
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Person {
+    pub id: Uuid,
+    pub firstname: String,
+    pub lastname: String,
+}
+
+// Define the request body for creating or replacing a person
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewPerson {
+    pub firstname: String,
+    pub lastname: String,
+}
+
+impl NewPerson {
+    // Build the person to store under the given ID
+    pub fn into_person(self, id: Uuid) -> Person {
+        Person {
+            id,
+            firstname: self.firstname,
+            lastname: self.lastname,
+        }
+    }
+}
+
+// Define the roles a person can be credited in, in the order credits are listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Director,
+    Writer,
+    Producer,
+    Actor,
+    Composer,
+}
+
+impl Role {
+    const ALL: [Role; 5] = [
+        Role::Director,
+        Role::Writer,
+        Role::Producer,
+        Role::Actor,
+        Role::Composer,
+    ];
+
+    // Look up a role by its name
+    pub fn parse(name: &str) -> Option<Self> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
+
+    // Return the name of the role as used in request and response bodies
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Director => "director",
+            Role::Writer => "writer",
+            Role::Producer => "producer",
+            Role::Actor => "actor",
+            Role::Composer => "composer",
+        }
+    }
+}
+
+// Define the Credit struct linking a person to a movie in a role. Credits with
+// the director role name further directors besides the movie's own director.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Credit {
+    pub id: Uuid,
+    pub movie_id: Uuid,
+    pub person_id: Uuid,
+    pub role: Role,
+    // Name of the character played, only for actors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub character: Option<String>,
+    // Position of the credit among those of the same role, starting at 1
+    pub billing: u32,
+}
+
+// Define the representation of a credit returned to clients, with the person
+// embedded in a movie's credits and the movie embedded in a filmography
+#[derive(Debug, Clone, Serialize)]
+pub struct CreditView {
+    #[serde(flatten)]
+    pub credit: Credit,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub person: Option<Person>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub movie: Option<Movie>,
+}
+
+// Define the request body for adding or replacing a credit of a movie; the
+// movie comes from the path. Without a billing position, a new credit is
+// billed after the others of its role and a replaced one keeps its position.
+#[derive(Debug, Deserialize)]
+pub struct NewCredit {
+    pub person_id: Uuid,
+    pub role: Role,
+    #[serde(default)]
+    pub character: Option<String>,
+    #[serde(default)]
+    pub billing: Option<u32>,
+}
+
+impl NewCredit {
+    // Build the credit to store under the given ID
+    pub fn into_credit(self, id: Uuid, movie_id: Uuid, billing: u32) -> Credit {
+        Credit {
+            id,
+            movie_id,
+            person_id: self.person_id,
+            role: self.role,
+            character: self.character,
+            billing,
+        }
+    }
+}
+
+// Define the Review struct for a user's score and opinion of a movie
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Review {
+    pub id: Uuid,
+    pub movie_id: Uuid,
+    // Name of the client who posted the review, as it authenticated, which
+    // is the only one besides editors who may change or remove it
+    pub author: String,
+    // Score from 1 to 10
+    pub score: u8,
+    pub text: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+// Define the representation of a review returned to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewView {
+    pub id: Uuid,
+    pub movie_id: Uuid,
+    pub author: String,
+    pub score: u8,
+    pub text: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+impl From<Review> for ReviewView {
+    fn from(review: Review) -> Self {
+        ReviewView {
+            id: review.id,
+            movie_id: review.movie_id,
+            author: review.author,
+            score: review.score,
+            text: review.text,
+            created_at: review.created_at,
+            updated_at: review.updated_at,
+        }
+    }
+}
+
+// Define the request body for posting or replacing a review; the movie comes
+// from the path and the author is the client posting it
+#[derive(Debug, Deserialize)]
+pub struct NewReview {
+    pub score: u8,
+    pub text: String,
+}
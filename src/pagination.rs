@@ -0,0 +1,436 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+
+// Number of items returned when paginating without an explicit limit
+const DEFAULT_LIMIT: usize = 100;
+// Largest page size a client may ask for
+const MAX_LIMIT: usize = 1000;
+
+// Define the pagination query parameters accepted by collection endpoints.
+// Passing `offset` selects offset pagination, passing only `limit` or a
+// `cursor` selects cursor pagination, and passing none returns everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+// Define the errors caused by invalid pagination parameters
+#[derive(Debug)]
+pub enum PageError {
+    InvalidLimit(usize),
+    OffsetWithCursor,
+    InvalidCursor,
+}
+
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageError::InvalidLimit(limit) => write!(
+                f,
+                "limit must be between 1 and {}, got {}",
+                MAX_LIMIT, limit
+            ),
+            PageError::OffsetWithCursor => write!(f, "offset and cursor cannot be combined"),
+            PageError::InvalidCursor => write!(f, "cursor is malformed"),
+        }
+    }
+}
+
+impl std::error::Error for PageError {}
+
+// Define how the items of a collection are ordered, so that a cursor can record
+// the position of an item and find it again on a later request
+pub trait CursorOrder<T> {
+    // Sort values of an item, stored inside cursors
+    type Key: Serialize + DeserializeOwned;
+
+    // Return the sort values of an item
+    fn key(&self, item: &T) -> Self::Key;
+
+    // Compare an item against the sort values stored in a cursor, returning None
+    // if the cursor was created for a different ordering
+    fn compare(&self, item: &T, key: &Self::Key) -> Option<Ordering>;
+}
+
+// Define the position a cursor points at, relative to the key of an item
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Cursor<K> {
+    // Items sorted strictly after the key
+    After(K),
+    // Items sorted strictly before the key
+    Before(K),
+}
+
+impl<K: Serialize + DeserializeOwned> Cursor<K> {
+    // Encode the cursor as an opaque URL-safe token
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor keys serialize to JSON");
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    // Decode a token produced by `encode`
+    fn decode(token: &str) -> Result<Self, PageError> {
+        let json = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| PageError::InvalidCursor)?;
+        serde_json::from_slice(&json).map_err(|_| PageError::InvalidCursor)
+    }
+}
+
+// Define the query parameters that lead to a neighbouring page
+#[derive(Debug)]
+pub enum PageLink {
+    Offset {
+        limit: usize,
+        offset: usize,
+    },
+    // Without a cursor the link points at the first page
+    Cursor {
+        limit: usize,
+        cursor: Option<String>,
+    },
+}
+
+impl PageLink {
+    // Render the link as query parameters
+    fn query(&self) -> String {
+        match self {
+            PageLink::Offset { limit, offset } => format!("limit={}&offset={}", limit, offset),
+            PageLink::Cursor {
+                limit,
+                cursor: Some(cursor),
+            } => format!("limit={}&cursor={}", limit, cursor),
+            PageLink::Cursor {
+                limit,
+                cursor: None,
+            } => format!("limit={}", limit),
+        }
+    }
+}
+
+// Define one page of a sorted collection
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub first: Option<PageLink>,
+    pub prev: Option<PageLink>,
+    pub next: Option<PageLink>,
+}
+
+impl<T> Page<T> {
+    // Build the value of a `Link` header for the page, keeping every query
+    // parameter of the original request other than the pagination ones
+    pub fn link_header(&self, path: &str, query: &str) -> Option<String> {
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                !pair.is_empty() && !matches!(name, "limit" | "offset" | "cursor")
+            })
+            .collect();
+
+        let links: Vec<String> = [
+            ("first", &self.first),
+            ("prev", &self.prev),
+            ("next", &self.next),
+        ]
+        .into_iter()
+        .filter_map(|(rel, link)| {
+            let link = link.as_ref()?;
+            let mut params = kept.clone();
+            let page_query = link.query();
+            params.push(&page_query);
+            Some(format!("<{}?{}>; rel=\"{}\"", path, params.join("&"), rel))
+        })
+        .collect();
+
+        match links.is_empty() {
+            true => None,
+            false => Some(links.join(", ")),
+        }
+    }
+}
+
+// Select the requested page of items, which must already be sorted in the
+// given order
+pub fn paginate<T, O>(items: Vec<T>, params: &PageParams, order: &O) -> Result<Page<T>, PageError>
+where
+    O: CursorOrder<T>,
+{
+    let total = items.len();
+
+    // Without any pagination parameter the whole collection is returned
+    if params.limit.is_none() && params.offset.is_none() && params.cursor.is_none() {
+        return Ok(Page {
+            items,
+            total,
+            first: None,
+            prev: None,
+            next: None,
+        });
+    }
+
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(PageError::InvalidLimit(limit));
+    }
+
+    if let Some(offset) = params.offset {
+        if params.cursor.is_some() {
+            return Err(PageError::OffsetWithCursor);
+        }
+        return Ok(offset_page(items, limit, offset));
+    }
+
+    // Find the range of items the cursor selects
+    let (start, end) = match &params.cursor {
+        None => (0, limit.min(total)),
+        Some(token) => match Cursor::decode(token)? {
+            Cursor::After(after) => {
+                let start = position(&items, order, &after, Ordering::Greater)?;
+                (start, (start + limit).min(total))
+            }
+            Cursor::Before(before) => {
+                let end = position(&items, order, &before, Ordering::Equal)?;
+                (end.saturating_sub(limit), end)
+            }
+        },
+    };
+
+    let cursor_link = |cursor: Option<Cursor<O::Key>>| PageLink::Cursor {
+        limit,
+        cursor: cursor.map(|cursor| cursor.encode()),
+    };
+    // A cursor whose neighbours were deleted can select an empty range at
+    // either end, which has no page before or after it to link to
+    let prev = match start {
+        start if start == 0 || start >= total => None,
+        _ => Some(cursor_link(Some(Cursor::Before(order.key(&items[start]))))),
+    };
+    let next = match end {
+        end if end == 0 || end >= total => None,
+        _ => Some(cursor_link(Some(Cursor::After(order.key(&items[end - 1]))))),
+    };
+    let first = cursor_link(None);
+
+    let items = items.into_iter().skip(start).take(end - start).collect();
+    Ok(Page {
+        items,
+        total,
+        first: Some(first),
+        prev,
+        next,
+    })
+}
+
+// Return the index of the first item that compares to the cursor key at least
+// as high as the given bound
+fn position<T, O>(items: &[T], order: &O, key: &O::Key, bound: Ordering) -> Result<usize, PageError>
+where
+    O: CursorOrder<T>,
+{
+    let mut invalid = false;
+    let index = items.partition_point(|item| match order.compare(item, key) {
+        Some(ordering) => ordering < bound,
+        None => {
+            invalid = true;
+            false
+        }
+    });
+
+    match invalid {
+        true => Err(PageError::InvalidCursor),
+        false => Ok(index),
+    }
+}
+
+// Select a page by its offset into the items
+fn offset_page<T>(items: Vec<T>, limit: usize, offset: usize) -> Page<T> {
+    let total = items.len();
+    let link = |offset| Some(PageLink::Offset { limit, offset });
+
+    let prev = match offset {
+        0 => None,
+        _ => link(offset.saturating_sub(limit)),
+    };
+    let next = match offset.saturating_add(limit) {
+        next if next >= total => None,
+        next => link(next),
+    };
+
+    Page {
+        items: items.into_iter().skip(offset).take(limit).collect(),
+        total,
+        first: link(0),
+        prev,
+        next,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Order numbers ascending, using the number itself as the cursor key
+    struct Ascending;
+
+    impl CursorOrder<u32> for Ascending {
+        type Key = u32;
+
+        fn key(&self, item: &u32) -> u32 {
+            *item
+        }
+
+        fn compare(&self, item: &u32, key: &u32) -> Option<Ordering> {
+            Some(item.cmp(key))
+        }
+    }
+
+    fn params(limit: usize, cursor: Option<Cursor<u32>>) -> PageParams {
+        PageParams {
+            limit: Some(limit),
+            offset: None,
+            cursor: cursor.map(|cursor| cursor.encode()),
+        }
+    }
+
+    // Return the cursor a link points at
+    fn cursor(link: &Option<PageLink>) -> Option<Cursor<u32>> {
+        match link {
+            Some(PageLink::Cursor {
+                cursor: Some(token),
+                ..
+            }) => Some(Cursor::decode(token).unwrap()),
+            _ => None,
+        }
+    }
+
+    fn numbers() -> Vec<u32> {
+        (1..=10).collect()
+    }
+
+    #[test]
+    fn first_page_links_to_next_only() {
+        let page = paginate(numbers(), &params(3, None), &Ascending).unwrap();
+        assert_eq!(page.items, vec![1, 2, 3]);
+        assert_eq!(page.total, 10);
+        assert!(page.first.is_some());
+        assert!(page.prev.is_none());
+        assert!(matches!(cursor(&page.next), Some(Cursor::After(3))));
+    }
+
+    #[test]
+    fn after_cursor_selects_following_items() {
+        let cursor_params = params(3, Some(Cursor::After(3)));
+        let page = paginate(numbers(), &cursor_params, &Ascending).unwrap();
+        assert_eq!(page.items, vec![4, 5, 6]);
+        assert!(matches!(cursor(&page.prev), Some(Cursor::Before(4))));
+        assert!(matches!(cursor(&page.next), Some(Cursor::After(6))));
+    }
+
+    #[test]
+    fn before_cursor_selects_preceding_items() {
+        let cursor_params = params(3, Some(Cursor::Before(7)));
+        let page = paginate(numbers(), &cursor_params, &Ascending).unwrap();
+        assert_eq!(page.items, vec![4, 5, 6]);
+        assert!(matches!(cursor(&page.prev), Some(Cursor::Before(4))));
+        assert!(matches!(cursor(&page.next), Some(Cursor::After(6))));
+    }
+
+    #[test]
+    fn last_page_links_to_prev_only() {
+        let cursor_params = params(3, Some(Cursor::After(8)));
+        let page = paginate(numbers(), &cursor_params, &Ascending).unwrap();
+        assert_eq!(page.items, vec![9, 10]);
+        assert!(matches!(cursor(&page.prev), Some(Cursor::Before(9))));
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn empty_collection_has_no_neighbours() {
+        let page = paginate(Vec::new(), &params(3, None), &Ascending).unwrap();
+        assert!(page.items.is_empty());
+        assert_eq!(page.total, 0);
+        assert!(page.prev.is_none());
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn stale_cursors_still_resolve() {
+        // The items the cursors were created from have since been deleted
+        let items = vec![2, 4, 6, 8];
+        let page = paginate(
+            items.clone(),
+            &params(2, Some(Cursor::After(5))),
+            &Ascending,
+        )
+        .unwrap();
+        assert_eq!(page.items, vec![6, 8]);
+        let page = paginate(items, &params(2, Some(Cursor::Before(5))), &Ascending).unwrap();
+        assert_eq!(page.items, vec![2, 4]);
+    }
+
+    #[test]
+    fn before_cursor_ahead_of_every_item_returns_empty_page() {
+        let cursor_params = params(3, Some(Cursor::Before(1)));
+        let page = paginate(numbers(), &cursor_params, &Ascending).unwrap();
+        assert!(page.items.is_empty());
+        assert!(page.prev.is_none());
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn after_cursor_past_every_item_returns_empty_page() {
+        let cursor_params = params(3, Some(Cursor::After(10)));
+        let page = paginate(numbers(), &cursor_params, &Ascending).unwrap();
+        assert!(page.items.is_empty());
+        assert!(page.prev.is_none());
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn malformed_cursor_is_rejected() {
+        let bad = PageParams {
+            limit: Some(3),
+            offset: None,
+            cursor: Some("not a cursor".to_string()),
+        };
+        assert!(matches!(
+            paginate(numbers(), &bad, &Ascending),
+            Err(PageError::InvalidCursor)
+        ));
+    }
+
+    #[test]
+    fn offset_pages_link_to_neighbours() {
+        let offset_params = PageParams {
+            limit: Some(4),
+            offset: Some(4),
+            cursor: None,
+        };
+        let page = paginate(numbers(), &offset_params, &Ascending).unwrap();
+        assert_eq!(page.items, vec![5, 6, 7, 8]);
+        assert!(matches!(
+            page.prev,
+            Some(PageLink::Offset {
+                limit: 4,
+                offset: 0
+            })
+        ));
+        assert!(matches!(
+            page.next,
+            Some(PageLink::Offset {
+                limit: 4,
+                offset: 8
+            })
+        ));
+    }
+}
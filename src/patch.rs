@@ -0,0 +1,261 @@
+use std::fmt;
+
+use crate::models::{Director, Movie, NewDirector};
+
+// Media type of JSON Merge Patch documents (RFC 7396)
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+// Media type of JSON Patch documents (RFC 6902)
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+// Define the errors that can occur while patching a movie
+#[derive(Debug)]
+pub enum PatchError {
+    // The request body is in neither of the supported patch formats
+    UnsupportedMediaType(String),
+    // The request body is not a valid patch document
+    Malformed(String),
+    // A JSON Patch operation could not be applied, e.g. a failed `test`
+    Failed(String),
+    // The patched document is not a valid movie
+    Invalid(String),
+    // The patch tried to change the ID of the movie
+    IdChanged,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnsupportedMediaType(media_type) => write!(
+                f,
+                "unsupported patch media type '{}', expected {} or {}",
+                media_type, MERGE_PATCH, JSON_PATCH
+            ),
+            PatchError::Malformed(msg) => write!(f, "malformed patch document: {}", msg),
+            PatchError::Failed(msg) => write!(f, "cannot apply patch: {}", msg),
+            PatchError::Invalid(msg) => write!(f, "patched movie is invalid: {}", msg),
+            PatchError::IdChanged => write!(f, "the id of a movie cannot be changed"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+// Define a patched movie, together with the director the patch renamed it to.
+// A renamed director is looked up or created by name the way PUT does it,
+// rather than renaming the director every other movie of theirs refers to.
+#[derive(Debug)]
+pub struct PatchedMovie {
+    pub movie: Movie,
+    pub director: Option<NewDirector>,
+}
+
+// Apply a JSON Merge Patch or JSON Patch document, picked by its media type, to
+// a movie and return the patched movie. The director is embedded in the
+// document by name, so that `director.*` paths can be patched as well.
+pub fn apply(
+    movie: &Movie,
+    director: &Director,
+    media_type: &str,
+    body: &[u8],
+) -> Result<PatchedMovie, PatchError> {
+    let current = NewDirector {
+        firstname: director.firstname.clone(),
+        lastname: director.lastname.clone(),
+    };
+    let mut doc =
+        serde_json::to_value(movie).map_err(|err| PatchError::Invalid(err.to_string()))?;
+    let embedded =
+        serde_json::to_value(&current).map_err(|err| PatchError::Invalid(err.to_string()))?;
+    if let Some(fields) = doc.as_object_mut() {
+        fields.insert("director".to_string(), embedded);
+    }
+
+    match media_type {
+        MERGE_PATCH => {
+            let patch: serde_json::Value = serde_json::from_slice(body)
+                .map_err(|err| PatchError::Malformed(err.to_string()))?;
+            json_patch::merge(&mut doc, &patch);
+        }
+        JSON_PATCH => {
+            let patch: json_patch::Patch = serde_json::from_slice(body)
+                .map_err(|err| PatchError::Malformed(err.to_string()))?;
+            json_patch::patch(&mut doc, &patch)
+                .map_err(|err| PatchError::Failed(err.to_string()))?;
+        }
+        other => return Err(PatchError::UnsupportedMediaType(other.to_string())),
+    }
+
+    // Take the director back out before reading the movie
+    let patched_director = doc
+        .as_object_mut()
+        .and_then(|fields| fields.remove("director"));
+    let patched: Movie =
+        serde_json::from_value(doc).map_err(|err| PatchError::Invalid(err.to_string()))?;
+    if patched.id != movie.id {
+        return Err(PatchError::IdChanged);
+    }
+
+    // The director can be changed by ID or by name, but not both at once
+    let id_changed = patched.director_id != movie.director_id;
+    let director = match patched_director {
+        None | Some(serde_json::Value::Null) if id_changed => None,
+        None | Some(serde_json::Value::Null) => {
+            return Err(PatchError::Invalid(
+                "the director of a movie cannot be removed".to_string(),
+            ))
+        }
+        Some(value) => {
+            let renamed: NewDirector = serde_json::from_value(value)
+                .map_err(|err| PatchError::Invalid(format!("director: {}", err)))?;
+            match (renamed == current, id_changed) {
+                (true, _) => None,
+                (false, false) => Some(renamed),
+                (false, true) => {
+                    return Err(PatchError::Invalid(
+                        "director must not be changed together with director_id".to_string(),
+                    ))
+                }
+            }
+        }
+    };
+
+    Ok(PatchedMovie {
+        movie: patched,
+        director,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{initial_version, MovieMetadata};
+    use uuid::Uuid;
+
+    fn director() -> Director {
+        Director {
+            id: Uuid::new_v4(),
+            firstname: "Peter".to_string(),
+            lastname: "Jackson".to_string(),
+        }
+    }
+
+    fn movie(director: &Director) -> Movie {
+        Movie {
+            id: Uuid::new_v4(),
+            isbn: "978-0-261-10235-4".to_string(),
+            title: "The Fellowship of the Ring".to_string(),
+            director_id: director.id,
+            metadata: MovieMetadata::default(),
+            version: initial_version(),
+        }
+    }
+
+    fn renamed(firstname: &str, lastname: &str) -> Option<NewDirector> {
+        Some(NewDirector {
+            firstname: firstname.to_string(),
+            lastname: lastname.to_string(),
+        })
+    }
+
+    #[test]
+    fn merge_patch_changes_fields() {
+        let director = director();
+        let movie = movie(&director);
+        let body = br#"{"title": "The Two Towers"}"#;
+        let patched = apply(&movie, &director, MERGE_PATCH, body).unwrap();
+        assert_eq!(patched.movie.title, "The Two Towers");
+        assert_eq!(patched.movie.director_id, director.id);
+        assert!(patched.director.is_none());
+    }
+
+    #[test]
+    fn merge_patch_renames_director() {
+        let director = director();
+        let movie = movie(&director);
+        let body = br#"{"director": {"firstname": "Fran", "lastname": "Walsh"}}"#;
+        let patched = apply(&movie, &director, MERGE_PATCH, body).unwrap();
+        assert_eq!(patched.director, renamed("Fran", "Walsh"));
+        assert_eq!(patched.movie.director_id, director.id);
+    }
+
+    #[test]
+    fn json_patch_renames_director() {
+        let director = director();
+        let movie = movie(&director);
+        let body = br#"[{"op": "replace", "path": "/director/lastname", "value": "Walsh"}]"#;
+        let patched = apply(&movie, &director, JSON_PATCH, body).unwrap();
+        assert_eq!(patched.director, renamed("Peter", "Walsh"));
+    }
+
+    #[test]
+    fn json_patch_can_test_director() {
+        let director = director();
+        let movie = movie(&director);
+        let body = br#"[{"op": "test", "path": "/director/lastname", "value": "Walsh"}]"#;
+        assert!(matches!(
+            apply(&movie, &director, JSON_PATCH, body),
+            Err(PatchError::Failed(_))
+        ));
+    }
+
+    #[test]
+    fn director_id_can_be_changed() {
+        let director = director();
+        let movie = movie(&director);
+        let other = Uuid::new_v4();
+        let body = format!(r#"{{"director_id": "{}"}}"#, other);
+        let patched = apply(&movie, &director, MERGE_PATCH, body.as_bytes()).unwrap();
+        assert_eq!(patched.movie.director_id, other);
+        assert!(patched.director.is_none());
+    }
+
+    #[test]
+    fn director_cannot_be_renamed_and_replaced_at_once() {
+        let director = director();
+        let movie = movie(&director);
+        let body = format!(
+            r#"{{"director_id": "{}", "director": {{"lastname": "Walsh"}}}}"#,
+            Uuid::new_v4()
+        );
+        assert!(matches!(
+            apply(&movie, &director, MERGE_PATCH, body.as_bytes()),
+            Err(PatchError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn director_cannot_be_removed() {
+        let director = director();
+        let movie = movie(&director);
+        assert!(matches!(
+            apply(&movie, &director, MERGE_PATCH, br#"{"director": null}"#),
+            Err(PatchError::Invalid(_))
+        ));
+        let body = br#"[{"op": "remove", "path": "/director"}]"#;
+        assert!(matches!(
+            apply(&movie, &director, JSON_PATCH, body),
+            Err(PatchError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn id_cannot_be_changed() {
+        let director = director();
+        let movie = movie(&director);
+        let body = format!(r#"{{"id": "{}"}}"#, Uuid::new_v4());
+        assert!(matches!(
+            apply(&movie, &director, MERGE_PATCH, body.as_bytes()),
+            Err(PatchError::IdChanged)
+        ));
+    }
+
+    #[test]
+    fn unknown_media_type_is_rejected() {
+        let director = director();
+        let movie = movie(&director);
+        assert!(matches!(
+            apply(&movie, &director, "application/json", b"{}"),
+            Err(PatchError::UnsupportedMediaType(_))
+        ));
+    }
+}
@@ -0,0 +1,525 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::MovieView;
+use crate::pagination::CursorOrder;
+
+// Query parameters that are not filters
+const RESERVED_PARAMS: &[&str] = &["limit", "offset", "cursor", "sort", "expand"];
+
+// Define the movie fields that can be filtered and sorted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Isbn,
+    DirectorFirstname,
+    DirectorLastname,
+    ReleaseDate,
+    RuntimeMinutes,
+    Genres,
+    OriginalLanguage,
+    Country,
+    Certification,
+    Synopsis,
+}
+
+impl Field {
+    const ALL: [Field; 11] = [
+        Field::Title,
+        Field::Isbn,
+        Field::DirectorFirstname,
+        Field::DirectorLastname,
+        Field::ReleaseDate,
+        Field::RuntimeMinutes,
+        Field::Genres,
+        Field::OriginalLanguage,
+        Field::Country,
+        Field::Certification,
+        Field::Synopsis,
+    ];
+
+    // Return the name of the field as used in query parameters
+    fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Isbn => "isbn",
+            Field::DirectorFirstname => "director.firstname",
+            Field::DirectorLastname => "director.lastname",
+            Field::ReleaseDate => "release_date",
+            Field::RuntimeMinutes => "runtime_minutes",
+            Field::Genres => "genres",
+            Field::OriginalLanguage => "original_language",
+            Field::Country => "country",
+            Field::Certification => "certification",
+            Field::Synopsis => "synopsis",
+        }
+    }
+
+    // Look up a field by its name
+    fn parse(name: &str) -> Result<Self, QueryError> {
+        Field::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+            .ok_or_else(|| QueryError::UnknownField(name.to_string()))
+    }
+
+    // Look up a field to sort on by its name; a list has no single value to
+    // sort by
+    fn parse_sortable(name: &str) -> Result<Self, QueryError> {
+        match Field::parse(name)? {
+            Field::Genres => Err(QueryError::NotSortable(name.to_string())),
+            field => Ok(field),
+        }
+    }
+
+    // Return the values of the field in a movie: none if an optional field is
+    // not set, several for a list. The names of a director that was not looked
+    // up are treated as empty.
+    fn values(self, movie: &MovieView) -> Vec<Cow<'_, str>> {
+        let director = movie.director.as_ref();
+        let metadata = &movie.movie.metadata;
+        let value = match self {
+            Field::Title => Some(Cow::Borrowed(movie.movie.title.as_str())),
+            Field::Isbn => Some(Cow::Borrowed(movie.movie.isbn.as_str())),
+            Field::DirectorFirstname => {
+                Some(Cow::Borrowed(director.map_or("", |d| d.firstname.as_str())))
+            }
+            Field::DirectorLastname => {
+                Some(Cow::Borrowed(director.map_or("", |d| d.lastname.as_str())))
+            }
+            Field::ReleaseDate => metadata.release_date.as_deref().map(Cow::Borrowed),
+            Field::RuntimeMinutes => metadata
+                .runtime_minutes
+                .map(|runtime| Cow::Owned(runtime.to_string())),
+            Field::Genres => {
+                return metadata
+                    .genres
+                    .iter()
+                    .map(|genre| Cow::Borrowed(genre.as_str()))
+                    .collect()
+            }
+            Field::OriginalLanguage => metadata.original_language.as_deref().map(Cow::Borrowed),
+            Field::Country => metadata.country.as_deref().map(Cow::Borrowed),
+            Field::Certification => metadata.certification.as_deref().map(Cow::Borrowed),
+            Field::Synopsis => metadata.synopsis.as_deref().map(Cow::Borrowed),
+        };
+        value.into_iter().collect()
+    }
+
+    // Return the value of the field to sort a movie by, treating a field that
+    // is not set as empty
+    fn value(self, movie: &MovieView) -> Cow<'_, str> {
+        self.values(movie).into_iter().next().unwrap_or_default()
+    }
+
+    // Compare two values of the field. Runtimes compare as numbers, and an empty
+    // value sorts before any other.
+    fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Field::RuntimeMinutes => {
+                let number = |value: &str| value.parse::<u64>().ok();
+                number(a).cmp(&number(b)).then_with(|| a.cmp(b))
+            }
+            _ => a.cmp(b),
+        }
+    }
+}
+
+// Define the operators a filter can apply to a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    // The field equals the value
+    Eq,
+    // The field contains the value, ignoring case
+    Contains,
+    // The field starts with the value, ignoring case
+    Prefix,
+    // The field equals one of the comma-separated values
+    In,
+    // The field sorts at or after the value
+    Gte,
+    // The field sorts at or before the value
+    Lte,
+}
+
+impl Operator {
+    // Look up an operator by its name
+    fn parse(name: &str) -> Result<Self, QueryError> {
+        match name {
+            "eq" => Ok(Operator::Eq),
+            "contains" => Ok(Operator::Contains),
+            "prefix" => Ok(Operator::Prefix),
+            "in" => Ok(Operator::In),
+            "gte" => Ok(Operator::Gte),
+            "lte" => Ok(Operator::Lte),
+            _ => Err(QueryError::UnknownOperator(name.to_string())),
+        }
+    }
+}
+
+// Define the errors caused by invalid filter or sort parameters
+#[derive(Debug)]
+pub enum QueryError {
+    UnknownField(String),
+    UnknownOperator(String),
+    NotSortable(String),
+    MalformedParam(String),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::UnknownField(name) => {
+                let fields: Vec<&str> = Field::ALL.iter().map(|field| field.name()).collect();
+                write!(
+                    f,
+                    "unknown field '{}', expected one of: {}",
+                    name,
+                    fields.join(", ")
+                )
+            }
+            QueryError::UnknownOperator(name) => write!(
+                f,
+                "unknown operator '{}', expected one of: eq, contains, prefix, in, gte, lte",
+                name
+            ),
+            QueryError::NotSortable(name) => write!(f, "cannot sort on field '{}'", name),
+            QueryError::MalformedParam(name) => write!(f, "malformed query parameter '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+// Define a single filter such as `title[contains]=ring`
+#[derive(Debug)]
+pub struct Filter {
+    field: Field,
+    operator: Operator,
+    value: String,
+}
+
+impl Filter {
+    // Check whether a movie passes the filter, which a list field does if any
+    // of its values does. A field that is not set passes no filter.
+    fn matches(&self, movie: &MovieView) -> bool {
+        self.field
+            .values(movie)
+            .iter()
+            .any(|actual| self.matches_value(actual))
+    }
+
+    // Check whether a single value passes the filter
+    fn matches_value(&self, actual: &str) -> bool {
+        match self.operator {
+            Operator::Eq => actual == self.value,
+            Operator::Contains => actual.to_lowercase().contains(&self.value.to_lowercase()),
+            Operator::Prefix => actual
+                .to_lowercase()
+                .starts_with(&self.value.to_lowercase()),
+            Operator::In => self.value.split(',').any(|value| actual == value),
+            Operator::Gte => self.field.compare(actual, &self.value).is_ge(),
+            Operator::Lte => self.field.compare(actual, &self.value).is_le(),
+        }
+    }
+}
+
+// Define one key of a `sort=` parameter such as `-title`
+#[derive(Debug)]
+pub struct SortKey {
+    field: Field,
+    descending: bool,
+}
+
+// Define the filters and sort order requested for a movie listing
+#[derive(Debug, Default)]
+pub struct MovieQuery {
+    filters: Vec<Filter>,
+    sort: Vec<SortKey>,
+}
+
+impl MovieQuery {
+    // Parse the filter and sort parameters out of the decoded query string.
+    // Filters are written `field=value` or `field[op]=value` and sorting as
+    // `sort=field,-other` where a leading `-` sorts in descending order.
+    pub fn parse(params: &[(String, String)]) -> Result<Self, QueryError> {
+        let mut query = MovieQuery::default();
+
+        for (name, value) in params {
+            if name == "sort" {
+                for key in value.split(',').filter(|key| !key.is_empty()) {
+                    let (descending, field) = match key.strip_prefix('-') {
+                        Some(field) => (true, field),
+                        None => (false, key),
+                    };
+                    query.sort.push(SortKey {
+                        field: Field::parse_sortable(field)?,
+                        descending,
+                    });
+                }
+                continue;
+            }
+            if RESERVED_PARAMS.contains(&name.as_str()) {
+                continue;
+            }
+
+            // Split `field[op]` into the field and the operator
+            let (field, operator) = match name.split_once('[') {
+                Some((field, rest)) => {
+                    let operator = rest
+                        .strip_suffix(']')
+                        .ok_or_else(|| QueryError::MalformedParam(name.clone()))?;
+                    (field, Operator::parse(operator)?)
+                }
+                None => (name.as_str(), Operator::Eq),
+            };
+            query.filters.push(Filter {
+                field: Field::parse(field)?,
+                operator,
+                value: value.clone(),
+            });
+        }
+
+        Ok(query)
+    }
+
+    // Keep the movies that pass every filter, sorted in the requested order
+    pub fn apply(&self, movies: Vec<MovieView>) -> Vec<MovieView> {
+        let mut movies: Vec<MovieView> = movies
+            .into_iter()
+            .filter(|movie| self.filters.iter().all(|filter| filter.matches(movie)))
+            .collect();
+        movies.sort_by(|a, b| {
+            let values: Vec<Cow<'_, str>> =
+                self.sort.iter().map(|key| key.field.value(b)).collect();
+            self.compare_to(a, values.iter().map(|value| value.as_ref()), b.movie.id)
+        });
+        movies
+    }
+
+    // Describe the sort order, so that cursors can be tied to it
+    fn sort_spec(&self) -> String {
+        let keys: Vec<String> = self
+            .sort
+            .iter()
+            .map(|key| match key.descending {
+                true => format!("-{}", key.field.name()),
+                false => key.field.name().to_string(),
+            })
+            .collect();
+        keys.join(",")
+    }
+
+    // Return the values of a movie for every sort key, followed by its ID
+    fn sort_values(&self, movie: &MovieView) -> MovieCursor {
+        MovieCursor {
+            sort: self.sort_spec(),
+            values: self
+                .sort
+                .iter()
+                .map(|key| key.field.value(movie).to_string())
+                .collect(),
+            id: movie.movie.id,
+        }
+    }
+
+    // Compare a movie against the sort values and ID of another, breaking ties by
+    // ID so that the order is total and stable
+    fn compare_to<'a>(
+        &self,
+        movie: &MovieView,
+        values: impl Iterator<Item = &'a str>,
+        id: Uuid,
+    ) -> Ordering {
+        self.sort
+            .iter()
+            .zip(values)
+            .map(|(key, value)| {
+                let ordering = key.field.compare(&key.field.value(movie), value);
+                match key.descending {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| movie.movie.id.cmp(&id))
+    }
+}
+
+// Define the sort values recorded in a cursor over a movie listing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MovieCursor {
+    sort: String,
+    values: Vec<String>,
+    id: Uuid,
+}
+
+impl CursorOrder<MovieView> for MovieQuery {
+    type Key = MovieCursor;
+
+    fn key(&self, movie: &MovieView) -> MovieCursor {
+        self.sort_values(movie)
+    }
+
+    fn compare(&self, movie: &MovieView, key: &MovieCursor) -> Option<Ordering> {
+        // A cursor is only meaningful for the sort order it was created with
+        match key.sort == self.sort_spec() && key.values.len() == self.sort.len() {
+            true => Some(self.compare_to(movie, key.values.iter().map(String::as_str), key.id)),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{initial_version, Director, Movie, MovieMetadata};
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn movie(title: &str, lastname: &str, runtime: Option<u32>, genres: &[&str]) -> MovieView {
+        MovieView {
+            movie: Movie {
+                id: Uuid::new_v4(),
+                isbn: "978-0-261-10235-4".to_string(),
+                title: title.to_string(),
+                director_id: Uuid::nil(),
+                metadata: MovieMetadata {
+                    runtime_minutes: runtime,
+                    genres: genres.iter().map(|genre| genre.to_string()).collect(),
+                    ..MovieMetadata::default()
+                },
+                version: initial_version(),
+            },
+            director: Some(Director {
+                id: Uuid::nil(),
+                firstname: String::new(),
+                lastname: lastname.to_string(),
+            }),
+            rating: None,
+        }
+    }
+
+    fn titles(movies: &[MovieView]) -> Vec<&str> {
+        movies
+            .iter()
+            .map(|movie| movie.movie.title.as_str())
+            .collect()
+    }
+
+    fn catalogue() -> Vec<MovieView> {
+        vec![
+            movie("Heat", "Mann", Some(170), &["crime"]),
+            movie("Alien", "Scott", Some(117), &["horror", "science_fiction"]),
+            movie("Blade Runner", "Scott", Some(117), &["science_fiction"]),
+            movie("Collateral", "Mann", None, &["crime", "thriller"]),
+        ]
+    }
+
+    #[test]
+    fn parse_reads_filters_and_sort_keys() {
+        let query = MovieQuery::parse(&params(&[
+            ("title[contains]", "ring"),
+            ("director.lastname", "Jackson"),
+            ("sort", "-runtime_minutes,title"),
+            ("limit", "10"),
+            ("expand", "director"),
+        ]))
+        .unwrap();
+
+        assert_eq!(query.filters.len(), 2);
+        assert_eq!(query.filters[0].field, Field::Title);
+        assert_eq!(query.filters[0].operator, Operator::Contains);
+        assert_eq!(query.filters[1].field, Field::DirectorLastname);
+        assert_eq!(query.filters[1].operator, Operator::Eq);
+        assert_eq!(query.sort_spec(), "-runtime_minutes,title");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_parameters() {
+        let parse = |pairs: &[(&str, &str)]| MovieQuery::parse(&params(pairs));
+        assert!(matches!(
+            parse(&[("rating", "5")]),
+            Err(QueryError::UnknownField(_))
+        ));
+        assert!(matches!(
+            parse(&[("title[like]", "x")]),
+            Err(QueryError::UnknownOperator(_))
+        ));
+        assert!(matches!(
+            parse(&[("title[eq", "x")]),
+            Err(QueryError::MalformedParam(_))
+        ));
+        assert!(matches!(
+            parse(&[("sort", "genres")]),
+            Err(QueryError::NotSortable(_))
+        ));
+        assert!(matches!(
+            parse(&[("sort", "-unknown")]),
+            Err(QueryError::UnknownField(_))
+        ));
+    }
+
+    #[test]
+    fn filters_apply_their_operators() {
+        let apply = |pairs: &[(&str, &str)]| {
+            let query = MovieQuery::parse(&params(pairs)).unwrap();
+            let mut found: Vec<String> = titles(&query.apply(catalogue()))
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(apply(&[("title[contains]", "RUN")]), vec!["Blade Runner"]);
+        assert_eq!(apply(&[("title[prefix]", "he")]), vec!["Heat"]);
+        assert_eq!(apply(&[("title[in]", "Heat,Alien")]), vec!["Alien", "Heat"]);
+        assert_eq!(apply(&[("genres", "crime")]), vec!["Collateral", "Heat"]);
+        // Runtimes compare as numbers, and movies without one pass no filter
+        assert_eq!(
+            apply(&[("runtime_minutes[gte]", "99")]),
+            vec!["Alien", "Blade Runner", "Heat"]
+        );
+        assert_eq!(
+            apply(&[("director.lastname", "Scott"), ("genres", "horror")]),
+            vec!["Alien"]
+        );
+    }
+
+    #[test]
+    fn sort_keys_apply_in_order() {
+        let query = MovieQuery::parse(&params(&[("sort", "-director.lastname,title")])).unwrap();
+        assert_eq!(
+            titles(&query.apply(catalogue())),
+            vec!["Alien", "Blade Runner", "Collateral", "Heat"]
+        );
+
+        // A missing runtime sorts before any other
+        let query = MovieQuery::parse(&params(&[("sort", "runtime_minutes,title")])).unwrap();
+        assert_eq!(
+            titles(&query.apply(catalogue())),
+            vec!["Collateral", "Alien", "Blade Runner", "Heat"]
+        );
+    }
+
+    #[test]
+    fn cursors_only_match_their_sort_order() {
+        let by_title = MovieQuery::parse(&params(&[("sort", "title")])).unwrap();
+        let by_runtime = MovieQuery::parse(&params(&[("sort", "runtime_minutes")])).unwrap();
+        let movies = catalogue();
+        let cursor = by_title.key(&movies[0]);
+
+        assert_eq!(by_title.compare(&movies[0], &cursor), Some(Ordering::Equal));
+        assert_eq!(by_title.compare(&movies[1], &cursor), Some(Ordering::Less));
+        assert_eq!(by_runtime.compare(&movies[0], &cursor), None);
+    }
+}
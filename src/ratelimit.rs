@@ -0,0 +1,470 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::Error;
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::auth;
+use crate::config::Config;
+use crate::error::ApiError;
+
+// Number of buckets above which full ones are dropped, as they behave exactly
+// like a bucket that was never created
+const PRUNE_ABOVE: usize = 10_000;
+
+// Define the kinds of requests that are limited separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Class {
+    Read,
+    Write,
+}
+
+impl Class {
+    fn of(method: &Method) -> Self {
+        match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS => Class::Read,
+            _ => Class::Write,
+        }
+    }
+}
+
+// Define the limit on one class of requests: a client may make `burst`
+// requests at once, after which it earns `per_minute` more every minute
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    per_minute: u32,
+    burst: u32,
+}
+
+impl Limit {
+    // Return the number of tokens a bucket earns per second
+    fn rate(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+// Define the tokens left to a client for one class of requests
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // Add the tokens earned since the last update, up to the burst size
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let earned = now.duration_since(self.updated).as_secs_f64() * limit.rate();
+        self.tokens = (self.tokens + earned).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+}
+
+// Define the outcome of taking a token, as reported in the response headers
+#[derive(Debug)]
+struct Decision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    // Seconds until the bucket is full again
+    reset: u64,
+    // Seconds until the next token, if the request was refused
+    retry_after: u64,
+}
+
+// Define the buckets of every client, along with the number of them that
+// makes the next request drop the full ones
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<(String, Class), Bucket>,
+    prune_above: usize,
+}
+
+// Define token-bucket rate limits per client, with separate buckets for reads
+// and writes so that a client busy writing can still read and vice versa
+pub struct RateLimiter {
+    read: Option<Limit>,
+    write: Option<Limit>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    // Create the limiter with the limits in the configuration, where a rate of
+    // zero turns off the limit for that class of requests
+    pub fn from_config(config: &Config) -> Self {
+        let limit = |per_minute, burst| match per_minute {
+            0 => None,
+            per_minute => Some(Limit { per_minute, burst }),
+        };
+        Self {
+            read: limit(config.read_rate_limit, config.read_burst),
+            write: limit(config.write_rate_limit, config.write_burst),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                prune_above: PRUNE_ABOVE,
+            }),
+        }
+    }
+
+    // Take a token from a client's bucket for a class of requests
+    fn take(&self, client: String, class: Class) -> Option<Decision> {
+        let limit = match class {
+            Class::Read => self.read?,
+            Class::Write => self.write?,
+        };
+        let now = Instant::now();
+        // A poisoned lock only means another request panicked while counting,
+        // which leaves the buckets usable
+        let mut state = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Buckets {
+            buckets,
+            prune_above,
+        } = &mut *state;
+
+        // Prune only once the buckets have doubled since the last time, so
+        // that clients coming from ever new addresses cannot make every
+        // request scan them all
+        if buckets.len() > *prune_above {
+            buckets.retain(|(_, class), bucket| {
+                let limit = match class {
+                    Class::Read => self.read,
+                    Class::Write => self.write,
+                };
+                limit.is_some_and(|limit| {
+                    bucket.refill(&limit, now);
+                    bucket.tokens < f64::from(limit.burst)
+                })
+            });
+            *prune_above = PRUNE_ABOVE.max(buckets.len() * 2);
+        }
+
+        let bucket = buckets.entry((client, class)).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+        bucket.refill(&limit, now);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        let seconds_until = |tokens: f64| (tokens.max(0.0) / limit.rate()).ceil() as u64;
+        Some(Decision {
+            allowed,
+            limit: limit.burst,
+            remaining: bucket.tokens.floor() as u32,
+            reset: seconds_until(f64::from(limit.burst) - bucket.tokens),
+            retry_after: seconds_until(1.0 - bucket.tokens),
+        })
+    }
+}
+
+// Name the client sending a request: the client its credentials identify, or
+// else the address it connects from. Credentials that do not check out are
+// ignored, so that made-up keys cannot each get a bucket of their own.
+fn client(req: &ServiceRequest) -> String {
+    let principal = auth::identify(req).ok().flatten();
+    match (principal, req.peer_addr()) {
+        (Some(principal), _) => format!("client:{}", principal.name),
+        (None, Some(addr)) => format!("ip:{}", addr.ip()),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+// Add the RateLimit-* headers describing a decision to a response
+fn insert_headers(headers: &mut header::HeaderMap, decision: &Decision) {
+    let values = [
+        ("ratelimit-limit", decision.limit as u64),
+        ("ratelimit-remaining", decision.remaining as u64),
+        ("ratelimit-reset", decision.reset),
+    ];
+    for (name, value) in values {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+}
+
+// Define a middleware that answers requests over their client's limit with a
+// 429 response
+pub struct RateLimit(pub Arc<RateLimiter>);
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service,
+            limiter: self.0.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let decision = match self.limiter.take(client(&req), Class::of(req.method())) {
+            Some(decision) => decision,
+            None => {
+                let response = self.service.call(req);
+                return Box::pin(async move { Ok(response.await?.map_into_boxed_body()) });
+            }
+        };
+
+        if !decision.allowed {
+            let err = ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate-limited",
+                "Too many requests",
+                format!(
+                    "the rate limit of {} requests is used up, retry in {} seconds",
+                    decision.limit, decision.retry_after
+                ),
+            )
+            .with_header(header::RETRY_AFTER, &decision.retry_after.to_string());
+            let mut response = req.error_response(err);
+            insert_headers(response.headers_mut(), &decision);
+            return Box::pin(ready(Ok(response)));
+        }
+
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let mut response = response.await?.map_into_boxed_body();
+            insert_headers(response.headers_mut(), &decision);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Authenticator;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use clap::Parser;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn limit(per_minute: u32, burst: u32) -> Limit {
+        Limit { per_minute, burst }
+    }
+
+    fn limiter(read: Option<Limit>, write: Option<Limit>) -> RateLimiter {
+        RateLimiter {
+            read,
+            write,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                prune_above: PRUNE_ABOVE,
+            }),
+        }
+    }
+
+    fn buckets(limiter: &RateLimiter) -> (usize, usize) {
+        let state = limiter.buckets.lock().unwrap();
+        (state.buckets.len(), state.prune_above)
+    }
+
+    #[test]
+    fn buckets_refill_at_the_rate_up_to_the_burst() {
+        let limit = limit(60, 5);
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated: start,
+        };
+        bucket.refill(&limit, start + Duration::from_millis(2500));
+        assert!((bucket.tokens - 2.5).abs() < 1e-9);
+        bucket.refill(&limit, start + Duration::from_secs(100));
+        assert_eq!(bucket.tokens, 5.0);
+    }
+
+    #[test]
+    fn take_counts_down_and_then_refuses() {
+        let limiter = limiter(Some(limit(60, 2)), None);
+        let first = limiter.take("ip:a".to_string(), Class::Read).unwrap();
+        assert!(first.allowed);
+        assert_eq!((first.limit, first.remaining, first.reset), (2, 1, 1));
+        let second = limiter.take("ip:a".to_string(), Class::Read).unwrap();
+        assert!(second.allowed);
+        assert_eq!((second.remaining, second.reset), (0, 2));
+        let third = limiter.take("ip:a".to_string(), Class::Read).unwrap();
+        assert!(!third.allowed);
+        assert_eq!((third.remaining, third.retry_after), (0, 1));
+        // Other clients have buckets of their own
+        assert!(
+            limiter
+                .take("ip:b".to_string(), Class::Read)
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn reads_and_writes_have_separate_buckets() {
+        let limiter = limiter(Some(limit(60, 1)), Some(limit(60, 1)));
+        assert!(
+            limiter
+                .take("ip:a".to_string(), Class::Read)
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !limiter
+                .take("ip:a".to_string(), Class::Read)
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            limiter
+                .take("ip:a".to_string(), Class::Write)
+                .unwrap()
+                .allowed
+        );
+        assert_eq!(Class::of(&Method::HEAD), Class::Read);
+        assert_eq!(Class::of(&Method::DELETE), Class::Write);
+    }
+
+    #[test]
+    fn a_rate_of_zero_turns_the_limit_off() {
+        let config = crate::config::Config::parse_from([
+            "movies",
+            "--read-rate-limit",
+            "0",
+            "--write-rate-limit",
+            "30",
+        ]);
+        let limiter = RateLimiter::from_config(&config);
+        assert!(limiter.take("ip:a".to_string(), Class::Read).is_none());
+        assert!(limiter.take("ip:a".to_string(), Class::Write).is_some());
+    }
+
+    #[test]
+    fn full_buckets_are_pruned_once_there_are_too_many() {
+        let limiter = limiter(Some(limit(60, 2)), None);
+        {
+            let mut state = limiter.buckets.lock().unwrap();
+            for i in 0..=PRUNE_ABOVE {
+                let bucket = Bucket {
+                    tokens: 2.0,
+                    updated: Instant::now(),
+                };
+                state
+                    .buckets
+                    .insert((format!("ip:{}", i), Class::Read), bucket);
+            }
+        }
+        limiter.take("ip:new".to_string(), Class::Read);
+        assert_eq!(buckets(&limiter), (1, PRUNE_ABOVE));
+    }
+
+    #[test]
+    fn pruning_waits_for_the_buckets_to_double() {
+        let limiter = limiter(Some(limit(1, 2)), None);
+        {
+            let mut state = limiter.buckets.lock().unwrap();
+            for i in 0..=PRUNE_ABOVE {
+                let bucket = Bucket {
+                    tokens: 0.0,
+                    updated: Instant::now(),
+                };
+                state
+                    .buckets
+                    .insert((format!("ip:{}", i), Class::Read), bucket);
+            }
+        }
+        // None of the buckets is full, so they are all kept and the next
+        // prune is put off until there are twice as many
+        limiter.take("ip:new".to_string(), Class::Read);
+        assert_eq!(buckets(&limiter), (PRUNE_ABOVE + 2, (PRUNE_ABOVE + 1) * 2));
+    }
+
+    // Load an authenticator knowing the key `alice-key`
+    fn authenticator() -> Authenticator {
+        let path = std::env::temp_dir().join(format!("keys-{}.json", Uuid::new_v4()));
+        let hash = "72ee9d4355ccb9d3a4c9dbf37382e38e75c1b1a225b5bd1f729ee91bbda30c20";
+        let keys = format!(
+            r#"[{{"name": "alice", "key_sha256": "{}", "role": "reader"}}]"#,
+            hash
+        );
+        std::fs::write(&path, keys).unwrap();
+        let config =
+            crate::config::Config::parse_from(["movies", "--api-keys", path.to_str().unwrap()]);
+        let authenticator = Authenticator::from_config(&config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        authenticator
+    }
+
+    #[test]
+    fn clients_are_named_by_principal_or_else_by_address() {
+        let key = "alice-key";
+        let authenticator = web::Data::new(authenticator());
+        let request = |key: Option<&str>| {
+            let mut req = TestRequest::default()
+                .app_data(authenticator.clone())
+                .peer_addr("203.0.113.7:4000".parse().unwrap());
+            if let Some(key) = key {
+                req = req.insert_header(("X-Api-Key", key));
+            }
+            req.to_srv_request()
+        };
+        assert_eq!(client(&request(Some(key))), "client:alice");
+        assert_eq!(client(&request(None)), "ip:203.0.113.7");
+        // Made-up keys do not get a bucket of their own
+        assert_eq!(client(&request(Some("made-up"))), "ip:203.0.113.7");
+    }
+
+    #[actix_web::test]
+    async fn requests_over_the_limit_get_429_with_retry_after() {
+        let limiter = Arc::new(limiter(Some(limit(60, 1)), None));
+        let app = init_service(
+            App::new()
+                .wrap(RateLimit(limiter))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers.get("ratelimit-limit").unwrap(), "1");
+        assert_eq!(headers.get("ratelimit-remaining").unwrap(), "0");
+        assert_eq!(headers.get("ratelimit-reset").unwrap(), "1");
+
+        let response = call_service(&app, TestRequest::get().to_request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = response.headers();
+        assert_eq!(headers.get(header::RETRY_AFTER).unwrap(), "1");
+        assert_eq!(headers.get("ratelimit-remaining").unwrap(), "0");
+        let body = read_body(response).await;
+        assert!(String::from_utf8_lossy(&body).contains("/problems/rate-limited"));
+    }
+}
@@ -0,0 +1,282 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use uuid::Uuid;
+
+use crate::models::Review;
+use crate::repository::RepositoryError;
+use crate::validation::{MAX_SCORE, MIN_SCORE};
+
+// Number of reviews at the catalogue-wide mean that every movie's weighted
+// rating is pulled towards, so a single enthusiastic review cannot put a movie
+// at the top
+const PRIOR_REVIEWS: f64 = 10.0;
+
+// Define the running totals of the reviews of a single movie
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    count: u64,
+    sum: u64,
+    // Number of reviews per score, starting at MIN_SCORE
+    histogram: [u64; (MAX_SCORE - MIN_SCORE + 1) as usize],
+}
+
+impl Tally {
+    // Count a score, which has to be between MIN_SCORE and MAX_SCORE
+    fn add(&mut self, score: u8) {
+        self.count += 1;
+        self.sum += u64::from(score);
+        self.histogram[usize::from(score - MIN_SCORE)] += 1;
+    }
+
+    // Stop counting a score, which has to be between MIN_SCORE and MAX_SCORE
+    fn remove(&mut self, score: u8) {
+        self.count = self.count.saturating_sub(1);
+        self.sum = self.sum.saturating_sub(u64::from(score));
+        let slot = &mut self.histogram[usize::from(score - MIN_SCORE)];
+        *slot = slot.saturating_sub(1);
+    }
+
+    // Stop counting every score of another tally
+    fn remove_all(&mut self, other: &Tally) {
+        self.count = self.count.saturating_sub(other.count);
+        self.sum = self.sum.saturating_sub(other.sum);
+        for (total, count) in self.histogram.iter_mut().zip(other.histogram) {
+            *total = total.saturating_sub(count);
+        }
+    }
+
+    // Return a checksum of the totals, which changes whenever they do
+    fn fingerprint(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.count.to_le_bytes());
+        hasher.update(&self.sum.to_le_bytes());
+        for count in self.histogram {
+            hasher.update(&count.to_le_bytes());
+        }
+        hasher.finalize()
+    }
+}
+
+// Define the state of the rating index
+#[derive(Debug, Default)]
+struct RatingState {
+    movies: HashMap<Uuid, Tally>,
+    // Totals over every review of every movie
+    total: Tally,
+}
+
+// Check that a review's score can be counted, as reviews read back from
+// storage were not necessarily validated by this version of the server
+fn check_score(review: &Review) -> Result<(), RepositoryError> {
+    match (MIN_SCORE..=MAX_SCORE).contains(&review.score) {
+        true => Ok(()),
+        false => Err(RepositoryError::Storage(format!(
+            "review {} has score {}, outside {} to {}",
+            review.id, review.score, MIN_SCORE, MAX_SCORE
+        ))),
+    }
+}
+
+impl RatingState {
+    fn add(&mut self, review: &Review) -> Result<(), RepositoryError> {
+        check_score(review)?;
+        self.movies
+            .entry(review.movie_id)
+            .or_default()
+            .add(review.score);
+        self.total.add(review.score);
+        Ok(())
+    }
+
+    fn remove(&mut self, review: &Review) -> Result<(), RepositoryError> {
+        check_score(review)?;
+        if let Some(tally) = self.movies.get_mut(&review.movie_id) {
+            tally.remove(review.score);
+            if tally.count == 0 {
+                self.movies.remove(&review.movie_id);
+            }
+            self.total.remove(review.score);
+        }
+        Ok(())
+    }
+}
+
+// Define the aggregate of the reviews of a movie as shown to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct RatingSummary {
+    pub count: u64,
+    // Mean score, if the movie has been reviewed at all
+    pub mean: Option<f64>,
+    // Number of reviews per score, from MIN_SCORE to MAX_SCORE
+    pub histogram: BTreeMap<u8, u64>,
+    // Bayesian average of the movie's scores and the catalogue-wide mean,
+    // suited to ranking movies with few reviews against those with many
+    pub weighted_rating: Option<f64>,
+    // Checksum of the movie's own totals, used in its ETag
+    #[serde(skip)]
+    pub fingerprint: u32,
+}
+
+// Define an index of the review totals of every movie, updated as reviews are
+// posted, changed and removed so that the aggregates never have to be
+// recomputed from the reviews themselves
+#[derive(Debug, Default)]
+pub struct RatingIndex {
+    state: RwLock<RatingState>,
+}
+
+impl RatingIndex {
+    // Build an index holding the given reviews
+    pub fn build(reviews: &[Review]) -> Result<Self, RepositoryError> {
+        let mut state = RatingState::default();
+        for review in reviews {
+            state.add(review)?;
+        }
+        Ok(Self {
+            state: RwLock::new(state),
+        })
+    }
+
+    // Return true if a panic while counting left the totals' lock poisoned
+    pub fn is_poisoned(&self) -> bool {
+        self.state.is_poisoned()
+    }
+
+    // Lock the totals for reading, reporting a poisoned lock as a storage error
+    fn read(&self) -> Result<RwLockReadGuard<'_, RatingState>, RepositoryError> {
+        self.state
+            .read()
+            .map_err(|_| RepositoryError::Storage("rating index lock poisoned".to_string()))
+    }
+
+    // Lock the totals for writing, reporting a poisoned lock as a storage error
+    fn write(&self) -> Result<RwLockWriteGuard<'_, RatingState>, RepositoryError> {
+        self.state
+            .write()
+            .map_err(|_| RepositoryError::Storage("rating index lock poisoned".to_string()))
+    }
+
+    // Count a new review
+    pub fn add(&self, review: &Review) -> Result<(), RepositoryError> {
+        self.write()?.add(review)
+    }
+
+    // Replace the score of a changed review
+    pub fn replace(&self, old: &Review, new: &Review) -> Result<(), RepositoryError> {
+        // Check the new score first, so that a failure leaves the old one counted
+        check_score(new)?;
+        let mut state = self.write()?;
+        state.remove(old)?;
+        state.add(new)
+    }
+
+    // Stop counting a removed review
+    pub fn remove(&self, review: &Review) -> Result<(), RepositoryError> {
+        self.write()?.remove(review)
+    }
+
+    // Stop counting the reviews of a removed movie
+    pub fn remove_movie(&self, movie_id: Uuid) -> Result<(), RepositoryError> {
+        let mut state = self.write()?;
+        if let Some(tally) = state.movies.remove(&movie_id) {
+            state.total.remove_all(&tally);
+        }
+        Ok(())
+    }
+
+    // Return the aggregate of the reviews of a movie
+    pub fn summary(&self, movie_id: Uuid) -> Result<RatingSummary, RepositoryError> {
+        let state = self.read()?;
+        let tally = state.movies.get(&movie_id).copied().unwrap_or_default();
+
+        let mean = |tally: &Tally| match tally.count {
+            0 => None,
+            count => Some(tally.sum as f64 / count as f64),
+        };
+        let weighted_rating = match (mean(&tally), mean(&state.total)) {
+            (Some(movie_mean), Some(global_mean)) => {
+                let count = tally.count as f64;
+                Some((count * movie_mean + PRIOR_REVIEWS * global_mean) / (count + PRIOR_REVIEWS))
+            }
+            _ => None,
+        };
+
+        Ok(RatingSummary {
+            count: tally.count,
+            mean: mean(&tally),
+            histogram: (MIN_SCORE..=MAX_SCORE).zip(tally.histogram).collect(),
+            weighted_rating,
+            fingerprint: tally.fingerprint(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    fn review(movie_id: Uuid, score: u8) -> Review {
+        Review {
+            id: Uuid::new_v4(),
+            movie_id,
+            author: "critic".to_string(),
+            score,
+            text: String::new(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn summary_follows_added_and_removed_reviews() {
+        let movie = Uuid::new_v4();
+        let low = review(movie, 4);
+        let index = RatingIndex::build(&[low.clone(), review(movie, 8)]).unwrap();
+
+        let summary = index.summary(movie).unwrap();
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.mean, Some(6.0));
+        assert_eq!(summary.histogram[&4], 1);
+        assert_eq!(summary.histogram[&8], 1);
+
+        let mut raised = low.clone();
+        raised.score = 10;
+        index.replace(&low, &raised).unwrap();
+        assert_eq!(index.summary(movie).unwrap().mean, Some(9.0));
+
+        index.remove_movie(movie).unwrap();
+        let summary = index.summary(movie).unwrap();
+        assert_eq!(summary.count, 0);
+        assert_eq!(summary.mean, None);
+    }
+
+    #[test]
+    fn scores_out_of_range_are_rejected() {
+        let movie = Uuid::new_v4();
+        assert!(RatingIndex::build(&[review(movie, 0)]).is_err());
+        assert!(RatingIndex::build(&[review(movie, MAX_SCORE + 1)]).is_err());
+
+        let stored = review(movie, 5);
+        let index = RatingIndex::build(std::slice::from_ref(&stored)).unwrap();
+        let mut invalid = stored.clone();
+        invalid.score = 11;
+        assert!(index.add(&invalid).is_err());
+        assert!(index.replace(&stored, &invalid).is_err());
+        assert_eq!(index.summary(movie).unwrap().mean, Some(5.0));
+    }
+
+    #[test]
+    fn poisoned_index_reports_errors() {
+        let index = RatingIndex::default();
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = index.state.write().unwrap();
+            panic!("poison the ratings");
+        });
+
+        assert!(index.is_poisoned());
+        assert!(index.summary(Uuid::new_v4()).is_err());
+        assert!(index.add(&review(Uuid::new_v4(), 5)).is_err());
+    }
+}
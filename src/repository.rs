@@ -0,0 +1,949 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::metrics;
+use crate::models::{Credit, Director, Movie, Person, Review};
+
+// Define the kinds of records held by a storage backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entity {
+    Movie,
+    Director,
+    Person,
+    Credit,
+    Review,
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entity::Movie => write!(f, "movie"),
+            Entity::Director => write!(f, "director"),
+            Entity::Person => write!(f, "person"),
+            Entity::Credit => write!(f, "credit"),
+            Entity::Review => write!(f, "review"),
+        }
+    }
+}
+
+// Define the errors a storage backend can report back to the handlers
+#[derive(Debug)]
+pub enum RepositoryError {
+    // No record of the given kind is stored under the given ID
+    NotFound(Entity, Uuid),
+    // A record of the given kind is already stored under the given ID
+    AlreadyExists(Entity, Uuid),
+    // A record refers to another one that does not exist
+    InvalidReference(Entity, Uuid),
+    // A record cannot be removed while other records still refer to it
+    Referenced {
+        entity: Entity,
+        id: Uuid,
+        // Kind and number of the records referring to it
+        referrer: Entity,
+        referrers: usize,
+    },
+    // The stored movie does not have the version the caller expected
+    VersionMismatch {
+        id: Uuid,
+        expected: u64,
+        actual: u64,
+    },
+    // The backend could not complete the operation
+    Storage(String),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::NotFound(entity, id) => write!(f, "{} {} not found", entity, id),
+            RepositoryError::AlreadyExists(entity, id) => {
+                write!(f, "{} {} already exists", entity, id)
+            }
+            RepositoryError::InvalidReference(entity, id) => {
+                write!(f, "referenced {} {} does not exist", entity, id)
+            }
+            RepositoryError::Referenced {
+                entity,
+                id,
+                referrer,
+                referrers,
+            } => write!(
+                f,
+                "{} {} is still referenced by {} {}(s)",
+                entity, id, referrers, referrer
+            ),
+            RepositoryError::VersionMismatch {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "movie {} is at version {}, expected version {}",
+                id, actual, expected
+            ),
+            RepositoryError::Storage(msg) => write!(f, "storage error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+// Define the operations every movie storage backend has to provide
+pub trait MovieRepository: Send + Sync {
+    // Return all stored movies
+    fn list(&self) -> Result<Vec<Movie>, RepositoryError>;
+
+    // Return the movie stored under the given ID
+    fn get(&self, id: Uuid) -> Result<Movie, RepositoryError>;
+
+    // Store a new movie under its own ID; its director must exist
+    fn insert(&self, movie: Movie) -> Result<Movie, RepositoryError>;
+
+    // Replace the stored movie that has the same ID and bump its version. If an
+    // expected version is given, the stored movie must still be at it.
+    fn update(&self, movie: Movie, expected_version: Option<u64>)
+        -> Result<Movie, RepositoryError>;
+
+    // Remove the movie stored under the given ID together with its credits. If
+    // an expected version is given, the stored movie must still be at it.
+    fn delete(&self, id: Uuid, expected_version: Option<u64>) -> Result<(), RepositoryError>;
+
+    // Verify that every record is stored under a key equal to its own ID and
+    // that every reference between records points at an existing one
+    fn check_integrity(&self) -> Result<(), RepositoryError>;
+
+    // Check that the storage backend can still serve requests
+    fn ping(&self) -> Result<(), RepositoryError>;
+
+    // Return true if a panic left a lock of the storage backend poisoned, after
+    // which it fails every request until the server is restarted
+    fn is_poisoned(&self) -> bool;
+
+    // Write out whatever the storage backend has not yet made durable, before
+    // the server exits
+    fn flush(&self) -> Result<(), RepositoryError>;
+}
+
+// Define the operations on directors every storage backend has to provide
+pub trait DirectorRepository: Send + Sync {
+    // Return all stored directors
+    fn list_directors(&self) -> Result<Vec<Director>, RepositoryError>;
+
+    // Return the director stored under the given ID
+    fn get_director(&self, id: Uuid) -> Result<Director, RepositoryError>;
+
+    // Store a new director under its own ID
+    fn insert_director(&self, director: Director) -> Result<Director, RepositoryError>;
+
+    // Replace the stored director that has the same ID
+    fn update_director(&self, director: Director) -> Result<Director, RepositoryError>;
+
+    // Remove the director stored under the given ID, refusing while any movie
+    // still refers to it
+    fn delete_director(&self, id: Uuid) -> Result<(), RepositoryError>;
+
+    // Return the stored director with the same first and last name, storing
+    // the given one if there is none
+    fn find_or_insert_director(&self, director: Director) -> Result<Director, RepositoryError>;
+}
+
+// Define the operations on people every storage backend has to provide
+pub trait PersonRepository: Send + Sync {
+    // Return all stored people
+    fn list_people(&self) -> Result<Vec<Person>, RepositoryError>;
+
+    // Return the person stored under the given ID
+    fn get_person(&self, id: Uuid) -> Result<Person, RepositoryError>;
+
+    // Store a new person under their own ID
+    fn insert_person(&self, person: Person) -> Result<Person, RepositoryError>;
+
+    // Replace the stored person that has the same ID
+    fn update_person(&self, person: Person) -> Result<Person, RepositoryError>;
+
+    // Remove the person stored under the given ID, refusing while any credit
+    // still refers to them
+    fn delete_person(&self, id: Uuid) -> Result<(), RepositoryError>;
+}
+
+// Define the operations on credits every storage backend has to provide
+pub trait CreditRepository: Send + Sync {
+    // Return the credits of the given movie
+    fn movie_credits(&self, movie_id: Uuid) -> Result<Vec<Credit>, RepositoryError>;
+
+    // Return the credits of the given person
+    fn person_credits(&self, person_id: Uuid) -> Result<Vec<Credit>, RepositoryError>;
+
+    // Return the credit stored under the given ID
+    fn get_credit(&self, id: Uuid) -> Result<Credit, RepositoryError>;
+
+    // Store a new credit under its own ID; its movie and person must exist
+    fn insert_credit(&self, credit: Credit) -> Result<Credit, RepositoryError>;
+
+    // Replace the stored credit that has the same ID
+    fn update_credit(&self, credit: Credit) -> Result<Credit, RepositoryError>;
+
+    // Remove the credit stored under the given ID
+    fn delete_credit(&self, id: Uuid) -> Result<(), RepositoryError>;
+}
+
+// Define the operations on reviews every storage backend has to provide
+pub trait ReviewRepository: Send + Sync {
+    // Return every stored review
+    fn list_reviews(&self) -> Result<Vec<Review>, RepositoryError>;
+
+    // Return the reviews of the given movie
+    fn movie_reviews(&self, movie_id: Uuid) -> Result<Vec<Review>, RepositoryError>;
+
+    // Return the review stored under the given ID
+    fn get_review(&self, id: Uuid) -> Result<Review, RepositoryError>;
+
+    // Store a new review under its own ID; its movie must exist
+    fn insert_review(&self, review: Review) -> Result<Review, RepositoryError>;
+
+    // Replace the stored review that has the same ID
+    fn update_review(&self, review: Review) -> Result<Review, RepositoryError>;
+
+    // Remove the review stored under the given ID
+    fn delete_review(&self, id: Uuid) -> Result<(), RepositoryError>;
+}
+
+// Define the full set of operations the handlers need from a storage backend
+pub trait Repository:
+    MovieRepository + DirectorRepository + PersonRepository + CreditRepository + ReviewRepository
+{
+}
+
+impl<T> Repository for T where
+    T: MovieRepository
+        + DirectorRepository
+        + PersonRepository
+        + CreditRepository
+        + ReviewRepository
+{
+}
+
+// Check that a stored movie is at the version the caller expected, if any
+pub fn check_version(stored: &Movie, expected: Option<u64>) -> Result<(), RepositoryError> {
+    match expected {
+        Some(expected) if expected != stored.version => Err(RepositoryError::VersionMismatch {
+            id: stored.id,
+            expected,
+            actual: stored.version,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Define the records of the in-memory backends together with the rules every
+// mutation has to follow. The `prepare_*` methods check a mutation and return
+// the record to write without changing anything, so that a backend can make
+// the change durable before applying it.
+#[derive(Debug, Default)]
+pub struct Store {
+    pub movies: HashMap<Uuid, Movie>,
+    pub directors: HashMap<Uuid, Director>,
+    pub people: HashMap<Uuid, Person>,
+    pub credits: HashMap<Uuid, Credit>,
+    pub reviews: HashMap<Uuid, Review>,
+}
+
+impl Store {
+    // Check that a movie refers to an existing director
+    fn check_director(&self, movie: &Movie) -> Result<(), RepositoryError> {
+        match self.directors.contains_key(&movie.director_id) {
+            true => Ok(()),
+            false => Err(RepositoryError::InvalidReference(
+                Entity::Director,
+                movie.director_id,
+            )),
+        }
+    }
+
+    pub fn get_movie(&self, id: Uuid) -> Result<&Movie, RepositoryError> {
+        self.movies
+            .get(&id)
+            .ok_or(RepositoryError::NotFound(Entity::Movie, id))
+    }
+
+    pub fn get_director(&self, id: Uuid) -> Result<&Director, RepositoryError> {
+        self.directors
+            .get(&id)
+            .ok_or(RepositoryError::NotFound(Entity::Director, id))
+    }
+
+    pub fn prepare_insert_movie(&self, movie: Movie) -> Result<Movie, RepositoryError> {
+        // Refuse to overwrite a movie that is already stored under this ID
+        if self.movies.contains_key(&movie.id) {
+            return Err(RepositoryError::AlreadyExists(Entity::Movie, movie.id));
+        }
+        self.check_director(&movie)?;
+        Ok(movie)
+    }
+
+    pub fn prepare_update_movie(
+        &self,
+        mut movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, RepositoryError> {
+        let stored = self.get_movie(movie.id)?;
+        check_version(stored, expected_version)?;
+        self.check_director(&movie)?;
+        movie.version = stored.version + 1;
+        Ok(movie)
+    }
+
+    pub fn prepare_delete_movie(
+        &self,
+        id: Uuid,
+        expected_version: Option<u64>,
+    ) -> Result<(), RepositoryError> {
+        check_version(self.get_movie(id)?, expected_version)
+    }
+
+    // Remove a movie together with its credits and reviews
+    pub fn remove_movie(&mut self, id: Uuid) {
+        self.movies.remove(&id);
+        self.credits.retain(|_, credit| credit.movie_id != id);
+        self.reviews.retain(|_, review| review.movie_id != id);
+    }
+
+    pub fn prepare_insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        if self.directors.contains_key(&director.id) {
+            return Err(RepositoryError::AlreadyExists(
+                Entity::Director,
+                director.id,
+            ));
+        }
+        Ok(director)
+    }
+
+    pub fn prepare_update_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        self.get_director(director.id)?;
+        Ok(director)
+    }
+
+    pub fn prepare_delete_director(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.get_director(id)?;
+
+        // Refuse to leave movies pointing at a director that no longer exists
+        let referrers = self
+            .movies
+            .values()
+            .filter(|movie| movie.director_id == id)
+            .count();
+        match referrers {
+            0 => Ok(()),
+            _ => Err(RepositoryError::Referenced {
+                entity: Entity::Director,
+                id,
+                referrer: Entity::Movie,
+                referrers,
+            }),
+        }
+    }
+
+    pub fn get_person(&self, id: Uuid) -> Result<&Person, RepositoryError> {
+        self.people
+            .get(&id)
+            .ok_or(RepositoryError::NotFound(Entity::Person, id))
+    }
+
+    pub fn get_credit(&self, id: Uuid) -> Result<&Credit, RepositoryError> {
+        self.credits
+            .get(&id)
+            .ok_or(RepositoryError::NotFound(Entity::Credit, id))
+    }
+
+    // Return the credits matching a predicate
+    pub fn credits_where(&self, predicate: impl Fn(&Credit) -> bool) -> Vec<Credit> {
+        self.credits
+            .values()
+            .filter(|credit| predicate(credit))
+            .cloned()
+            .collect()
+    }
+
+    pub fn prepare_insert_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        if self.people.contains_key(&person.id) {
+            return Err(RepositoryError::AlreadyExists(Entity::Person, person.id));
+        }
+        Ok(person)
+    }
+
+    pub fn prepare_update_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        self.get_person(person.id)?;
+        Ok(person)
+    }
+
+    pub fn prepare_delete_person(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.get_person(id)?;
+
+        // Refuse to leave credits pointing at a person who no longer exists
+        let referrers = self
+            .credits
+            .values()
+            .filter(|credit| credit.person_id == id)
+            .count();
+        match referrers {
+            0 => Ok(()),
+            _ => Err(RepositoryError::Referenced {
+                entity: Entity::Person,
+                id,
+                referrer: Entity::Credit,
+                referrers,
+            }),
+        }
+    }
+
+    // Check that a credit refers to an existing movie and person
+    fn check_credit(&self, credit: &Credit) -> Result<(), RepositoryError> {
+        if !self.movies.contains_key(&credit.movie_id) {
+            return Err(RepositoryError::InvalidReference(
+                Entity::Movie,
+                credit.movie_id,
+            ));
+        }
+        if !self.people.contains_key(&credit.person_id) {
+            return Err(RepositoryError::InvalidReference(
+                Entity::Person,
+                credit.person_id,
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn prepare_insert_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        if self.credits.contains_key(&credit.id) {
+            return Err(RepositoryError::AlreadyExists(Entity::Credit, credit.id));
+        }
+        self.check_credit(&credit)?;
+        Ok(credit)
+    }
+
+    pub fn prepare_update_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        self.get_credit(credit.id)?;
+        self.check_credit(&credit)?;
+        Ok(credit)
+    }
+
+    pub fn prepare_delete_credit(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.get_credit(id).map(|_| ())
+    }
+
+    pub fn get_review(&self, id: Uuid) -> Result<&Review, RepositoryError> {
+        self.reviews
+            .get(&id)
+            .ok_or(RepositoryError::NotFound(Entity::Review, id))
+    }
+
+    // Return the reviews of the given movie
+    pub fn movie_reviews(&self, movie_id: Uuid) -> Vec<Review> {
+        self.reviews
+            .values()
+            .filter(|review| review.movie_id == movie_id)
+            .cloned()
+            .collect()
+    }
+
+    // Check that a review refers to an existing movie
+    fn check_review(&self, review: &Review) -> Result<(), RepositoryError> {
+        match self.movies.contains_key(&review.movie_id) {
+            true => Ok(()),
+            false => Err(RepositoryError::InvalidReference(
+                Entity::Movie,
+                review.movie_id,
+            )),
+        }
+    }
+
+    pub fn prepare_insert_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        if self.reviews.contains_key(&review.id) {
+            return Err(RepositoryError::AlreadyExists(Entity::Review, review.id));
+        }
+        self.check_review(&review)?;
+        Ok(review)
+    }
+
+    pub fn prepare_update_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        self.get_review(review.id)?;
+        self.check_review(&review)?;
+        Ok(review)
+    }
+
+    pub fn prepare_delete_review(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.get_review(id).map(|_| ())
+    }
+
+    // Return the stored director with the same first and last name, if any
+    pub fn find_director(&self, director: &Director) -> Option<&Director> {
+        self.directors.values().find(|stored| {
+            stored.firstname == director.firstname && stored.lastname == director.lastname
+        })
+    }
+
+    // Verify that every record is keyed by its own ID and refers to existing
+    // records
+    pub fn check_integrity(&self) -> Result<(), RepositoryError> {
+        check_keys(Entity::Movie, &self.movies, |movie| movie.id)?;
+        check_keys(Entity::Director, &self.directors, |director| director.id)?;
+        check_keys(Entity::Person, &self.people, |person| person.id)?;
+        check_keys(Entity::Credit, &self.credits, |credit| credit.id)?;
+        check_keys(Entity::Review, &self.reviews, |review| review.id)?;
+        for movie in self.movies.values() {
+            self.check_director(movie).map_err(|_| {
+                RepositoryError::Storage(format!(
+                    "movie {} refers to missing director {}",
+                    movie.id, movie.director_id
+                ))
+            })?;
+        }
+        for credit in self.credits.values() {
+            self.check_credit(credit).map_err(|err| {
+                RepositoryError::Storage(format!("credit {}: {}", credit.id, err))
+            })?;
+        }
+        for review in self.reviews.values() {
+            self.check_review(review).map_err(|err| {
+                RepositoryError::Storage(format!("review {}: {}", review.id, err))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+// Check that every record of a collection is keyed by its own ID
+fn check_keys<T>(
+    entity: Entity,
+    records: &HashMap<Uuid, T>,
+    id: impl Fn(&T) -> Uuid,
+) -> Result<(), RepositoryError> {
+    match records.iter().find(|(key, record)| **key != id(record)) {
+        Some((key, record)) => Err(RepositoryError::Storage(format!(
+            "{} {} is stored under mismatched key {}",
+            entity,
+            id(record),
+            key
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Define an in-memory backend that keeps the records in a Mutex-guarded Store
+#[derive(Default)]
+pub struct InMemoryMovieRepository {
+    store: Mutex<Store>,
+}
+
+impl InMemoryMovieRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Lock the Store, reporting a poisoned Mutex as a storage error
+    fn lock(&self) -> Result<MutexGuard<'_, Store>, RepositoryError> {
+        metrics::lock("memory", &self.store)
+            .map_err(|_| RepositoryError::Storage("movie store lock poisoned".to_string()))
+    }
+}
+
+impl MovieRepository for InMemoryMovieRepository {
+    fn list(&self) -> Result<Vec<Movie>, RepositoryError> {
+        // Lock the data and clone the values out of the HashMap
+        let store = self.lock()?;
+        Ok(store.movies.values().cloned().collect())
+    }
+
+    fn get(&self, id: Uuid) -> Result<Movie, RepositoryError> {
+        self.lock()?.get_movie(id).cloned()
+    }
+
+    fn insert(&self, movie: Movie) -> Result<Movie, RepositoryError> {
+        let mut store = self.lock()?;
+        let movie = store.prepare_insert_movie(movie)?;
+        store.movies.insert(movie.id, movie.clone());
+        Ok(movie)
+    }
+
+    fn update(
+        &self,
+        movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, RepositoryError> {
+        let mut store = self.lock()?;
+        let movie = store.prepare_update_movie(movie, expected_version)?;
+        store.movies.insert(movie.id, movie.clone());
+        Ok(movie)
+    }
+
+    fn delete(&self, id: Uuid, expected_version: Option<u64>) -> Result<(), RepositoryError> {
+        let mut store = self.lock()?;
+        store.prepare_delete_movie(id, expected_version)?;
+        store.remove_movie(id);
+        Ok(())
+    }
+
+    fn check_integrity(&self) -> Result<(), RepositoryError> {
+        self.lock()?.check_integrity()
+    }
+
+    fn ping(&self) -> Result<(), RepositoryError> {
+        self.lock().map(|_| ())
+    }
+
+    fn is_poisoned(&self) -> bool {
+        self.store.is_poisoned()
+    }
+
+    fn flush(&self) -> Result<(), RepositoryError> {
+        // There is nowhere to write the movies to
+        Ok(())
+    }
+}
+
+impl DirectorRepository for InMemoryMovieRepository {
+    fn list_directors(&self) -> Result<Vec<Director>, RepositoryError> {
+        let store = self.lock()?;
+        Ok(store.directors.values().cloned().collect())
+    }
+
+    fn get_director(&self, id: Uuid) -> Result<Director, RepositoryError> {
+        self.lock()?.get_director(id).cloned()
+    }
+
+    fn insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let mut store = self.lock()?;
+        let director = store.prepare_insert_director(director)?;
+        store.directors.insert(director.id, director.clone());
+        Ok(director)
+    }
+
+    fn update_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let mut store = self.lock()?;
+        let director = store.prepare_update_director(director)?;
+        store.directors.insert(director.id, director.clone());
+        Ok(director)
+    }
+
+    fn delete_director(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut store = self.lock()?;
+        store.prepare_delete_director(id)?;
+        store.directors.remove(&id);
+        Ok(())
+    }
+
+    fn find_or_insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let mut store = self.lock()?;
+        if let Some(stored) = store.find_director(&director) {
+            return Ok(stored.clone());
+        }
+        let director = store.prepare_insert_director(director)?;
+        store.directors.insert(director.id, director.clone());
+        Ok(director)
+    }
+}
+
+impl PersonRepository for InMemoryMovieRepository {
+    fn list_people(&self) -> Result<Vec<Person>, RepositoryError> {
+        let store = self.lock()?;
+        Ok(store.people.values().cloned().collect())
+    }
+
+    fn get_person(&self, id: Uuid) -> Result<Person, RepositoryError> {
+        self.lock()?.get_person(id).cloned()
+    }
+
+    fn insert_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        let mut store = self.lock()?;
+        let person = store.prepare_insert_person(person)?;
+        store.people.insert(person.id, person.clone());
+        Ok(person)
+    }
+
+    fn update_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        let mut store = self.lock()?;
+        let person = store.prepare_update_person(person)?;
+        store.people.insert(person.id, person.clone());
+        Ok(person)
+    }
+
+    fn delete_person(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut store = self.lock()?;
+        store.prepare_delete_person(id)?;
+        store.people.remove(&id);
+        Ok(())
+    }
+}
+
+impl CreditRepository for InMemoryMovieRepository {
+    fn movie_credits(&self, movie_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        Ok(self
+            .lock()?
+            .credits_where(|credit| credit.movie_id == movie_id))
+    }
+
+    fn person_credits(&self, person_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        Ok(self
+            .lock()?
+            .credits_where(|credit| credit.person_id == person_id))
+    }
+
+    fn get_credit(&self, id: Uuid) -> Result<Credit, RepositoryError> {
+        self.lock()?.get_credit(id).cloned()
+    }
+
+    fn insert_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        let mut store = self.lock()?;
+        let credit = store.prepare_insert_credit(credit)?;
+        store.credits.insert(credit.id, credit.clone());
+        Ok(credit)
+    }
+
+    fn update_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        let mut store = self.lock()?;
+        let credit = store.prepare_update_credit(credit)?;
+        store.credits.insert(credit.id, credit.clone());
+        Ok(credit)
+    }
+
+    fn delete_credit(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut store = self.lock()?;
+        store.prepare_delete_credit(id)?;
+        store.credits.remove(&id);
+        Ok(())
+    }
+}
+
+impl ReviewRepository for InMemoryMovieRepository {
+    fn list_reviews(&self) -> Result<Vec<Review>, RepositoryError> {
+        Ok(self.lock()?.reviews.values().cloned().collect())
+    }
+
+    fn movie_reviews(&self, movie_id: Uuid) -> Result<Vec<Review>, RepositoryError> {
+        Ok(self.lock()?.movie_reviews(movie_id))
+    }
+
+    fn get_review(&self, id: Uuid) -> Result<Review, RepositoryError> {
+        self.lock()?.get_review(id).cloned()
+    }
+
+    fn insert_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        let mut store = self.lock()?;
+        let review = store.prepare_insert_review(review)?;
+        store.reviews.insert(review.id, review.clone());
+        Ok(review)
+    }
+
+    fn update_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        let mut store = self.lock()?;
+        let review = store.prepare_update_review(review)?;
+        store.reviews.insert(review.id, review.clone());
+        Ok(review)
+    }
+
+    fn delete_review(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut store = self.lock()?;
+        store.prepare_delete_review(id)?;
+        store.reviews.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{initial_version, MovieMetadata, Role};
+    use time::OffsetDateTime;
+
+    // Build a store holding one record of every kind, all referring to each
+    // other
+    fn store() -> Store {
+        let director = Director {
+            id: Uuid::new_v4(),
+            firstname: "Peter".to_string(),
+            lastname: "Jackson".to_string(),
+        };
+        let movie = Movie {
+            id: Uuid::new_v4(),
+            isbn: "978-0-261-10235-4".to_string(),
+            title: "The Fellowship of the Ring".to_string(),
+            director_id: director.id,
+            metadata: MovieMetadata::default(),
+            version: initial_version(),
+        };
+        let person = Person {
+            id: Uuid::new_v4(),
+            firstname: "Elijah".to_string(),
+            lastname: "Wood".to_string(),
+        };
+        let credit = Credit {
+            id: Uuid::new_v4(),
+            movie_id: movie.id,
+            person_id: person.id,
+            role: Role::Actor,
+            character: Some("Frodo".to_string()),
+            billing: 1,
+        };
+        let review = Review {
+            id: Uuid::new_v4(),
+            movie_id: movie.id,
+            author: "critic".to_string(),
+            score: 9,
+            text: String::new(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+        };
+
+        Store {
+            directors: HashMap::from([(director.id, director)]),
+            movies: HashMap::from([(movie.id, movie)]),
+            people: HashMap::from([(person.id, person)]),
+            credits: HashMap::from([(credit.id, credit)]),
+            reviews: HashMap::from([(review.id, review)]),
+        }
+    }
+
+    // Move the only record of a collection to a different key
+    fn rekey<T>(records: &mut HashMap<Uuid, T>) {
+        let key = *records.keys().next().unwrap();
+        let record = records.remove(&key).unwrap();
+        records.insert(Uuid::new_v4(), record);
+    }
+
+    fn mismatched(store: &Store, entity: &str) -> bool {
+        match store.check_integrity() {
+            Err(RepositoryError::Storage(msg)) => {
+                msg.starts_with(entity) && msg.contains("mismatched key")
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn consistent_store_passes() {
+        store().check_integrity().unwrap();
+    }
+
+    #[test]
+    fn mismatched_keys_are_found_in_every_collection() {
+        let mut movies = store();
+        rekey(&mut movies.movies);
+        assert!(mismatched(&movies, "movie"));
+
+        let mut directors = store();
+        rekey(&mut directors.directors);
+        assert!(mismatched(&directors, "director"));
+
+        let mut people = store();
+        rekey(&mut people.people);
+        assert!(mismatched(&people, "person"));
+
+        let mut credits = store();
+        rekey(&mut credits.credits);
+        assert!(mismatched(&credits, "credit"));
+
+        let mut reviews = store();
+        rekey(&mut reviews.reviews);
+        assert!(mismatched(&reviews, "review"));
+    }
+
+    #[test]
+    fn dangling_references_are_found() {
+        let mut store = store();
+        store.people.clear();
+        assert!(store.check_integrity().is_err());
+
+        let mut store = self::store();
+        store.directors.clear();
+        assert!(store.check_integrity().is_err());
+    }
+
+    // Return a repository holding the records of `store()` and the store itself
+    fn repository() -> (InMemoryMovieRepository, Store) {
+        let store = store();
+        let repository = InMemoryMovieRepository::new();
+        for director in store.directors.values() {
+            repository.insert_director(director.clone()).unwrap();
+        }
+        for movie in store.movies.values() {
+            repository.insert(movie.clone()).unwrap();
+        }
+        (repository, store)
+    }
+
+    #[test]
+    fn movies_round_trip() {
+        let (repository, store) = repository();
+        let movie = store.movies.values().next().unwrap().clone();
+        assert_eq!(repository.get(movie.id).unwrap(), movie);
+        assert_eq!(repository.list().unwrap(), vec![movie.clone()]);
+        assert!(matches!(
+            repository.insert(movie.clone()),
+            Err(RepositoryError::AlreadyExists(Entity::Movie, _))
+        ));
+
+        // Every update stores the next version, and a stale one is refused
+        let renamed = Movie {
+            title: "The Lord of the Rings".to_string(),
+            ..movie.clone()
+        };
+        let updated = repository.update(renamed.clone(), Some(1)).unwrap();
+        assert_eq!(updated.version, 2);
+        assert_eq!(repository.get(movie.id).unwrap(), updated);
+        assert!(matches!(
+            repository.update(renamed, Some(1)),
+            Err(RepositoryError::VersionMismatch {
+                expected: 1,
+                actual: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            repository.delete(movie.id, Some(1)),
+            Err(RepositoryError::VersionMismatch { .. })
+        ));
+
+        repository.delete(movie.id, Some(2)).unwrap();
+        assert!(matches!(
+            repository.get(movie.id),
+            Err(RepositoryError::NotFound(Entity::Movie, _))
+        ));
+    }
+
+    #[test]
+    fn movies_refer_to_stored_directors() {
+        let (repository, store) = repository();
+        let director = store.directors.values().next().unwrap();
+        let orphan = Movie {
+            id: Uuid::new_v4(),
+            director_id: Uuid::new_v4(),
+            ..store.movies.values().next().unwrap().clone()
+        };
+        assert!(matches!(
+            repository.insert(orphan),
+            Err(RepositoryError::InvalidReference(Entity::Director, _))
+        ));
+        assert!(matches!(
+            repository.delete_director(director.id),
+            Err(RepositoryError::Referenced {
+                referrer: Entity::Movie,
+                referrers: 1,
+                ..
+            })
+        ));
+
+        // Directors are found by name rather than stored twice
+        let namesake = Director {
+            id: Uuid::new_v4(),
+            ..director.clone()
+        };
+        assert_eq!(
+            repository.find_or_insert_director(namesake).unwrap().id,
+            director.id
+        );
+        assert_eq!(repository.list_directors().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,706 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::metrics;
+use crate::models::{Credit, Director, Movie, MovieView, Person, Review};
+use crate::ratings::RatingIndex;
+use crate::repository::{
+    CreditRepository, DirectorRepository, MovieRepository, PersonRepository, Repository,
+    RepositoryError, ReviewRepository,
+};
+
+// Number of results returned when no limit is given
+const DEFAULT_LIMIT: usize = 20;
+// Largest number of results a client may ask for
+const MAX_LIMIT: usize = 100;
+// Relative score of a term that only matches a query token as a prefix
+const PREFIX_WEIGHT: f64 = 0.5;
+
+// Define the movie fields covered by the search index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum SearchField {
+    #[serde(rename = "title")]
+    Title,
+    #[serde(rename = "director.firstname")]
+    DirectorFirstname,
+    #[serde(rename = "director.lastname")]
+    DirectorLastname,
+}
+
+impl SearchField {
+    const ALL: [SearchField; 3] = [
+        SearchField::Title,
+        SearchField::DirectorFirstname,
+        SearchField::DirectorLastname,
+    ];
+
+    // Return the value of the field in a movie and its director
+    fn value<'a>(self, movie: &'a Movie, director: &'a Director) -> &'a str {
+        match self {
+            SearchField::Title => &movie.title,
+            SearchField::DirectorFirstname => &director.firstname,
+            SearchField::DirectorLastname => &director.lastname,
+        }
+    }
+
+    // Return how much a match in this field counts towards the score
+    fn weight(self) -> f64 {
+        match self {
+            SearchField::Title => 2.0,
+            SearchField::DirectorFirstname | SearchField::DirectorLastname => 1.0,
+        }
+    }
+}
+
+// Define a token of a text together with its position, in characters
+#[derive(Debug)]
+struct Token {
+    term: String,
+    start: usize,
+    end: usize,
+}
+
+// Split a text into alphanumeric tokens, folded to lowercase without diacritics
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (index, c) in text.chars().enumerate() {
+        if c.is_alphanumeric() {
+            current
+                .get_or_insert_with(|| (index, String::new()))
+                .1
+                .push(c);
+        } else if let Some((start, word)) = current.take() {
+            tokens.push(Token {
+                term: fold(&word),
+                start,
+                end: index,
+            });
+        }
+    }
+    if let Some((start, word)) = current {
+        tokens.push(Token {
+            term: fold(&word),
+            start,
+            end: text.chars().count(),
+        });
+    }
+
+    tokens
+}
+
+// Fold a word to lowercase and strip its diacritics, so that "Amélie" and
+// "AMELIE" are indexed under the same term
+fn fold(word: &str) -> String {
+    word.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Define the state of the inverted index
+#[derive(Debug, Default)]
+struct IndexState {
+    // Map each term to the movies containing it and the weighted number of times
+    // it occurs in them
+    postings: BTreeMap<String, HashMap<Uuid, f64>>,
+    // Map each movie to the terms it was indexed under, so it can be removed
+    terms: HashMap<Uuid, Vec<String>>,
+}
+
+impl IndexState {
+    // Index the searchable fields of a movie, replacing any previous entry
+    fn add(&mut self, movie: &Movie, director: &Director) {
+        self.remove(movie.id);
+
+        let mut frequencies: HashMap<String, f64> = HashMap::new();
+        for field in SearchField::ALL {
+            for token in tokenize(field.value(movie, director)) {
+                *frequencies.entry(token.term).or_default() += field.weight();
+            }
+        }
+
+        for (term, frequency) in &frequencies {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(movie.id, *frequency);
+        }
+        self.terms
+            .insert(movie.id, frequencies.into_keys().collect());
+    }
+
+    // Remove a movie from the index
+    fn remove(&mut self, id: Uuid) {
+        for term in self.terms.remove(&id).unwrap_or_default() {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.remove(&id);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    // Score the movies matching a single query token, either exactly or as a
+    // prefix of a longer term
+    fn score_token(&self, token: &str) -> HashMap<Uuid, f64> {
+        let documents = self.terms.len() as f64;
+        let mut scores: HashMap<Uuid, f64> = HashMap::new();
+
+        let matching = self
+            .postings
+            .range(token.to_string()..)
+            .take_while(|(term, _)| term.starts_with(token));
+        for (term, postings) in matching {
+            // Rare terms count more than common ones
+            let idf = (1.0 + documents / postings.len() as f64).ln();
+            let weight = match term == token {
+                true => 1.0,
+                false => PREFIX_WEIGHT,
+            };
+            for (id, frequency) in postings {
+                let score = frequency * idf * weight;
+                let best = scores.entry(*id).or_default();
+                *best = best.max(score);
+            }
+        }
+
+        scores
+    }
+}
+
+// Define the query parameters of the search endpoint
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+// Define the part of a field that matched the query, in characters
+#[derive(Debug, Serialize)]
+pub struct Highlight {
+    pub field: SearchField,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Define a single ranked search result
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub score: f64,
+    pub movie: MovieView,
+    pub highlights: Vec<Highlight>,
+}
+
+// Define an inverted index over the titles and director names of the movies
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    state: RwLock<IndexState>,
+}
+
+impl SearchIndex {
+    // Build an index holding the given movies, looking up their directors
+    pub fn build(movies: &[Movie], directors: &[Director]) -> Result<Self, RepositoryError> {
+        let directors: HashMap<Uuid, &Director> = directors.iter().map(|d| (d.id, d)).collect();
+        let mut state = IndexState::default();
+        for movie in movies {
+            let director = directors.get(&movie.director_id).ok_or_else(|| {
+                RepositoryError::Storage(format!(
+                    "movie {} refers to missing director {}",
+                    movie.id, movie.director_id
+                ))
+            })?;
+            state.add(movie, director);
+        }
+        Ok(Self {
+            state: RwLock::new(state),
+        })
+    }
+
+    // Return true if a panic while updating the index left its lock poisoned
+    fn is_poisoned(&self) -> bool {
+        self.state.is_poisoned()
+    }
+
+    // Lock the index for reading, reporting a poisoned lock as a storage error
+    fn read(&self) -> Result<RwLockReadGuard<'_, IndexState>, RepositoryError> {
+        self.state
+            .read()
+            .map_err(|_| RepositoryError::Storage("search index lock poisoned".to_string()))
+    }
+
+    // Lock the index for writing, reporting a poisoned lock as a storage error
+    fn write(&self) -> Result<RwLockWriteGuard<'_, IndexState>, RepositoryError> {
+        self.state
+            .write()
+            .map_err(|_| RepositoryError::Storage("search index lock poisoned".to_string()))
+    }
+
+    // Return the number of movies in the index. A panic while updating the
+    // index leaves this off by one at most, and the metrics it is exported to
+    // are most needed once something has gone wrong, so a poisoned lock is
+    // read anyway.
+    pub fn movie_count(&self) -> usize {
+        self.state
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .terms
+            .len()
+    }
+
+    // Index a new or changed movie
+    fn add(&self, movie: &Movie, director: &Director) -> Result<(), RepositoryError> {
+        self.write()?.add(movie, director);
+        Ok(())
+    }
+
+    // Remove a movie from the index
+    fn remove(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.write()?.remove(id);
+        Ok(())
+    }
+
+    // Return the IDs and scores of the movies matching every token of the
+    // query, best match first
+    pub fn search(&self, params: &SearchParams) -> Result<Vec<(Uuid, f64)>, RepositoryError> {
+        let tokens = tokenize(&params.q);
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let state = self.read()?;
+        let mut scores: Option<HashMap<Uuid, f64>> = None;
+        for token in &tokens {
+            let token_scores = state.score_token(&token.term);
+            // Keep only the movies that also matched the previous tokens
+            scores = Some(match scores {
+                None => token_scores,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(id, score)| Some((id, score + token_scores.get(&id)?)))
+                    .collect(),
+            });
+        }
+
+        let mut hits: Vec<(Uuid, f64)> = scores.unwrap_or_default().into_iter().collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT));
+        Ok(hits)
+    }
+}
+
+// Return the parts of a movie's searchable fields that match the query
+pub fn highlights(movie: &Movie, director: &Director, query: &str) -> Vec<Highlight> {
+    let query: Vec<String> = tokenize(query)
+        .into_iter()
+        .map(|token| token.term)
+        .collect();
+
+    SearchField::ALL
+        .into_iter()
+        .flat_map(|field| {
+            tokenize(field.value(movie, director))
+                .into_iter()
+                .filter(|token| query.iter().any(|q| token.term.starts_with(q.as_str())))
+                .map(move |token| Highlight {
+                    field,
+                    start: token.start,
+                    end: token.end,
+                })
+        })
+        .collect()
+}
+
+// Define a storage decorator that keeps a search index and the review totals
+// in step with every mutation of the wrapped backend
+pub struct IndexedMovieRepository {
+    inner: Arc<dyn Repository>,
+    index: Arc<SearchIndex>,
+    ratings: Arc<RatingIndex>,
+    // Serialise mutations so the index sees them in the same order as the store
+    writes: Mutex<()>,
+}
+
+impl IndexedMovieRepository {
+    // Wrap a backend, indexing the movies and reviews it already holds
+    pub fn new(inner: Arc<dyn Repository>) -> Result<Self, RepositoryError> {
+        let index = Arc::new(SearchIndex::build(
+            &inner.list()?,
+            &inner.list_directors()?,
+        )?);
+        let ratings = Arc::new(RatingIndex::build(&inner.list_reviews()?)?);
+        Ok(Self {
+            inner,
+            index,
+            ratings,
+            writes: Mutex::new(()),
+        })
+    }
+
+    // Return the search index maintained by the decorator
+    pub fn index(&self) -> Arc<SearchIndex> {
+        self.index.clone()
+    }
+
+    // Return the review totals maintained by the decorator
+    pub fn ratings(&self) -> Arc<RatingIndex> {
+        self.ratings.clone()
+    }
+
+    // Run a mutation while holding the write lock
+    fn write<T>(
+        &self,
+        f: impl FnOnce() -> Result<T, RepositoryError>,
+    ) -> Result<T, RepositoryError> {
+        let _guard = metrics::lock("index_writes", &self.writes)
+            .map_err(|_| RepositoryError::Storage("search index lock poisoned".to_string()))?;
+        f()
+    }
+
+    // Report a failure to update the review totals after a write has already
+    // stored the review, which leaves them off until the server restarts
+    fn count_review(&self, counted: Result<(), RepositoryError>, review: &Review) {
+        if let Err(err) = counted {
+            tracing::error!(error = %err, review = %review.id, "cannot update the ratings");
+        }
+    }
+
+    // Index a movie a write has already stored, given the result of looking
+    // up its director beforehand. The write cannot be undone by then, so a
+    // failure is only reported, leaving the movie out of search results.
+    fn index_movie(&self, movie: &Movie, director: Result<Director, RepositoryError>) {
+        if let Err(err) = director.and_then(|director| self.index.add(movie, &director)) {
+            tracing::error!(error = %err, movie = %movie.id, "cannot index movie");
+        }
+    }
+}
+
+impl MovieRepository for IndexedMovieRepository {
+    fn list(&self) -> Result<Vec<Movie>, RepositoryError> {
+        self.inner.list()
+    }
+
+    fn get(&self, id: Uuid) -> Result<Movie, RepositoryError> {
+        self.inner.get(id)
+    }
+
+    fn insert(&self, movie: Movie) -> Result<Movie, RepositoryError> {
+        self.write(|| {
+            // Look up the director first, as a failure after the write would
+            // report an error for a movie that was stored. A missing director
+            // fails the write itself.
+            let director = self.inner.get_director(movie.director_id);
+            let movie = self.inner.insert(movie)?;
+            self.index_movie(&movie, director);
+            Ok(movie)
+        })
+    }
+
+    fn update(
+        &self,
+        movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, RepositoryError> {
+        self.write(|| {
+            let director = self.inner.get_director(movie.director_id);
+            let movie = self.inner.update(movie, expected_version)?;
+            self.index_movie(&movie, director);
+            Ok(movie)
+        })
+    }
+
+    fn delete(&self, id: Uuid, expected_version: Option<u64>) -> Result<(), RepositoryError> {
+        self.write(|| {
+            self.inner.delete(id, expected_version)?;
+            if let Err(err) = self.index.remove(id) {
+                tracing::error!(error = %err, movie = %id, "cannot remove movie from the index");
+            }
+            // The movie's reviews went with it
+            if let Err(err) = self.ratings.remove_movie(id) {
+                tracing::error!(error = %err, movie = %id, "cannot remove the reviews of a movie from the ratings");
+            }
+            Ok(())
+        })
+    }
+
+    fn check_integrity(&self) -> Result<(), RepositoryError> {
+        self.inner.check_integrity()
+    }
+
+    fn ping(&self) -> Result<(), RepositoryError> {
+        self.inner.ping()
+    }
+
+    fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+            || self.writes.is_poisoned()
+            || self.index.is_poisoned()
+            || self.ratings.is_poisoned()
+    }
+
+    fn flush(&self) -> Result<(), RepositoryError> {
+        // Wait for the mutation in progress, if any
+        self.write(|| self.inner.flush())
+    }
+}
+
+impl DirectorRepository for IndexedMovieRepository {
+    fn list_directors(&self) -> Result<Vec<Director>, RepositoryError> {
+        self.inner.list_directors()
+    }
+
+    fn get_director(&self, id: Uuid) -> Result<Director, RepositoryError> {
+        self.inner.get_director(id)
+    }
+
+    fn insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        self.write(|| self.inner.insert_director(director))
+    }
+
+    fn update_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        self.write(|| {
+            // List the movies first, so that the rename is not reported as
+            // failed once it is stored
+            let movies = self.inner.list();
+            let director = self.inner.update_director(director)?;
+
+            // Reindex the director's movies under the new name
+            match movies {
+                Ok(movies) => movies
+                    .iter()
+                    .filter(|movie| movie.director_id == director.id)
+                    .for_each(|movie| self.index_movie(movie, Ok(director.clone()))),
+                Err(err) => tracing::error!(
+                    error = %err,
+                    director = %director.id,
+                    "cannot reindex the movies of a renamed director"
+                ),
+            }
+            Ok(director)
+        })
+    }
+
+    fn delete_director(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.write(|| self.inner.delete_director(id))
+    }
+
+    fn find_or_insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        self.write(|| self.inner.find_or_insert_director(director))
+    }
+}
+
+// People and credits are not searchable, so they are passed straight through
+impl PersonRepository for IndexedMovieRepository {
+    fn list_people(&self) -> Result<Vec<Person>, RepositoryError> {
+        self.inner.list_people()
+    }
+
+    fn get_person(&self, id: Uuid) -> Result<Person, RepositoryError> {
+        self.inner.get_person(id)
+    }
+
+    fn insert_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        self.inner.insert_person(person)
+    }
+
+    fn update_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        self.inner.update_person(person)
+    }
+
+    fn delete_person(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.inner.delete_person(id)
+    }
+}
+
+impl CreditRepository for IndexedMovieRepository {
+    fn movie_credits(&self, movie_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        self.inner.movie_credits(movie_id)
+    }
+
+    fn person_credits(&self, person_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        self.inner.person_credits(person_id)
+    }
+
+    fn get_credit(&self, id: Uuid) -> Result<Credit, RepositoryError> {
+        self.inner.get_credit(id)
+    }
+
+    fn insert_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        self.inner.insert_credit(credit)
+    }
+
+    fn update_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        self.inner.update_credit(credit)
+    }
+
+    fn delete_credit(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.inner.delete_credit(id)
+    }
+}
+
+impl ReviewRepository for IndexedMovieRepository {
+    fn list_reviews(&self) -> Result<Vec<Review>, RepositoryError> {
+        self.inner.list_reviews()
+    }
+
+    fn movie_reviews(&self, movie_id: Uuid) -> Result<Vec<Review>, RepositoryError> {
+        self.inner.movie_reviews(movie_id)
+    }
+
+    fn get_review(&self, id: Uuid) -> Result<Review, RepositoryError> {
+        self.inner.get_review(id)
+    }
+
+    fn insert_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        self.write(|| {
+            let review = self.inner.insert_review(review)?;
+            self.count_review(self.ratings.add(&review), &review);
+            Ok(review)
+        })
+    }
+
+    fn update_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        self.write(|| {
+            // Look up the old score while no other write can change it
+            let old = self.inner.get_review(review.id)?;
+            let review = self.inner.update_review(review)?;
+            self.count_review(self.ratings.replace(&old, &review), &review);
+            Ok(review)
+        })
+    }
+
+    fn delete_review(&self, id: Uuid) -> Result<(), RepositoryError> {
+        self.write(|| {
+            let old = self.inner.get_review(id)?;
+            self.inner.delete_review(id)?;
+            self.count_review(self.ratings.remove(&old), &old);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{initial_version, MovieMetadata};
+    use crate::repository::InMemoryMovieRepository;
+
+    fn terms(text: &str) -> Vec<String> {
+        tokenize(text).into_iter().map(|token| token.term).collect()
+    }
+
+    fn director(firstname: &str, lastname: &str) -> Director {
+        Director {
+            id: Uuid::new_v4(),
+            firstname: firstname.to_string(),
+            lastname: lastname.to_string(),
+        }
+    }
+
+    fn movie(title: &str, director: &Director) -> Movie {
+        Movie {
+            id: Uuid::new_v4(),
+            isbn: "978-0-261-10235-4".to_string(),
+            title: title.to_string(),
+            director_id: director.id,
+            metadata: MovieMetadata::default(),
+            version: initial_version(),
+        }
+    }
+
+    fn query(q: &str) -> SearchParams {
+        SearchParams {
+            q: q.to_string(),
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn fold_lowercases_and_strips_diacritics() {
+        assert_eq!(fold("Amélie"), "amelie");
+        assert_eq!(fold("AMELIE"), "amelie");
+        assert_eq!(fold("Ångström"), "angstrom");
+        assert_eq!(fold("日本"), "日本");
+    }
+
+    #[test]
+    fn tokenize_splits_on_non_alphanumerics() {
+        assert_eq!(
+            terms("The Lord of the Rings: The Two-Towers"),
+            vec!["the", "lord", "of", "the", "rings", "the", "two", "towers"]
+        );
+        assert_eq!(
+            terms("2001: A Space Odyssey"),
+            vec!["2001", "a", "space", "odyssey"]
+        );
+        assert!(terms(" -- ").is_empty());
+        assert!(terms("").is_empty());
+    }
+
+    #[test]
+    fn tokenize_reports_character_positions() {
+        let tokens = tokenize("Le Fabuleux Destin d'Amélie");
+        let last = tokens.last().unwrap();
+        assert_eq!(last.term, "amelie");
+        assert_eq!((last.start, last.end), (21, 27));
+        let first = &tokens[0];
+        assert_eq!((first.start, first.end), (0, 2));
+    }
+
+    #[test]
+    fn search_matches_every_token_and_prefixes() {
+        let jackson = director("Peter", "Jackson");
+        let jeunet = director("Jean-Pierre", "Jeunet");
+        let fellowship = movie("The Fellowship of the Ring", &jackson);
+        let amelie = movie("Le Fabuleux Destin d'Amélie Poulain", &jeunet);
+        let index =
+            SearchIndex::build(&[fellowship.clone(), amelie.clone()], &[jackson, jeunet]).unwrap();
+
+        let ids = |q: &str| -> Vec<Uuid> {
+            let hits = index.search(&query(q)).unwrap();
+            hits.into_iter().map(|(id, _)| id).collect()
+        };
+        assert_eq!(ids("amelie"), vec![amelie.id]);
+        assert_eq!(ids("fellow jackson"), vec![fellowship.id]);
+        assert!(ids("fellowship jeunet").is_empty());
+        assert!(ids("").is_empty());
+        assert_eq!(index.movie_count(), 2);
+    }
+
+    #[test]
+    fn poisoned_index_reports_errors() {
+        let index = SearchIndex::default();
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = index.state.write().unwrap();
+            panic!("poison the index");
+        });
+
+        assert!(index.is_poisoned());
+        assert!(index.search(&query("ring")).is_err());
+        assert_eq!(index.movie_count(), 0);
+    }
+
+    #[test]
+    fn failed_index_update_does_not_fail_the_write() {
+        let inner: Arc<dyn Repository> = Arc::new(InMemoryMovieRepository::new());
+        let repository = IndexedMovieRepository::new(inner).unwrap();
+        let jackson = repository
+            .insert_director(director("Peter", "Jackson"))
+            .unwrap();
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = repository.index.state.write().unwrap();
+            panic!("poison the index");
+        });
+
+        let stored = repository
+            .insert(movie("The Two Towers", &jackson))
+            .unwrap();
+        assert_eq!(repository.get(stored.id).unwrap().title, "The Two Towers");
+    }
+}
@@ -0,0 +1,361 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::models::{MovieMetadata, NewDirector, NewMovie};
+use crate::repository::{Repository, RepositoryError};
+use crate::validation::Validate;
+
+// Define the errors that can occur while loading a fixture file
+#[derive(Debug)]
+pub enum SeedError {
+    // The fixture file could not be read
+    Io(io::Error),
+    // The fixture file could not be parsed
+    Parse(String),
+    // A record in the fixture file is invalid
+    Invalid { record: usize, reason: String },
+    // The fixture could not be stored
+    Repository(RepositoryError),
+}
+
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeedError::Io(err) => write!(f, "cannot read seed file: {}", err),
+            SeedError::Parse(msg) => write!(f, "cannot parse seed file: {}", msg),
+            SeedError::Invalid { record, reason } => {
+                write!(f, "invalid seed record #{}: {}", record, reason)
+            }
+            SeedError::Repository(err) => write!(f, "cannot store seed movie: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SeedError {}
+
+// Define a fixture movie, which names its director instead of referring to it
+// by ID so that fixtures can be loaded into any store
+#[derive(Debug, Deserialize)]
+pub struct SeedMovie {
+    id: Uuid,
+    isbn: String,
+    title: String,
+    director: NewDirector,
+    #[serde(flatten)]
+    metadata: MovieMetadata,
+}
+
+// Define the flat row layout of CSV fixtures
+#[derive(Debug, Deserialize)]
+struct CsvMovie {
+    id: Uuid,
+    isbn: String,
+    title: String,
+    director_firstname: String,
+    director_lastname: String,
+    // The descriptive columns may be left out or empty
+    #[serde(default)]
+    release_date: Option<String>,
+    #[serde(default)]
+    runtime_minutes: Option<u32>,
+    // Genres separated by `|`, such as `drama|fantasy`
+    #[serde(default)]
+    genres: Option<String>,
+    #[serde(default)]
+    original_language: Option<String>,
+    #[serde(default)]
+    country: Option<String>,
+    #[serde(default)]
+    certification: Option<String>,
+    #[serde(default)]
+    synopsis: Option<String>,
+}
+
+impl From<CsvMovie> for SeedMovie {
+    fn from(row: CsvMovie) -> Self {
+        SeedMovie {
+            id: row.id,
+            isbn: row.isbn,
+            title: row.title,
+            director: NewDirector {
+                firstname: row.director_firstname,
+                lastname: row.director_lastname,
+            },
+            metadata: MovieMetadata {
+                release_date: row.release_date,
+                runtime_minutes: row.runtime_minutes,
+                genres: row
+                    .genres
+                    .unwrap_or_default()
+                    .split('|')
+                    .filter(|genre| !genre.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                original_language: row.original_language,
+                country: row.country,
+                certification: row.certification,
+                synopsis: row.synopsis,
+            },
+        }
+    }
+}
+
+impl SeedMovie {
+    // Turn the fixture into a request body, so it is validated like one
+    fn to_new_movie(&self) -> NewMovie {
+        NewMovie {
+            id: None,
+            isbn: self.isbn.clone(),
+            title: self.title.clone(),
+            director_id: None,
+            director: Some(self.director.clone()),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+// Read the movies from a JSON or CSV fixture file, picked by its extension
+pub fn load(path: &Path) -> Result<Vec<SeedMovie>, SeedError> {
+    let file = File::open(path).map_err(SeedError::Io)?;
+    let reader = BufReader::new(file);
+
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let movies = if is_csv {
+        csv::Reader::from_reader(reader)
+            .deserialize::<CsvMovie>()
+            .map(|row| row.map(SeedMovie::from))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| SeedError::Parse(err.to_string()))?
+    } else {
+        serde_json::from_reader(reader).map_err(|err| SeedError::Parse(err.to_string()))?
+    };
+
+    validate(&movies)?;
+    Ok(movies)
+}
+
+// Check that every fixture record is valid and that no ID is used twice
+fn validate(movies: &[SeedMovie]) -> Result<(), SeedError> {
+    let mut ids = HashSet::new();
+
+    for (index, movie) in movies.iter().enumerate() {
+        let invalid = |reason: String| SeedError::Invalid {
+            record: index + 1,
+            reason,
+        };
+
+        if !ids.insert(movie.id) {
+            return Err(invalid(format!("duplicate id {}", movie.id)));
+        }
+
+        movie
+            .to_new_movie()
+            .validate()
+            .map_err(|errors| invalid(errors.to_string()))?;
+    }
+
+    Ok(())
+}
+
+// Insert the fixture movies under their own IDs, skipping those already stored.
+// Directors are looked up by name and created when missing.
+pub fn seed(repository: &dyn Repository, movies: Vec<SeedMovie>) -> Result<usize, SeedError> {
+    let mut inserted = 0;
+
+    for movie in movies {
+        let id = movie.id;
+        // Skip a stored movie before its director could be created again
+        match repository.get(id) {
+            Ok(_) => continue,
+            Err(RepositoryError::NotFound(..)) => {}
+            Err(err) => return Err(SeedError::Repository(err)),
+        }
+
+        let new_movie = movie.to_new_movie();
+        let proposed = Uuid::new_v4();
+        let director = repository
+            .find_or_insert_director(movie.director.into_director(proposed))
+            .map_err(SeedError::Repository)?;
+
+        match repository.insert(new_movie.into_movie(id, director.id)) {
+            Ok(_) => inserted += 1,
+            Err(err) => {
+                // Remove a director created for the movie, so that a refused
+                // movie leaves no director behind
+                if director.id == proposed {
+                    if let Err(err) = repository.delete_director(director.id) {
+                        tracing::warn!(error = %err, "cannot remove the director of a refused seed movie");
+                    }
+                }
+                match err {
+                    RepositoryError::AlreadyExists(..) => {}
+                    err => return Err(SeedError::Repository(err)),
+                }
+            }
+        }
+    }
+
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{DirectorRepository, InMemoryMovieRepository, MovieRepository};
+    use crate::sqlite::SqliteMovieRepository;
+    use std::path::PathBuf;
+
+    const LOTR: &str = "6f1d3a52-2f6c-4f0a-9d8e-1b2f4c5a7e01";
+    const HITCHHIKER: &str = "0c9e8b7a-4d3f-4e21-8a6b-5c4d3e2f1a02";
+
+    // Write a fixture to a file with the given extension
+    fn fixture(extension: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("seed-test-{}.{}", Uuid::new_v4(), extension));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn load_fixture(extension: &str, content: &str) -> Result<Vec<SeedMovie>, SeedError> {
+        let path = fixture(extension, content);
+        let result = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn json_movie(id: &str, title: &str) -> String {
+        format!(
+            r#"{{"id": "{}", "isbn": "978-3-16-148410-0", "title": "{}",
+                "director": {{"firstname": "Peter", "lastname": "Jackson"}}}}"#,
+            id, title
+        )
+    }
+
+    #[test]
+    fn json_fixtures_are_stored_under_their_own_ids() {
+        let movies = load_fixture(
+            "json",
+            &format!(
+                "[{}, {}]",
+                json_movie(LOTR, "The Lord of the Rings"),
+                json_movie(HITCHHIKER, "The Two Towers")
+            ),
+        )
+        .unwrap();
+        let repository = InMemoryMovieRepository::new();
+        assert_eq!(seed(&repository, movies).unwrap(), 2);
+
+        // Both movies share the director they name
+        let lotr = repository.get(Uuid::parse_str(LOTR).unwrap()).unwrap();
+        let towers = repository
+            .get(Uuid::parse_str(HITCHHIKER).unwrap())
+            .unwrap();
+        assert_eq!(lotr.title, "The Lord of the Rings");
+        assert_eq!(lotr.director_id, towers.director_id);
+        assert_eq!(repository.list_directors().unwrap().len(), 1);
+        repository.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn csv_fixtures_are_stored_with_their_metadata() {
+        let movies = load_fixture(
+            "csv",
+            &format!(
+                "id,isbn,title,director_firstname,director_lastname,runtime_minutes,genres,country\n\
+                 {},978-3-16-148410-0,The Lord of the Rings,Peter,Jackson,178,adventure|fantasy,NZ\n\
+                 {},978-0-345-39180-3,The Hitchhiker's Guide to the Galaxy,Garth,Jennings,,,\n",
+                LOTR, HITCHHIKER
+            ),
+        )
+        .unwrap();
+        let repository = InMemoryMovieRepository::new();
+        assert_eq!(seed(&repository, movies).unwrap(), 2);
+
+        let lotr = repository.get(Uuid::parse_str(LOTR).unwrap()).unwrap();
+        assert_eq!(lotr.metadata.runtime_minutes, Some(178));
+        assert_eq!(lotr.metadata.genres, ["adventure", "fantasy"]);
+        assert_eq!(lotr.metadata.country.as_deref(), Some("NZ"));
+        let hitchhiker = repository
+            .get(Uuid::parse_str(HITCHHIKER).unwrap())
+            .unwrap();
+        assert_eq!(hitchhiker.metadata, MovieMetadata::default());
+        assert_eq!(repository.list_directors().unwrap().len(), 2);
+        repository.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn seeding_again_skips_stored_movies() {
+        let repository = InMemoryMovieRepository::new();
+        let movies = || load_fixture("json", &format!("[{}]", json_movie(LOTR, "Heat"))).unwrap();
+        assert_eq!(seed(&repository, movies()).unwrap(), 1);
+        assert_eq!(seed(&repository, movies()).unwrap(), 0);
+        assert_eq!(repository.list().unwrap().len(), 1);
+        assert_eq!(repository.list_directors().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn bundled_fixtures_load() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        for name in ["movies.json", "movies.csv"] {
+            let movies = load(&dir.join(name)).unwrap();
+            assert!(!movies.is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn duplicate_ids_are_refused() {
+        let result = load_fixture(
+            "json",
+            &format!(
+                "[{}, {}]",
+                json_movie(LOTR, "Heat"),
+                json_movie(LOTR, "Ronin")
+            ),
+        );
+        assert!(matches!(result, Err(SeedError::Invalid { record: 2, .. })));
+    }
+
+    #[test]
+    fn invalid_records_are_refused() {
+        let result = load_fixture(
+            "json",
+            &format!(
+                "[{}, {}]",
+                json_movie(LOTR, "Heat"),
+                json_movie(HITCHHIKER, "")
+            ),
+        );
+        assert!(matches!(result, Err(SeedError::Invalid { record: 2, .. })));
+
+        let result = load_fixture("csv", "id,isbn,title\nnot-a-uuid,978-3-16-148410-0,Heat\n");
+        assert!(matches!(result, Err(SeedError::Parse(_))));
+    }
+
+    #[test]
+    fn refused_movies_leave_no_director_behind() {
+        let path = fixture("db", "");
+        let repository = SqliteMovieRepository::open(&path).unwrap();
+        // Refuse every movie with a storage error
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "ALTER TABLE movies RENAME TO stored_movies;
+                 CREATE VIEW movies AS SELECT * FROM stored_movies;",
+            )
+            .unwrap();
+
+        let movies = load_fixture("json", &format!("[{}]", json_movie(LOTR, "Heat"))).unwrap();
+        assert!(matches!(
+            seed(&repository, movies),
+            Err(SeedError::Repository(_))
+        ));
+        assert!(repository.list_directors().unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
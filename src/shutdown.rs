@@ -0,0 +1,193 @@
+use actix_web::dev::ServerHandle;
+use actix_web::rt::signal::unix::{signal, Signal, SignalKind};
+use actix_web::rt::time::sleep;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+// Exit status when requests were still running as the server stopped
+pub const EXIT_REQUESTS_CUT_OFF: u8 = 3;
+// Exit status when the store could not be flushed, so writes may be lost
+pub const EXIT_FLUSH_FAILED: u8 = 4;
+
+// Time between checks for the requests in progress while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Define the count of requests in progress, which draining waits for. The
+// server counts them itself rather than relying on the metrics, so that
+// shutdown keeps working whatever the metrics middleware does.
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    // Count a request as in progress until the returned guard is dropped, when
+    // it completes or its connection is
+    pub fn start(&self) -> Tracked {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Tracked(self.0.clone())
+    }
+
+    // Return the number of requests in progress
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Define a request counted as in progress
+pub struct Tracked(Arc<AtomicUsize>);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Define the signals that stop the server
+pub struct Signals {
+    terminate: Signal,
+    interrupt: Signal,
+}
+
+impl Signals {
+    // Start listening for SIGTERM and SIGINT, which from then on no longer kill
+    // the process outright
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    // Wait for the next signal, returning its name
+    pub async fn recv(&mut self) -> &'static str {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<&'static str> {
+        if self.terminate.poll_recv(cx).is_ready() {
+            return Poll::Ready("SIGTERM");
+        }
+        if self.interrupt.poll_recv(cx).is_ready() {
+            return Poll::Ready("SIGINT");
+        }
+        Poll::Pending
+    }
+}
+
+// Stop the server from accepting connections and wait for the requests in
+// progress, then close the connections left open. Requests still running once
+// the timeout passes or on a second signal are cut off. Returns true if every
+// request finished.
+pub async fn drain(
+    server: ServerHandle,
+    mut signals: Signals,
+    in_flight: &InFlight,
+    timeout: Duration,
+) -> bool {
+    server.pause().await;
+
+    // Idle keep-alive connections would hold up the server's own graceful
+    // stop until the timeout, so wait for the requests rather than for them
+    let drained = wait(in_flight, timeout, signals.recv()).await;
+
+    server.stop(false).await;
+    drained
+}
+
+// Wait for the requests in progress to finish, returning false if the timeout
+// passes or the cut-off signal arrives first
+async fn wait(
+    in_flight: &InFlight,
+    timeout: Duration,
+    cut_off: impl Future<Output = &'static str>,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut cut_off = pin!(cut_off);
+    loop {
+        if in_flight.count() == 0 {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+
+        let mut tick = pin!(sleep(DRAIN_POLL_INTERVAL));
+        let signal = poll_fn(|cx| match cut_off.as_mut().poll(cx) {
+            Poll::Ready(name) => Poll::Ready(Some(name)),
+            Poll::Pending => tick.as_mut().poll(cx).map(|()| None),
+        })
+        .await;
+        if let Some(name) = signal {
+            tracing::warn!(
+                "Received {} while draining, stopping without waiting for requests",
+                name
+            );
+            return false;
+        }
+    }
+}
+
+// Return the exit status of the process once the server stopped, given whether
+// every request finished and whether the store was flushed
+pub fn exit_status(drained: bool, flushed: bool) -> u8 {
+    match (flushed, drained) {
+        (false, _) => EXIT_FLUSH_FAILED,
+        (true, false) => EXIT_REQUESTS_CUT_OFF,
+        (true, true) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::{pending, ready};
+
+    #[actix_web::test]
+    async fn wait_returns_at_once_without_requests() {
+        let in_flight = InFlight::default();
+        assert!(wait(&in_flight, Duration::ZERO, pending()).await);
+    }
+
+    #[actix_web::test]
+    async fn wait_returns_once_the_requests_finish() {
+        let in_flight = InFlight::default();
+        let request = in_flight.start();
+        assert_eq!(in_flight.count(), 1);
+        actix_web::rt::spawn(async move {
+            sleep(Duration::from_millis(120)).await;
+            drop(request);
+        });
+        assert!(wait(&in_flight, Duration::from_secs(10), pending()).await);
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn wait_gives_up_at_the_timeout() {
+        let in_flight = InFlight::default();
+        let _request = in_flight.start();
+        let start = Instant::now();
+        assert!(!wait(&in_flight, Duration::from_millis(150), pending()).await);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[actix_web::test]
+    async fn wait_stops_on_a_second_signal() {
+        let in_flight = InFlight::default();
+        let _request = in_flight.start();
+        let start = Instant::now();
+        assert!(!wait(&in_flight, Duration::from_secs(10), ready("SIGTERM")).await);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn exit_status_reports_lost_writes_before_cut_off_requests() {
+        assert_eq!(exit_status(true, true), 0);
+        assert_eq!(exit_status(false, true), EXIT_REQUESTS_CUT_OFF);
+        assert_eq!(exit_status(true, false), EXIT_FLUSH_FAILED);
+        assert_eq!(exit_status(false, false), EXIT_FLUSH_FAILED);
+    }
+}
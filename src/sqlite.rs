@@ -0,0 +1,1172 @@
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::metrics;
+use crate::models::{Credit, Director, Movie, MovieMetadata, Person, Review, Role};
+use crate::repository::{
+    check_version, CreditRepository, DirectorRepository, Entity, MovieRepository, PersonRepository,
+    RepositoryError, ReviewRepository,
+};
+
+// Define the schema migrations, applied in order and tracked with PRAGMA user_version
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE movies (
+        id TEXT PRIMARY KEY NOT NULL,
+        isbn TEXT NOT NULL,
+        title TEXT NOT NULL,
+        director_firstname TEXT NOT NULL,
+        director_lastname TEXT NOT NULL
+    );",
+    "ALTER TABLE movies ADD COLUMN version INTEGER NOT NULL DEFAULT 1;",
+    // Move the embedded director names into their own table, generating a
+    // random (version 4) UUID for every distinct name
+    "CREATE TABLE directors (
+        id TEXT PRIMARY KEY NOT NULL,
+        firstname TEXT NOT NULL,
+        lastname TEXT NOT NULL
+    );
+    INSERT INTO directors (id, firstname, lastname)
+    SELECT lower(
+               hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+               substr(hex(randomblob(2)), 2) || '-' ||
+               substr('89ab', 1 + abs(random()) % 4, 1) ||
+               substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+           ),
+           director_firstname, director_lastname
+    FROM (SELECT DISTINCT director_firstname, director_lastname FROM movies);
+    CREATE TABLE movies_new (
+        id TEXT PRIMARY KEY NOT NULL,
+        isbn TEXT NOT NULL,
+        title TEXT NOT NULL,
+        director_id TEXT NOT NULL REFERENCES directors (id),
+        version INTEGER NOT NULL DEFAULT 1
+    );
+    INSERT INTO movies_new (id, isbn, title, director_id, version)
+    SELECT movies.id, movies.isbn, movies.title, directors.id, movies.version
+    FROM movies JOIN directors
+        ON directors.firstname = movies.director_firstname
+        AND directors.lastname = movies.director_lastname;
+    DROP TABLE movies;
+    ALTER TABLE movies_new RENAME TO movies;
+    CREATE INDEX movies_director_id ON movies (director_id);",
+    "CREATE TABLE people (
+        id TEXT PRIMARY KEY NOT NULL,
+        firstname TEXT NOT NULL,
+        lastname TEXT NOT NULL
+    );
+    CREATE TABLE credits (
+        id TEXT PRIMARY KEY NOT NULL,
+        movie_id TEXT NOT NULL REFERENCES movies (id) ON DELETE CASCADE,
+        person_id TEXT NOT NULL REFERENCES people (id),
+        role TEXT NOT NULL,
+        character TEXT,
+        billing INTEGER NOT NULL
+    );
+    CREATE INDEX credits_movie_id ON credits (movie_id);
+    CREATE INDEX credits_person_id ON credits (person_id);",
+    // The genres are stored as a JSON array
+    "ALTER TABLE movies ADD COLUMN release_date TEXT;
+    ALTER TABLE movies ADD COLUMN runtime_minutes INTEGER;
+    ALTER TABLE movies ADD COLUMN genres TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE movies ADD COLUMN original_language TEXT;
+    ALTER TABLE movies ADD COLUMN country TEXT;
+    ALTER TABLE movies ADD COLUMN certification TEXT;
+    ALTER TABLE movies ADD COLUMN synopsis TEXT;",
+    // The timestamps are stored as RFC 3339 text
+    "CREATE TABLE reviews (
+        id TEXT PRIMARY KEY NOT NULL,
+        movie_id TEXT NOT NULL REFERENCES movies (id) ON DELETE CASCADE,
+        author TEXT NOT NULL,
+        score INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        token_hash TEXT NOT NULL
+    );
+    CREATE INDEX reviews_movie_id ON reviews (movie_id);",
+    // Reviews belong to the client who posted them, named as their author,
+    // rather than to whoever holds an edit token
+    "ALTER TABLE reviews DROP COLUMN token_hash;",
+];
+
+impl From<rusqlite::Error> for RepositoryError {
+    fn from(err: rusqlite::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+// Define an SQLite backend that persists the movies in a database file
+pub struct SqliteMovieRepository {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteMovieRepository {
+    // Open (or create) the database file and bring its schema up to date
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RepositoryError> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        // Let SQLite enforce the references between the tables as well
+        conn.pragma_update(None, "foreign_keys", true)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // Lock the connection, reporting a poisoned Mutex as a storage error
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, RepositoryError> {
+        metrics::lock("sqlite", &self.conn)
+            .map_err(|_| RepositoryError::Storage("database lock poisoned".to_string()))
+    }
+}
+
+// Apply every migration newer than the version recorded in the database
+fn migrate(conn: &mut Connection) -> Result<(), RepositoryError> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        // Run each migration and its version bump in a single transaction
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+// Read a UUID from a text column
+fn uuid_from_row(row: &Row<'_>, column: &str) -> rusqlite::Result<Uuid> {
+    let id: String = row.get(column)?;
+    Uuid::parse_str(&id).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+// Build a Movie from a row of the movies table
+fn movie_from_row(row: &Row<'_>) -> rusqlite::Result<Movie> {
+    let genres: String = row.get("genres")?;
+    let genres = serde_json::from_str(&genres).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+    })?;
+
+    Ok(Movie {
+        id: uuid_from_row(row, "id")?,
+        isbn: row.get("isbn")?,
+        title: row.get("title")?,
+        director_id: uuid_from_row(row, "director_id")?,
+        metadata: MovieMetadata {
+            release_date: row.get("release_date")?,
+            runtime_minutes: row.get("runtime_minutes")?,
+            genres,
+            original_language: row.get("original_language")?,
+            country: row.get("country")?,
+            certification: row.get("certification")?,
+            synopsis: row.get("synopsis")?,
+        },
+        version: row.get("version")?,
+    })
+}
+
+// Build a Director from a row of the directors table
+fn director_from_row(row: &Row<'_>) -> rusqlite::Result<Director> {
+    Ok(Director {
+        id: uuid_from_row(row, "id")?,
+        firstname: row.get("firstname")?,
+        lastname: row.get("lastname")?,
+    })
+}
+
+// Build a Person from a row of the people table
+fn person_from_row(row: &Row<'_>) -> rusqlite::Result<Person> {
+    Ok(Person {
+        id: uuid_from_row(row, "id")?,
+        firstname: row.get("firstname")?,
+        lastname: row.get("lastname")?,
+    })
+}
+
+// Build a Credit from a row of the credits table
+fn credit_from_row(row: &Row<'_>) -> rusqlite::Result<Credit> {
+    let role: String = row.get("role")?;
+    let role = Role::parse(&role).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(
+            0,
+            rusqlite::types::Type::Text,
+            format!("unknown role '{}'", role).into(),
+        )
+    })?;
+
+    Ok(Credit {
+        id: uuid_from_row(row, "id")?,
+        movie_id: uuid_from_row(row, "movie_id")?,
+        person_id: uuid_from_row(row, "person_id")?,
+        role,
+        character: row.get("character")?,
+        billing: row.get("billing")?,
+    })
+}
+
+// Read an RFC 3339 timestamp from a text column
+fn timestamp_from_row(row: &Row<'_>, column: &str) -> rusqlite::Result<OffsetDateTime> {
+    let timestamp: String = row.get(column)?;
+    OffsetDateTime::parse(&timestamp, &Rfc3339).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+// Format a timestamp for storage
+fn format_timestamp(timestamp: OffsetDateTime) -> Result<String, RepositoryError> {
+    timestamp
+        .format(&Rfc3339)
+        .map_err(|err| RepositoryError::Storage(err.to_string()))
+}
+
+// Build a Review from a row of the reviews table
+fn review_from_row(row: &Row<'_>) -> rusqlite::Result<Review> {
+    Ok(Review {
+        id: uuid_from_row(row, "id")?,
+        movie_id: uuid_from_row(row, "movie_id")?,
+        author: row.get("author")?,
+        score: row.get("score")?,
+        text: row.get("text")?,
+        created_at: timestamp_from_row(row, "created_at")?,
+        updated_at: timestamp_from_row(row, "updated_at")?,
+    })
+}
+
+// Load every row of a table
+fn load_all<T>(
+    conn: &Connection,
+    table: &str,
+    from_row: fn(&Row<'_>) -> rusqlite::Result<T>,
+) -> Result<Vec<T>, RepositoryError> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
+    let rows = stmt
+        .query_map([], from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+// Load a single movie by ID
+fn get_movie(conn: &Connection, id: Uuid) -> Result<Movie, RepositoryError> {
+    conn.query_row(
+        "SELECT * FROM movies WHERE id = ?1",
+        params![id.to_string()],
+        movie_from_row,
+    )
+    .optional()?
+    .ok_or(RepositoryError::NotFound(Entity::Movie, id))
+}
+
+// Load a single director by ID
+fn get_director(conn: &Connection, id: Uuid) -> Result<Director, RepositoryError> {
+    conn.query_row(
+        "SELECT * FROM directors WHERE id = ?1",
+        params![id.to_string()],
+        director_from_row,
+    )
+    .optional()?
+    .ok_or(RepositoryError::NotFound(Entity::Director, id))
+}
+
+// Load a single person by ID
+fn get_person(conn: &Connection, id: Uuid) -> Result<Person, RepositoryError> {
+    conn.query_row(
+        "SELECT * FROM people WHERE id = ?1",
+        params![id.to_string()],
+        person_from_row,
+    )
+    .optional()?
+    .ok_or(RepositoryError::NotFound(Entity::Person, id))
+}
+
+// Load a single credit by ID
+fn get_credit(conn: &Connection, id: Uuid) -> Result<Credit, RepositoryError> {
+    conn.query_row(
+        "SELECT * FROM credits WHERE id = ?1",
+        params![id.to_string()],
+        credit_from_row,
+    )
+    .optional()?
+    .ok_or(RepositoryError::NotFound(Entity::Credit, id))
+}
+
+// Load the credits whose given column holds the given ID
+fn credits_by(conn: &Connection, column: &str, id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM credits WHERE {} = ?1", column))?;
+    let credits = stmt
+        .query_map(params![id.to_string()], credit_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(credits)
+}
+
+// Check that a credit refers to an existing movie and person
+fn check_credit(conn: &Connection, credit: &Credit) -> Result<(), RepositoryError> {
+    match get_movie(conn, credit.movie_id) {
+        Err(RepositoryError::NotFound(..)) => {
+            return Err(RepositoryError::InvalidReference(
+                Entity::Movie,
+                credit.movie_id,
+            ))
+        }
+        result => result.map(|_| ())?,
+    }
+    match get_person(conn, credit.person_id) {
+        Err(RepositoryError::NotFound(..)) => Err(RepositoryError::InvalidReference(
+            Entity::Person,
+            credit.person_id,
+        )),
+        result => result.map(|_| ()),
+    }
+}
+
+// Load a single review by ID
+fn get_review(conn: &Connection, id: Uuid) -> Result<Review, RepositoryError> {
+    conn.query_row(
+        "SELECT * FROM reviews WHERE id = ?1",
+        params![id.to_string()],
+        review_from_row,
+    )
+    .optional()?
+    .ok_or(RepositoryError::NotFound(Entity::Review, id))
+}
+
+// Check that a review refers to an existing movie
+fn check_review(conn: &Connection, review: &Review) -> Result<(), RepositoryError> {
+    match get_movie(conn, review.movie_id) {
+        Err(RepositoryError::NotFound(..)) => Err(RepositoryError::InvalidReference(
+            Entity::Movie,
+            review.movie_id,
+        )),
+        result => result.map(|_| ()),
+    }
+}
+
+// Check that a movie refers to an existing director
+fn check_director(conn: &Connection, movie: &Movie) -> Result<(), RepositoryError> {
+    match get_director(conn, movie.director_id) {
+        Err(RepositoryError::NotFound(..)) => Err(RepositoryError::InvalidReference(
+            Entity::Director,
+            movie.director_id,
+        )),
+        result => result.map(|_| ()),
+    }
+}
+
+// Store a new director, reporting a taken ID as AlreadyExists
+fn insert_director(conn: &Connection, director: Director) -> Result<Director, RepositoryError> {
+    let result = conn.execute(
+        "INSERT INTO directors (id, firstname, lastname) VALUES (?1, ?2, ?3)",
+        params![
+            director.id.to_string(),
+            director.firstname,
+            director.lastname
+        ],
+    );
+
+    match result {
+        Ok(_) => Ok(director),
+        Err(rusqlite::Error::SqliteFailure(err, _))
+            if err.code == ErrorCode::ConstraintViolation =>
+        {
+            Err(RepositoryError::AlreadyExists(
+                Entity::Director,
+                director.id,
+            ))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl MovieRepository for SqliteMovieRepository {
+    fn list(&self) -> Result<Vec<Movie>, RepositoryError> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare("SELECT * FROM movies")?;
+        let movies = stmt
+            .query_map([], movie_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(movies)
+    }
+
+    fn get(&self, id: Uuid) -> Result<Movie, RepositoryError> {
+        let conn = self.lock()?;
+        get_movie(&conn, id)
+    }
+
+    fn insert(&self, movie: Movie) -> Result<Movie, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        // Check the director first so a constraint violation can only mean
+        // that the ID is already taken
+        check_director(&tx, &movie)?;
+        let result = tx.execute(
+            "INSERT INTO movies (id, isbn, title, director_id, version, release_date,
+                 runtime_minutes, genres, original_language, country, certification, synopsis)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                movie.id.to_string(),
+                movie.isbn,
+                movie.title,
+                movie.director_id.to_string(),
+                movie.version,
+                movie.metadata.release_date,
+                movie.metadata.runtime_minutes,
+                serde_json::to_string(&movie.metadata.genres)?,
+                movie.metadata.original_language,
+                movie.metadata.country,
+                movie.metadata.certification,
+                movie.metadata.synopsis,
+            ],
+        );
+
+        match result {
+            Ok(_) => {
+                tx.commit()?;
+                Ok(movie)
+            }
+            // A primary key violation means the ID is already taken
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(RepositoryError::AlreadyExists(Entity::Movie, movie.id))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn update(
+        &self,
+        mut movie: Movie,
+        expected_version: Option<u64>,
+    ) -> Result<Movie, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        // Check the stored version and write the next one in a single transaction
+        let stored = get_movie(&tx, movie.id)?;
+        check_version(&stored, expected_version)?;
+        check_director(&tx, &movie)?;
+        movie.version = stored.version + 1;
+        tx.execute(
+            "UPDATE movies
+             SET isbn = ?2, title = ?3, director_id = ?4, version = ?5, release_date = ?6,
+                 runtime_minutes = ?7, genres = ?8, original_language = ?9, country = ?10,
+                 certification = ?11, synopsis = ?12
+             WHERE id = ?1",
+            params![
+                movie.id.to_string(),
+                movie.isbn,
+                movie.title,
+                movie.director_id.to_string(),
+                movie.version,
+                movie.metadata.release_date,
+                movie.metadata.runtime_minutes,
+                serde_json::to_string(&movie.metadata.genres)?,
+                movie.metadata.original_language,
+                movie.metadata.country,
+                movie.metadata.certification,
+                movie.metadata.synopsis,
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(movie)
+    }
+
+    fn delete(&self, id: Uuid, expected_version: Option<u64>) -> Result<(), RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        let stored = get_movie(&tx, id)?;
+        check_version(&stored, expected_version)?;
+        tx.execute("DELETE FROM movies WHERE id = ?1", params![id.to_string()])?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn check_integrity(&self) -> Result<(), RepositoryError> {
+        // Run every check under one lock and in one read transaction, so that
+        // no write can land between them
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        // Let SQLite verify its own file structure first
+        let result: String = tx.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        if result != "ok" {
+            return Err(RepositoryError::Storage(format!(
+                "database integrity check failed: {}",
+                result
+            )));
+        }
+
+        // Movies written before foreign keys were enforced may still point at
+        // a missing director
+        let violations: usize =
+            tx.query_row("SELECT count(*) FROM pragma_foreign_key_check", [], |row| {
+                row.get(0)
+            })?;
+        if violations > 0 {
+            return Err(RepositoryError::Storage(format!(
+                "database has {} rows referring to missing records",
+                violations
+            )));
+        }
+
+        // The id columns are primary keys, so it only remains to check that
+        // every stored id is a valid UUID, which loading the rows does
+        load_all(&tx, "movies", movie_from_row)?;
+        load_all(&tx, "directors", director_from_row)?;
+        load_all(&tx, "people", person_from_row)?;
+        load_all(&tx, "credits", credit_from_row)?;
+        load_all(&tx, "reviews", review_from_row)?;
+
+        // Nothing was written, so ending the transaction cannot lose anything
+        tx.rollback()?;
+        Ok(())
+    }
+
+    fn ping(&self) -> Result<(), RepositoryError> {
+        // Reading a page of the movies table finds out whether the database
+        // file is still there and readable
+        let conn = self.lock()?;
+        conn.query_row(
+            "SELECT count(*) FROM (SELECT 1 FROM movies LIMIT 1)",
+            [],
+            |_| Ok(()),
+        )?;
+        Ok(())
+    }
+
+    fn is_poisoned(&self) -> bool {
+        self.conn.is_poisoned()
+    }
+
+    fn flush(&self) -> Result<(), RepositoryError> {
+        // Every transaction is durable once committed, so only let SQLite
+        // update its query planner statistics, as it advises before closing
+        self.lock()?.execute_batch("PRAGMA optimize")?;
+        Ok(())
+    }
+}
+
+impl DirectorRepository for SqliteMovieRepository {
+    fn list_directors(&self) -> Result<Vec<Director>, RepositoryError> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare("SELECT * FROM directors")?;
+        let directors = stmt
+            .query_map([], director_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(directors)
+    }
+
+    fn get_director(&self, id: Uuid) -> Result<Director, RepositoryError> {
+        let conn = self.lock()?;
+        get_director(&conn, id)
+    }
+
+    fn insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let conn = self.lock()?;
+        insert_director(&conn, director)
+    }
+
+    fn update_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let conn = self.lock()?;
+        let updated = conn.execute(
+            "UPDATE directors SET firstname = ?2, lastname = ?3 WHERE id = ?1",
+            params![
+                director.id.to_string(),
+                director.firstname,
+                director.lastname
+            ],
+        )?;
+
+        match updated {
+            0 => Err(RepositoryError::NotFound(Entity::Director, director.id)),
+            _ => Ok(director),
+        }
+    }
+
+    fn delete_director(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        // Refuse to leave movies pointing at a director that no longer exists
+        get_director(&tx, id)?;
+        let referrers: usize = tx.query_row(
+            "SELECT count(*) FROM movies WHERE director_id = ?1",
+            params![id.to_string()],
+            |row| row.get(0),
+        )?;
+        if referrers > 0 {
+            return Err(RepositoryError::Referenced {
+                entity: Entity::Director,
+                id,
+                referrer: Entity::Movie,
+                referrers,
+            });
+        }
+        tx.execute(
+            "DELETE FROM directors WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn find_or_insert_director(&self, director: Director) -> Result<Director, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        let stored = tx
+            .query_row(
+                "SELECT * FROM directors WHERE firstname = ?1 AND lastname = ?2 LIMIT 1",
+                params![director.firstname, director.lastname],
+                director_from_row,
+            )
+            .optional()?;
+        let director = match stored {
+            Some(stored) => stored,
+            None => insert_director(&tx, director)?,
+        };
+        tx.commit()?;
+
+        Ok(director)
+    }
+}
+
+impl PersonRepository for SqliteMovieRepository {
+    fn list_people(&self) -> Result<Vec<Person>, RepositoryError> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare("SELECT * FROM people")?;
+        let people = stmt
+            .query_map([], person_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(people)
+    }
+
+    fn get_person(&self, id: Uuid) -> Result<Person, RepositoryError> {
+        let conn = self.lock()?;
+        get_person(&conn, id)
+    }
+
+    fn insert_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        let conn = self.lock()?;
+        let result = conn.execute(
+            "INSERT INTO people (id, firstname, lastname) VALUES (?1, ?2, ?3)",
+            params![person.id.to_string(), person.firstname, person.lastname],
+        );
+
+        match result {
+            Ok(_) => Ok(person),
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(RepositoryError::AlreadyExists(Entity::Person, person.id))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn update_person(&self, person: Person) -> Result<Person, RepositoryError> {
+        let conn = self.lock()?;
+        let updated = conn.execute(
+            "UPDATE people SET firstname = ?2, lastname = ?3 WHERE id = ?1",
+            params![person.id.to_string(), person.firstname, person.lastname],
+        )?;
+
+        match updated {
+            0 => Err(RepositoryError::NotFound(Entity::Person, person.id)),
+            _ => Ok(person),
+        }
+    }
+
+    fn delete_person(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        // Refuse to leave credits pointing at a person who no longer exists
+        get_person(&tx, id)?;
+        let referrers: usize = tx.query_row(
+            "SELECT count(*) FROM credits WHERE person_id = ?1",
+            params![id.to_string()],
+            |row| row.get(0),
+        )?;
+        if referrers > 0 {
+            return Err(RepositoryError::Referenced {
+                entity: Entity::Person,
+                id,
+                referrer: Entity::Credit,
+                referrers,
+            });
+        }
+        tx.execute("DELETE FROM people WHERE id = ?1", params![id.to_string()])?;
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+impl CreditRepository for SqliteMovieRepository {
+    fn movie_credits(&self, movie_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        let conn = self.lock()?;
+        credits_by(&conn, "movie_id", movie_id)
+    }
+
+    fn person_credits(&self, person_id: Uuid) -> Result<Vec<Credit>, RepositoryError> {
+        let conn = self.lock()?;
+        credits_by(&conn, "person_id", person_id)
+    }
+
+    fn get_credit(&self, id: Uuid) -> Result<Credit, RepositoryError> {
+        let conn = self.lock()?;
+        get_credit(&conn, id)
+    }
+
+    fn insert_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        // Check the references first so a constraint violation can only mean
+        // that the ID is already taken
+        check_credit(&tx, &credit)?;
+        let result = tx.execute(
+            "INSERT INTO credits (id, movie_id, person_id, role, character, billing)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                credit.id.to_string(),
+                credit.movie_id.to_string(),
+                credit.person_id.to_string(),
+                credit.role.name(),
+                credit.character,
+                credit.billing,
+            ],
+        );
+
+        match result {
+            Ok(_) => {
+                tx.commit()?;
+                Ok(credit)
+            }
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(RepositoryError::AlreadyExists(Entity::Credit, credit.id))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn update_credit(&self, credit: Credit) -> Result<Credit, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        get_credit(&tx, credit.id)?;
+        check_credit(&tx, &credit)?;
+        tx.execute(
+            "UPDATE credits
+             SET movie_id = ?2, person_id = ?3, role = ?4, character = ?5, billing = ?6
+             WHERE id = ?1",
+            params![
+                credit.id.to_string(),
+                credit.movie_id.to_string(),
+                credit.person_id.to_string(),
+                credit.role.name(),
+                credit.character,
+                credit.billing,
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(credit)
+    }
+
+    fn delete_credit(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let conn = self.lock()?;
+        let deleted = conn.execute("DELETE FROM credits WHERE id = ?1", params![id.to_string()])?;
+
+        match deleted {
+            0 => Err(RepositoryError::NotFound(Entity::Credit, id)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ReviewRepository for SqliteMovieRepository {
+    fn list_reviews(&self) -> Result<Vec<Review>, RepositoryError> {
+        let conn = self.lock()?;
+        load_all(&conn, "reviews", review_from_row)
+    }
+
+    fn movie_reviews(&self, movie_id: Uuid) -> Result<Vec<Review>, RepositoryError> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare("SELECT * FROM reviews WHERE movie_id = ?1")?;
+        let reviews = stmt
+            .query_map(params![movie_id.to_string()], review_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(reviews)
+    }
+
+    fn get_review(&self, id: Uuid) -> Result<Review, RepositoryError> {
+        let conn = self.lock()?;
+        get_review(&conn, id)
+    }
+
+    fn insert_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        // Check the movie first so a constraint violation can only mean that
+        // the ID is already taken
+        check_review(&tx, &review)?;
+        let result = tx.execute(
+            "INSERT INTO reviews
+             (id, movie_id, author, score, text, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                review.id.to_string(),
+                review.movie_id.to_string(),
+                review.author,
+                review.score,
+                review.text,
+                format_timestamp(review.created_at)?,
+                format_timestamp(review.updated_at)?,
+            ],
+        );
+
+        match result {
+            Ok(_) => {
+                tx.commit()?;
+                Ok(review)
+            }
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(RepositoryError::AlreadyExists(Entity::Review, review.id))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn update_review(&self, review: Review) -> Result<Review, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+
+        get_review(&tx, review.id)?;
+        check_review(&tx, &review)?;
+        tx.execute(
+            "UPDATE reviews
+             SET movie_id = ?2, author = ?3, score = ?4, text = ?5, created_at = ?6,
+                 updated_at = ?7
+             WHERE id = ?1",
+            params![
+                review.id.to_string(),
+                review.movie_id.to_string(),
+                review.author,
+                review.score,
+                review.text,
+                format_timestamp(review.created_at)?,
+                format_timestamp(review.updated_at)?,
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(review)
+    }
+
+    fn delete_review(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let conn = self.lock()?;
+        let deleted = conn.execute("DELETE FROM reviews WHERE id = ?1", params![id.to_string()])?;
+
+        match deleted {
+            0 => Err(RepositoryError::NotFound(Entity::Review, id)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{initial_version, MovieMetadata};
+
+    fn director() -> Director {
+        Director {
+            id: Uuid::new_v4(),
+            firstname: "Peter".to_string(),
+            lastname: "Jackson".to_string(),
+        }
+    }
+
+    fn movie(director_id: Uuid) -> Movie {
+        Movie {
+            id: Uuid::new_v4(),
+            isbn: "978-0-261-10235-4".to_string(),
+            title: "The Fellowship of the Ring".to_string(),
+            director_id,
+            metadata: MovieMetadata::default(),
+            version: initial_version(),
+        }
+    }
+
+    #[test]
+    fn consistent_database_passes() {
+        let repository = SqliteMovieRepository::open(":memory:").unwrap();
+        let director = repository.insert_director(director()).unwrap();
+        repository.insert(movie(director.id)).unwrap();
+        repository.check_integrity().unwrap();
+        // The check leaves no transaction open behind it
+        repository.insert(movie(director.id)).unwrap();
+    }
+
+    #[test]
+    fn dangling_director_is_found() {
+        let repository = SqliteMovieRepository::open(":memory:").unwrap();
+        let director = repository.insert_director(director()).unwrap();
+        repository.insert(movie(director.id)).unwrap();
+        {
+            // Remove the director the way a database written before foreign
+            // keys were enforced could have
+            let conn = repository.lock().unwrap();
+            conn.pragma_update(None, "foreign_keys", false).unwrap();
+            conn.execute(
+                "DELETE FROM directors WHERE id = ?1",
+                params![director.id.to_string()],
+            )
+            .unwrap();
+            conn.pragma_update(None, "foreign_keys", true).unwrap();
+        }
+        assert!(repository.check_integrity().is_err());
+    }
+
+    #[test]
+    fn directors_are_moved_into_their_own_table() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..2] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 2).unwrap();
+        let movies = [
+            ("The Fellowship of the Ring", "Peter", "Jackson", 3),
+            ("The Two Towers", "Peter", "Jackson", 1),
+            ("Master and Commander", "Peter", "Weir", 2),
+        ];
+        for (title, firstname, lastname, version) in movies {
+            conn.execute(
+                "INSERT INTO movies (id, isbn, title, director_firstname, director_lastname, version)
+                 VALUES (?1, '978-0-261-10235-4', ?2, ?3, ?4, ?5)",
+                params![Uuid::new_v4().to_string(), title, firstname, lastname, version],
+            )
+            .unwrap();
+        }
+
+        migrate(&mut conn).unwrap();
+        let repository = SqliteMovieRepository {
+            conn: Mutex::new(conn),
+        };
+
+        // Every distinct name becomes one director with a random UUID
+        let directors = repository.list_directors().unwrap();
+        assert_eq!(directors.len(), 2);
+        for director in &directors {
+            assert_eq!(director.id.get_version_num(), 4);
+            assert_eq!(director.id.get_variant(), uuid::Variant::RFC4122);
+        }
+
+        // Every movie keeps its version and refers to the director it named
+        let stored = repository.list().unwrap();
+        assert_eq!(stored.len(), movies.len());
+        for (title, firstname, lastname, version) in movies {
+            let movie = stored.iter().find(|movie| movie.title == title).unwrap();
+            let director = repository.get_director(movie.director_id).unwrap();
+            assert_eq!(
+                (director.firstname.as_str(), director.lastname.as_str()),
+                (firstname, lastname)
+            );
+            assert_eq!(movie.version, version);
+        }
+        repository.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn movies_round_trip() {
+        let repository = SqliteMovieRepository::open(":memory:").unwrap();
+        let director = repository.insert_director(director()).unwrap();
+        let mut new = movie(director.id);
+        new.metadata = MovieMetadata {
+            release_date: Some("2001-12-19".to_string()),
+            runtime_minutes: Some(178),
+            genres: vec!["adventure".to_string(), "fantasy".to_string()],
+            original_language: Some("en".to_string()),
+            country: Some("NZ".to_string()),
+            certification: Some("PG-13".to_string()),
+            synopsis: Some("A hobbit sets out to destroy a ring.".to_string()),
+        };
+
+        let inserted = repository.insert(new.clone()).unwrap();
+        assert_eq!(inserted, new);
+        assert_eq!(repository.get(new.id).unwrap(), new);
+        assert!(matches!(
+            repository.insert(new.clone()),
+            Err(RepositoryError::AlreadyExists(Entity::Movie, _))
+        ));
+
+        // Every update stores the next version
+        let mut changed = new.clone();
+        changed.title = "The Lord of the Rings: The Fellowship of the Ring".to_string();
+        changed.metadata.genres.clear();
+        let updated = repository.update(changed.clone(), Some(1)).unwrap();
+        assert_eq!(updated.version, 2);
+        assert_eq!(repository.get(new.id).unwrap(), updated);
+        assert_eq!(repository.update(changed, None).unwrap().version, 3);
+
+        repository.delete(new.id, Some(3)).unwrap();
+        assert!(matches!(
+            repository.get(new.id),
+            Err(RepositoryError::NotFound(Entity::Movie, _))
+        ));
+        assert!(matches!(
+            repository.delete(new.id, None),
+            Err(RepositoryError::NotFound(Entity::Movie, _))
+        ));
+    }
+
+    #[test]
+    fn movies_need_a_stored_director() {
+        let repository = SqliteMovieRepository::open(":memory:").unwrap();
+        let orphan = movie(Uuid::new_v4());
+        assert!(matches!(
+            repository.insert(orphan),
+            Err(RepositoryError::InvalidReference(Entity::Director, _))
+        ));
+    }
+
+    #[test]
+    fn stale_versions_are_refused() {
+        let repository = SqliteMovieRepository::open(":memory:").unwrap();
+        let director = repository.insert_director(director()).unwrap();
+        let stored = repository.insert(movie(director.id)).unwrap();
+        repository.update(stored.clone(), Some(1)).unwrap();
+
+        let mut stale = stored.clone();
+        stale.title = "Overwritten".to_string();
+        assert!(matches!(
+            repository.update(stale, Some(1)),
+            Err(RepositoryError::VersionMismatch {
+                expected: 1,
+                actual: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            repository.delete(stored.id, Some(1)),
+            Err(RepositoryError::VersionMismatch { .. })
+        ));
+
+        // Neither refused write changed the movie
+        let current = repository.get(stored.id).unwrap();
+        assert_eq!(current.title, stored.title);
+        assert_eq!(current.version, 2);
+    }
+
+    #[test]
+    fn reviews_round_trip() {
+        let repository = SqliteMovieRepository::open(":memory:").unwrap();
+        let director = repository.insert_director(director()).unwrap();
+        let movie = repository.insert(movie(director.id)).unwrap();
+        let posted = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+        let review = Review {
+            id: Uuid::new_v4(),
+            movie_id: movie.id,
+            author: "alice".to_string(),
+            score: 9,
+            text: "Long, but worth it".to_string(),
+            created_at: posted,
+            updated_at: posted,
+        };
+
+        repository.insert_review(review.clone()).unwrap();
+        assert_eq!(repository.get_review(review.id).unwrap(), review);
+        let edited = Review {
+            score: 8,
+            updated_at: posted + time::Duration::hours(1),
+            ..review.clone()
+        };
+        repository.update_review(edited.clone()).unwrap();
+        assert_eq!(repository.movie_reviews(movie.id).unwrap(), vec![edited]);
+
+        // Reviews go with their movie
+        repository.delete(movie.id, None).unwrap();
+        assert!(repository.get_review(review.id).is_err());
+    }
+
+    #[test]
+    fn credits_refer_to_stored_movies_and_people() {
+        let repository = SqliteMovieRepository::open(":memory:").unwrap();
+        let director = repository.insert_director(director()).unwrap();
+        let movie = repository.insert(movie(director.id)).unwrap();
+        let person = repository
+            .insert_person(Person {
+                id: Uuid::new_v4(),
+                firstname: "Howard".to_string(),
+                lastname: "Shore".to_string(),
+            })
+            .unwrap();
+        let credit = Credit {
+            id: Uuid::new_v4(),
+            movie_id: movie.id,
+            person_id: person.id,
+            role: Role::Composer,
+            character: None,
+            billing: 1,
+        };
+
+        for dangling in [
+            Credit {
+                person_id: Uuid::new_v4(),
+                ..credit.clone()
+            },
+            Credit {
+                movie_id: Uuid::new_v4(),
+                ..credit.clone()
+            },
+        ] {
+            assert!(matches!(
+                repository.insert_credit(dangling),
+                Err(RepositoryError::InvalidReference(..))
+            ));
+        }
+
+        repository.insert_credit(credit.clone()).unwrap();
+        assert_eq!(
+            repository.movie_credits(movie.id).unwrap(),
+            vec![credit.clone()]
+        );
+        assert_eq!(
+            repository.person_credits(person.id).unwrap(),
+            vec![credit.clone()]
+        );
+        let moved = Credit {
+            billing: 2,
+            ..credit.clone()
+        };
+        repository.update_credit(moved.clone()).unwrap();
+        assert_eq!(repository.get_credit(credit.id).unwrap(), moved);
+
+        // People stay while they are credited, and credits go with their movie
+        assert!(matches!(
+            repository.delete_person(person.id),
+            Err(RepositoryError::Referenced { referrers: 1, .. })
+        ));
+        repository.delete(movie.id, None).unwrap();
+        assert!(repository.person_credits(person.id).unwrap().is_empty());
+        repository.delete_person(person.id).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+use actix_web::rt::time::sleep;
+use rustls::server::{AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+
+// Define the errors that can occur while loading the certificates
+#[derive(Debug)]
+pub enum TlsError {
+    // A PEM file could not be read
+    Io(PathBuf, io::Error),
+    // A PEM file does not hold what it should
+    Invalid(PathBuf, String),
+    // The TLS settings do not fit together
+    Config(&'static str),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            TlsError::Invalid(path, msg) => write!(f, "invalid {}: {}", path.display(), msg),
+            TlsError::Config(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+// Read the certificates in a PEM file
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let file = File::open(path).map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+    if certs.is_empty() {
+        return Err(TlsError::Invalid(
+            path.to_path_buf(),
+            "no certificates found".to_string(),
+        ));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+// Read the first private key in a PEM file, in any of the usual encodings
+fn read_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let file = File::open(path).map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::Invalid(path.to_path_buf(), "no private key found".to_string()))
+}
+
+// Return when a file was last changed, if it can be told
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// Define the server certificate, which is read again from its files whenever
+// they change so that renewing it needs no restart
+pub struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCert {
+    fn open(cert_path: &Path, key_path: &Path) -> Result<Self, TlsError> {
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(Arc::new(Self::load(cert_path, key_path)?)),
+        })
+    }
+
+    // Read the certificate chain and its private key
+    fn load(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, TlsError> {
+        let certs = read_certs(cert_path)?;
+        let key = sign::any_supported_type(&read_key(key_path)?)
+            .map_err(|err| TlsError::Invalid(key_path.to_path_buf(), err.to_string()))?;
+        Ok(CertifiedKey::new(certs, key))
+    }
+
+    // Return when the certificate and key files were last changed
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        (modified(&self.cert_path), modified(&self.key_path))
+    }
+
+    // Check the files for changes every interval, switching to the new
+    // certificate once it loads. A certificate and key written one after the
+    // other may not match until the second is written, which the next check
+    // then picks up.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        let mut loaded = self.modified();
+        loop {
+            sleep(interval).await;
+            let modified = self.modified();
+            if modified == loaded {
+                continue;
+            }
+            loaded = modified;
+
+            match Self::load(&self.cert_path, &self.key_path) {
+                Ok(key) => {
+                    *self
+                        .current
+                        .write()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(key);
+                    tracing::info!(
+                        "Reloaded the TLS certificate from {}",
+                        self.cert_path.display()
+                    );
+                }
+                Err(err) => tracing::warn!(
+                    error = %err,
+                    "cannot reload the TLS certificate, keeping the current one"
+                ),
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone(),
+        )
+    }
+}
+
+// Define the TLS settings of the server
+pub struct Tls {
+    pub server_config: ServerConfig,
+    pub cert: Arc<ReloadingCert>,
+}
+
+impl Tls {
+    // Load the certificate, key and client CA named in the configuration,
+    // returning None if TLS is not configured
+    pub fn from_config(config: &Config) -> Result<Option<Self>, TlsError> {
+        let (cert_path, key_path) = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) if config.tls_client_ca.is_none() => return Ok(None),
+            (None, None) => {
+                return Err(TlsError::Config("tls_client_ca needs tls_cert and tls_key"))
+            }
+            _ => {
+                return Err(TlsError::Config(
+                    "tls_cert and tls_key have to be given together",
+                ))
+            }
+        };
+        let cert = Arc::new(ReloadingCert::open(cert_path, key_path)?);
+
+        // Clients have to present a certificate signed by one of the given
+        // CAs. Unlike the server certificate, these are only read at startup.
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &config.tls_client_ca {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for ca in read_certs(ca_path)? {
+                    roots
+                        .add(&ca)
+                        .map_err(|err| TlsError::Invalid(ca_path.clone(), err.to_string()))?;
+                }
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(Some(Self {
+            server_config: builder.with_cert_resolver(cert.clone()),
+            cert,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use uuid::Uuid;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/keys")
+            .join(name)
+    }
+
+    fn config(args: &[(&str, PathBuf)]) -> Config {
+        let mut argv = vec!["movies".to_string()];
+        for (flag, path) in args {
+            argv.push(flag.to_string());
+            argv.push(path.display().to_string());
+        }
+        Config::parse_from(argv)
+    }
+
+    fn served(cert: &ReloadingCert) -> Vec<Certificate> {
+        cert.current.read().unwrap().cert.clone()
+    }
+
+    #[test]
+    fn tls_is_off_without_certificate() {
+        assert!(Tls::from_config(&config(&[])).unwrap().is_none());
+    }
+
+    #[test]
+    fn tls_loads_a_certificate_with_its_key_and_client_ca() {
+        let tls = Tls::from_config(&config(&[
+            ("--tls-cert", fixture("tls-rsa.crt")),
+            ("--tls-key", fixture("tls-rsa.key")),
+            ("--tls-client-ca", fixture("tls-ec.crt")),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            served(&tls.cert),
+            read_certs(&fixture("tls-rsa.crt")).unwrap()
+        );
+    }
+
+    #[test]
+    fn tls_settings_that_do_not_fit_together_are_refused() {
+        let cert_only = config(&[("--tls-cert", fixture("tls-rsa.crt"))]);
+        assert!(matches!(
+            Tls::from_config(&cert_only),
+            Err(TlsError::Config(_))
+        ));
+        let key_only = config(&[("--tls-key", fixture("tls-rsa.key"))]);
+        assert!(matches!(
+            Tls::from_config(&key_only),
+            Err(TlsError::Config(_))
+        ));
+        let ca_only = config(&[("--tls-client-ca", fixture("tls-ec.crt"))]);
+        assert!(matches!(
+            Tls::from_config(&ca_only),
+            Err(TlsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn keys_are_read_in_any_of_the_usual_encodings() {
+        for name in ["tls-pkcs8.key", "tls-rsa.key", "tls-ec.key"] {
+            let key = read_key(&fixture(name)).unwrap();
+            assert!(sign::any_supported_type(&key).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn files_without_keys_or_certificates_are_refused() {
+        assert!(matches!(
+            read_key(&fixture("tls-rsa.crt")),
+            Err(TlsError::Invalid(..))
+        ));
+        assert!(matches!(
+            read_certs(&fixture("tls-rsa.key")),
+            Err(TlsError::Invalid(..))
+        ));
+        assert!(matches!(
+            read_certs(&fixture("missing.crt")),
+            Err(TlsError::Io(..))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn the_certificate_is_reloaded_only_once_the_new_pair_loads() {
+        let dir = std::env::temp_dir().join(format!("tls-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("server.crt"), dir.join("server.key"));
+        fs::copy(fixture("tls-rsa.crt"), &cert_path).unwrap();
+        fs::copy(fixture("tls-rsa.key"), &key_path).unwrap();
+
+        let cert = Arc::new(ReloadingCert::open(&cert_path, &key_path).unwrap());
+        let original = served(&cert);
+        let interval = Duration::from_millis(20);
+        actix_web::rt::spawn(cert.clone().watch(interval));
+        sleep(interval * 3).await;
+
+        // A broken certificate keeps the current one in place
+        fs::write(&cert_path, "not a certificate").unwrap();
+        sleep(interval * 5).await;
+        assert_eq!(served(&cert), original);
+
+        // A new pair that loads replaces it
+        fs::copy(fixture("tls-ec.crt"), &cert_path).unwrap();
+        fs::copy(fixture("tls-ec.key"), &key_path).unwrap();
+        sleep(interval * 5).await;
+        assert_eq!(served(&cert), read_certs(&fixture("tls-ec.crt")).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,597 @@
+use serde::Serialize;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::{
+    Director, Movie, MovieMetadata, MovieUpdate, NewCredit, NewDirector, NewMovie, NewPerson,
+    NewReview, Person, Role,
+};
+use crate::patch::PatchedMovie;
+
+// Longest title a movie may have, in characters
+const MAX_TITLE_LEN: usize = 200;
+// Longest first or last name a director or person may have, in characters
+const MAX_NAME_LEN: usize = 100;
+// Longest character name an actor may be credited with, in characters
+const MAX_CHARACTER_LEN: usize = 200;
+// Longest text a review may have, in characters
+const MAX_REVIEW_LEN: usize = 5000;
+// Lowest and highest score a review may give
+pub const MIN_SCORE: u8 = 1;
+pub const MAX_SCORE: u8 = 10;
+// Longest runtime a movie may have, in minutes
+const MAX_RUNTIME_MINUTES: u32 = 1440;
+// Longest age certification a movie may have, in characters
+const MAX_CERTIFICATION_LEN: usize = 16;
+// Longest synopsis a movie may have, in characters
+const MAX_SYNOPSIS_LEN: usize = 2000;
+
+// Define the controlled vocabulary of movie genres
+pub const GENRES: &[&str] = &[
+    "action",
+    "adventure",
+    "animation",
+    "biography",
+    "comedy",
+    "crime",
+    "documentary",
+    "drama",
+    "family",
+    "fantasy",
+    "history",
+    "horror",
+    "musical",
+    "mystery",
+    "romance",
+    "science_fiction",
+    "sport",
+    "thriller",
+    "war",
+    "western",
+];
+
+// Define a problem with a single field of a request
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    // Path of the field, such as `director.lastname`
+    pub field: String,
+    // Machine-readable reason, such as `empty` or `checksum`
+    pub code: &'static str,
+    // Human-readable description of the problem
+    pub message: String,
+}
+
+// Define the field-by-field report of a failed validation
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    // Record a problem with a field
+    fn add(&mut self, field: &str, code: &'static str, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            code,
+            message: message.into(),
+        });
+    }
+
+    // Turn the report into a result, failing if any problem was recorded
+    fn into_result(self) -> Result<(), ValidationErrors> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self),
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self
+            .errors
+            .iter()
+            .map(|err| format!("{}: {}", err.field, err.message))
+            .collect();
+        write!(f, "{}", errors.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+// Define types whose contents can be checked before they are stored
+pub trait Validate {
+    // Check every field, reporting all problems at once
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+impl Validate for Movie {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_movie(&mut errors, &self.title, &self.isbn, &self.metadata);
+        errors.into_result()
+    }
+}
+
+impl Validate for NewMovie {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_movie(&mut errors, &self.title, &self.isbn, &self.metadata);
+        check_director_choice(&mut errors, self.director_id, self.director.as_ref());
+        errors.into_result()
+    }
+}
+
+impl Validate for MovieUpdate {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_movie(&mut errors, &self.title, &self.isbn, &self.metadata);
+        check_director_choice(&mut errors, self.director_id, self.director.as_ref());
+        errors.into_result()
+    }
+}
+
+impl Validate for PatchedMovie {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let movie = &self.movie;
+        check_movie(&mut errors, &movie.title, &movie.isbn, &movie.metadata);
+        if let Some(director) = &self.director {
+            check_names(
+                &mut errors,
+                "director.",
+                &director.firstname,
+                &director.lastname,
+            );
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for Director {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_names(&mut errors, "", &self.firstname, &self.lastname);
+        errors.into_result()
+    }
+}
+
+impl Validate for NewDirector {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_names(&mut errors, "", &self.firstname, &self.lastname);
+        errors.into_result()
+    }
+}
+
+impl Validate for Person {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_names(&mut errors, "", &self.firstname, &self.lastname);
+        errors.into_result()
+    }
+}
+
+impl Validate for NewPerson {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_names(&mut errors, "", &self.firstname, &self.lastname);
+        errors.into_result()
+    }
+}
+
+impl Validate for NewCredit {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        // Only actors play a character, and every actor plays one
+        match (&self.character, self.role) {
+            (Some(character), Role::Actor) => {
+                check_text(&mut errors, "character", character, MAX_CHARACTER_LEN)
+            }
+            (None, Role::Actor) => errors.add("character", "missing", "must be given for actors"),
+            (Some(_), role) => errors.add(
+                "character",
+                "not_allowed",
+                format!("must not be given for the {} role", role.name()),
+            ),
+            (None, _) => {}
+        }
+        if self.billing == Some(0) {
+            errors.add("billing", "out_of_range", "must be at least 1");
+        }
+
+        errors.into_result()
+    }
+}
+
+impl Validate for NewReview {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        if !(MIN_SCORE..=MAX_SCORE).contains(&self.score) {
+            errors.add(
+                "score",
+                "out_of_range",
+                format!("must be between {} and {}", MIN_SCORE, MAX_SCORE),
+            );
+        }
+        check_text(&mut errors, "text", &self.text, MAX_REVIEW_LEN);
+
+        errors.into_result()
+    }
+}
+
+// Check the fields every movie representation has
+fn check_movie(errors: &mut ValidationErrors, title: &str, isbn: &str, metadata: &MovieMetadata) {
+    check_text(errors, "title", title, MAX_TITLE_LEN);
+    if let Err(message) = check_isbn(isbn) {
+        errors.add("isbn", "isbn", message);
+    }
+    check_metadata(errors, metadata);
+}
+
+// Check the descriptive fields of a movie that were given
+fn check_metadata(errors: &mut ValidationErrors, metadata: &MovieMetadata) {
+    if let Some(date) = &metadata.release_date {
+        if let Err(message) = check_date(date) {
+            errors.add("release_date", "date", message);
+        }
+    }
+    if let Some(runtime) = metadata.runtime_minutes {
+        if !(1..=MAX_RUNTIME_MINUTES).contains(&runtime) {
+            errors.add(
+                "runtime_minutes",
+                "out_of_range",
+                format!("must be between 1 and {}", MAX_RUNTIME_MINUTES),
+            );
+        }
+    }
+    for (index, genre) in metadata.genres.iter().enumerate() {
+        let field = format!("genres[{}]", index);
+        if !GENRES.contains(&genre.as_str()) {
+            errors.add(
+                &field,
+                "unknown_genre",
+                format!(
+                    "unknown genre '{}', expected one of: {}",
+                    genre,
+                    GENRES.join(", ")
+                ),
+            );
+        } else if metadata.genres[..index].contains(genre) {
+            errors.add(
+                &field,
+                "duplicate",
+                format!("genre '{}' is listed twice", genre),
+            );
+        }
+    }
+    if let Some(language) = &metadata.original_language {
+        if !is_code(language, 2, |c| c.is_ascii_lowercase()) {
+            errors.add(
+                "original_language",
+                "language",
+                "must be a two-letter lowercase ISO 639-1 code such as 'en'",
+            );
+        }
+    }
+    if let Some(country) = &metadata.country {
+        if !is_code(country, 2, |c| c.is_ascii_uppercase()) {
+            errors.add(
+                "country",
+                "country",
+                "must be a two-letter uppercase ISO 3166-1 code such as 'US'",
+            );
+        }
+    }
+    if let Some(certification) = &metadata.certification {
+        check_text(
+            errors,
+            "certification",
+            certification,
+            MAX_CERTIFICATION_LEN,
+        );
+    }
+    if let Some(synopsis) = &metadata.synopsis {
+        check_text(errors, "synopsis", synopsis, MAX_SYNOPSIS_LEN);
+    }
+}
+
+// Check that a code has the given length and consists of matching characters
+fn is_code(code: &str, len: usize, matches: impl Fn(char) -> bool) -> bool {
+    code.chars().count() == len && code.chars().all(matches)
+}
+
+// Check that a date is an existing calendar date written as `YYYY-MM-DD`
+fn check_date(date: &str) -> Result<(), String> {
+    let malformed = || format!("'{}' is not a date of the form YYYY-MM-DD", date);
+
+    let parts: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(malformed());
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return Err(malformed());
+    }
+    let number = |part: &str| {
+        part.chars()
+            .all(|c| c.is_ascii_digit())
+            .then(|| part.parse::<u32>().ok())
+            .flatten()
+            .ok_or_else(malformed)
+    };
+    let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return Err(format!("month {} does not exist", month)),
+    };
+    match (1..=days_in_month).contains(&day) {
+        true => Ok(()),
+        false => Err(format!(
+            "day {} does not exist in {}-{:02}",
+            day, year, month
+        )),
+    }
+}
+
+// Check that a movie request body names its director exactly one way, and the
+// inline director if that is the way chosen
+fn check_director_choice(
+    errors: &mut ValidationErrors,
+    director_id: Option<Uuid>,
+    director: Option<&NewDirector>,
+) {
+    match (director_id, director) {
+        (Some(_), Some(_)) => errors.add(
+            "director",
+            "ambiguous",
+            "must not be given together with director_id",
+        ),
+        (None, None) => errors.add(
+            "director_id",
+            "missing",
+            "either director_id or director must be given",
+        ),
+        (None, Some(director)) => {
+            check_names(errors, "director.", &director.firstname, &director.lastname)
+        }
+        (Some(_), None) => {}
+    }
+}
+
+// Check the names of a director, reporting them under the given field prefix
+fn check_names(errors: &mut ValidationErrors, prefix: &str, firstname: &str, lastname: &str) {
+    check_text(
+        errors,
+        &format!("{}firstname", prefix),
+        firstname,
+        MAX_NAME_LEN,
+    );
+    check_text(
+        errors,
+        &format!("{}lastname", prefix),
+        lastname,
+        MAX_NAME_LEN,
+    );
+}
+
+// Check that a text field is not blank and not longer than the given limit
+fn check_text(errors: &mut ValidationErrors, field: &str, value: &str, max_len: usize) {
+    let len = value.trim().chars().count();
+    if len == 0 {
+        errors.add(field, "empty", "must not be empty");
+    } else if len > max_len {
+        errors.add(
+            field,
+            "too_long",
+            format!("must be at most {} characters, got {}", max_len, len),
+        );
+    } else if len != value.chars().count() {
+        errors.add(field, "whitespace", "must not start or end with whitespace");
+    }
+}
+
+// Check that an ISBN-10 or ISBN-13 is well-formed and has a valid check digit.
+// Hyphens and spaces between the digits are ignored.
+pub fn check_isbn(isbn: &str) -> Result<(), String> {
+    let normalized: String = isbn.chars().filter(|c| *c != '-' && *c != ' ').collect();
+
+    match normalized.len() {
+        10 => check_isbn10(&normalized),
+        13 => check_isbn13(&normalized),
+        0 => Err("must not be empty".to_string()),
+        len => Err(format!(
+            "must have 10 or 13 digits without hyphens, got {}",
+            len
+        )),
+    }
+}
+
+// Check an ISBN-10, whose digits weighted 10 down to 1 sum to a multiple of 11.
+// The check digit may be `X`, standing for 10.
+fn check_isbn10(isbn: &str) -> Result<(), String> {
+    let mut sum = 0;
+    for (index, c) in isbn.chars().enumerate() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'X' | 'x' if index == 9 => 10,
+            _ => return Err(format!("invalid ISBN-10 character '{}'", c)),
+        };
+        sum += digit * (10 - index as u32);
+    }
+
+    match sum % 11 {
+        0 => Ok(()),
+        _ => Err("ISBN-10 check digit does not match".to_string()),
+    }
+}
+
+// Check an ISBN-13, whose digits weighted alternately 1 and 3 sum to a multiple
+// of 10
+fn check_isbn13(isbn: &str) -> Result<(), String> {
+    let mut sum = 0;
+    for (index, c) in isbn.chars().enumerate() {
+        let digit = c
+            .to_digit(10)
+            .ok_or_else(|| format!("invalid ISBN-13 character '{}'", c))?;
+        sum += match index % 2 {
+            0 => digit,
+            _ => digit * 3,
+        };
+    }
+
+    match sum % 10 {
+        0 => Ok(()),
+        _ => Err("ISBN-13 check digit does not match".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::initial_version;
+
+    #[test]
+    fn valid_isbns_pass() {
+        assert!(check_isbn("0-306-40615-2").is_ok());
+        assert!(check_isbn("0306406152").is_ok());
+        assert!(check_isbn("0-8044-2957-X").is_ok());
+        assert!(check_isbn("080442957x").is_ok());
+        assert!(check_isbn("978-0-306-40615-7").is_ok());
+        assert!(check_isbn("978 0 306 40615 7").is_ok());
+    }
+
+    #[test]
+    fn isbns_with_wrong_check_digits_fail() {
+        assert!(check_isbn("0-306-40615-3").is_err());
+        assert!(check_isbn("978-0-306-40615-8").is_err());
+    }
+
+    #[test]
+    fn malformed_isbns_fail() {
+        assert!(check_isbn("").is_err());
+        assert!(check_isbn("12345").is_err());
+        // X may only stand for the last digit of an ISBN-10
+        assert!(check_isbn("X306406152").is_err());
+        assert!(check_isbn("978030640615X").is_err());
+        assert!(check_isbn("97803064061５7").is_err());
+    }
+
+    #[test]
+    fn valid_dates_pass() {
+        assert!(check_date("2001-12-19").is_ok());
+        assert!(check_date("2000-02-29").is_ok());
+        assert!(check_date("2024-02-29").is_ok());
+        assert!(check_date("1999-12-31").is_ok());
+    }
+
+    #[test]
+    fn impossible_dates_fail() {
+        assert!(check_date("1900-02-29").is_err());
+        assert!(check_date("2023-02-29").is_err());
+        assert!(check_date("2001-04-31").is_err());
+        assert!(check_date("2001-13-01").is_err());
+        assert!(check_date("2001-00-10").is_err());
+        assert!(check_date("2001-01-00").is_err());
+    }
+
+    #[test]
+    fn malformed_dates_fail() {
+        assert!(check_date("").is_err());
+        assert!(check_date("2001-1-19").is_err());
+        assert!(check_date("01-12-19").is_err());
+        assert!(check_date("2001/12/19").is_err());
+        assert!(check_date("2001-12-19T00:00").is_err());
+        assert!(check_date("+001-12-19").is_err());
+        assert!(check_date("2001-12-1９").is_err());
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let movie = Movie {
+            id: Uuid::new_v4(),
+            isbn: "0-306-40615-3".to_string(),
+            title: " ".to_string(),
+            director_id: Uuid::new_v4(),
+            metadata: MovieMetadata {
+                release_date: Some("2001-02-30".to_string()),
+                runtime_minutes: Some(0),
+                ..MovieMetadata::default()
+            },
+            version: initial_version(),
+        };
+        let errors = movie.validate().unwrap_err();
+        let fields: Vec<&str> = errors.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec!["title", "isbn", "release_date", "runtime_minutes"]
+        );
+    }
+
+    fn with_metadata(metadata: MovieMetadata) -> Movie {
+        Movie {
+            id: Uuid::new_v4(),
+            isbn: "978-0-261-10235-4".to_string(),
+            title: "The Fellowship of the Ring".to_string(),
+            director_id: Uuid::new_v4(),
+            metadata,
+            version: initial_version(),
+        }
+    }
+
+    #[test]
+    fn complete_metadata_passes() {
+        let movie = with_metadata(MovieMetadata {
+            release_date: Some("2001-12-19".to_string()),
+            runtime_minutes: Some(178),
+            genres: vec!["adventure".to_string(), "fantasy".to_string()],
+            original_language: Some("en".to_string()),
+            country: Some("NZ".to_string()),
+            certification: Some("PG-13".to_string()),
+            synopsis: Some("A hobbit sets out to destroy a ring.".to_string()),
+        });
+        assert!(movie.validate().is_ok());
+        assert!(with_metadata(MovieMetadata::default()).validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_metadata_is_reported_by_field() {
+        let movie = with_metadata(MovieMetadata {
+            runtime_minutes: Some(MAX_RUNTIME_MINUTES + 1),
+            genres: vec![
+                "fantasy".to_string(),
+                "space_opera".to_string(),
+                "fantasy".to_string(),
+            ],
+            original_language: Some("EN".to_string()),
+            country: Some("nzl".to_string()),
+            certification: Some("x".repeat(MAX_CERTIFICATION_LEN + 1)),
+            synopsis: Some(" ".to_string()),
+            ..MovieMetadata::default()
+        });
+        let errors = movie.validate().unwrap_err();
+        let reported: Vec<(&str, &str)> = errors
+            .errors
+            .iter()
+            .map(|e| (e.field.as_str(), e.code))
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                ("runtime_minutes", "out_of_range"),
+                ("genres[1]", "unknown_genre"),
+                ("genres[2]", "duplicate"),
+                ("original_language", "language"),
+                ("country", "country"),
+                ("certification", "too_long"),
+                ("synopsis", "empty"),
+            ]
+        );
+    }
+}